@@ -30,7 +30,7 @@ fn spawn_static(b: &mut Bencher) {
     struct Bundle {
         pos: Position,
         vel: Velocity,
-    };
+    }
 
     let mut world = World::new();
     b.iter(|| {
@@ -46,7 +46,7 @@ fn spawn_batch(b: &mut Bencher) {
     struct Bundle {
         pos: Position,
         vel: Velocity,
-    };
+    }
 
     let mut world = World::new();
     b.iter(|| {
@@ -72,6 +72,19 @@ fn iterate_100k(b: &mut Bencher) {
     })
 }
 
+fn iterate_prepared_100k(b: &mut Bencher) {
+    let mut world = World::new();
+    for i in 0..100_000 {
+        world.spawn((Position(-(i as f32)), Velocity(i as f32)));
+    }
+    let mut query = PreparedQuery::<(&mut Position, &Velocity)>::new();
+    b.iter(|| {
+        for (_, (pos, vel)) in query.query_mut(&mut world) {
+            pos.0 += vel.0;
+        }
+    })
+}
+
 fn build(b: &mut Bencher) {
     let mut world = World::new();
     let mut builder = EntityBuilder::new();
@@ -87,6 +100,7 @@ benchmark_group!(
     spawn_static,
     spawn_batch,
     iterate_100k,
+    iterate_prepared_100k,
     build
 );
 benchmark_main!(benches);
@@ -121,18 +121,16 @@ fn system_fire_at_closest(world: &mut World) {
     }
 }
 
-fn system_remove_dead(world: &mut World) {
-    // Here we query entities with 0 or less hp and despawn them
-    let mut to_remove: Vec<Entity> = Vec::new();
+fn system_remove_dead(world: &mut World, cmd: &mut CommandBuffer) {
+    // Here we query entities with 0 or less hp and despawn them. The world can't be modified while
+    // it's being queried, so despawns are recorded and applied afterwards.
     for (id, hp) in &mut world.query::<&Health>() {
         if hp.0 <= 0 {
-            to_remove.push(id);
+            cmd.despawn(id);
         }
     }
 
-    for entity in to_remove {
-        world.despawn(entity).unwrap();
-    }
+    world.run_commands(cmd);
 }
 
 fn print_world_state(world: &mut World) {
@@ -145,6 +143,7 @@ fn print_world_state(world: &mut World) {
 
 fn main() {
     let mut world = World::new();
+    let mut cmd = CommandBuffer::new();
 
     batch_spawn_entities(&mut world, 5);
 
@@ -160,7 +159,7 @@ fn main() {
                 // Run all simulation systems:
                 system_integrate_motion(&mut world);
                 system_fire_at_closest(&mut world);
-                system_remove_dead(&mut world);
+                system_remove_dead(&mut world, &mut cmd);
             }
             "q" => break,
             "?" => {
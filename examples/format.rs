@@ -1,27 +1,20 @@
 //! One way to the contents of an entity, as you might do for debugging. A similar pattern could
 //! also be useful for serialization, or other row-oriented generic operations.
 
-fn format_entity(entity: hecs::EntityRef<'_>) -> String {
-    fn fmt<T: hecs::Component + std::fmt::Display>(entity: hecs::EntityRef<'_>) -> Option<String> {
-        Some(entity.get::<T>()?.to_string())
-    }
-
-    const FUNCTIONS: &[&dyn Fn(hecs::EntityRef<'_>) -> Option<String>] =
-        &[&fmt::<i32>, &fmt::<bool>, &fmt::<f64>];
-
+fn format_entity(registry: &hecs::FormatRegistry, entity: hecs::EntityRef<'_>) -> String {
     let mut out = String::new();
-    for f in FUNCTIONS {
-        if let Some(x) = f(entity) {
+    for ty in entity.component_types() {
+        if let Some(x) = registry.component(entity, ty.id()) {
             if out.is_empty() {
-                out.push_str("[");
+                out.push('[');
             } else {
                 out.push_str(", ");
             }
-            out.push_str(&x);
+            out.push_str(&x.to_string());
         }
     }
     if out.is_empty() {
-        out.push_str(&"[]");
+        out.push_str("[]");
     } else {
         out.push(']');
     }
@@ -29,7 +22,13 @@ fn format_entity(entity: hecs::EntityRef<'_>) -> String {
 }
 
 fn main() {
+    let registry = hecs::FormatRegistry::new()
+        .register_display::<i32>()
+        .register_display::<bool>()
+        .register_display::<f64>();
     let mut world = hecs::World::new();
     let e = world.spawn((42, true));
-    println!("{}", format_entity(world.entity(e).unwrap()));
+    let entity = world.entity(e).unwrap();
+    println!("{}", format_entity(&registry, entity));
+    println!("{:?}", registry.entity(entity));
 }
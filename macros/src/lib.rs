@@ -16,22 +16,19 @@ extern crate proc_macro;
 
 use proc_macro::TokenStream;
 use proc_macro2::Span;
-use quote::quote;
-use syn::{parse_macro_input, DeriveInput};
+use quote::{format_ident, quote};
+use syn::visit_mut::VisitMut;
+use syn::{parse_macro_input, parse_quote, DeriveInput, Lifetime};
 
-/// Implement `Bundle` for a monomorphic struct
+/// Implement `Bundle` for a struct
 ///
 /// Using derived `Bundle` impls improves spawn performance and can be convenient when combined with
-/// other derives like `serde::Deserialize`.
+/// other derives like `serde::Deserialize`. Generic structs are supported, with every field's type
+/// required to be a `Component`. Each instantiation's component IDs are computed once and cached.
 #[allow(clippy::cognitive_complexity)]
 #[proc_macro_derive(Bundle)]
 pub fn derive_bundle(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
-    if !input.generics.params.is_empty() {
-        return TokenStream::from(
-            quote! { compile_error!("derive(Bundle) does not support generics"); },
-        );
-    }
     let data = match input.data {
         syn::Data::Struct(s) => s,
         _ => {
@@ -42,65 +39,91 @@ pub fn derive_bundle(input: TokenStream) -> TokenStream {
     };
     let ident = input.ident;
     let (tys, fields) = struct_fields(&data.fields);
+    let mut generics = input.generics;
+    {
+        let where_clause = generics.make_where_clause();
+        for ty in &tys {
+            where_clause
+                .predicates
+                .push(parse_quote! { #ty: ::hecs::Component });
+        }
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
     let n = tys.len();
+    let compute_ids = quote! {{
+        use ::hecs::core::any::type_name;
+        use ::hecs::core::mem;
+        use ::hecs::ComponentId;
+
+        let mut tys: [(usize, ComponentId, &str); #n] = [#((mem::align_of::<#tys>(), ComponentId::of::<#tys>(), type_name::<#tys>())),*];
+        tys.sort_unstable_by(|x, y| x.0.cmp(&y.0).reverse().then(x.1.cmp(&y.1)));
+        // Sorting places fields of the same type next to each other
+        if let Some(x) = tys.windows(2).find(|x| x[0].1 == x[1].1) {
+            panic!("{} has multiple {} fields; each type must occur at most once!", stringify!(#ident), x[0].2);
+        }
+        let mut ids = [ComponentId::of::<()>(); #n];
+        for (id, info) in ids.iter_mut().zip(tys.iter()) {
+            *id = info.1;
+        }
+        ids
+    }};
+    let with_static_ids = if generics.params.is_empty() {
+        quote! {
+            ::hecs::lazy_static::lazy_static! {
+                static ref ELEMENTS: [::hecs::ComponentId; #n] = #compute_ids;
+            }
+
+            f(&*ELEMENTS)
+        }
+    } else {
+        // Statics can't depend on generic parameters, so every instantiation shares one cache
+        quote! {
+            ::hecs::lazy_static::lazy_static! {
+                static ref ELEMENTS: ::hecs::BundleIdCache = ::hecs::BundleIdCache::new();
+            }
+
+            f(ELEMENTS.get::<Self>(|| #compute_ids.to_vec()))
+        }
+    };
     let code = quote! {
-        impl ::hecs::DynamicBundle for #ident {
-            fn with_ids<T>(&self, f: impl FnOnce(&[std::any::TypeId]) -> T) -> T {
-                Self::with_static_ids(f)
+        impl #impl_generics ::hecs::DynamicBundle for #ident #ty_generics #where_clause {
+            fn with_ids<__HecsT>(&self, f: impl FnOnce(&[::hecs::ComponentId]) -> __HecsT) -> __HecsT {
+                <Self as ::hecs::Bundle>::with_static_ids(f)
             }
 
-            fn type_info(&self) -> Vec<::hecs::TypeInfo> {
-                Self::static_type_info()
+            fn type_info(&self) -> ::hecs::Vec<::hecs::TypeInfo> {
+                <Self as ::hecs::Bundle>::static_type_info()
             }
 
-            unsafe fn put(mut self, mut f: impl FnMut(*mut u8, std::any::TypeId, usize) -> bool) {
+            // Fields are forgotten once moved out, whether or not their type implements `Drop`
+            #[allow(clippy::forget_non_drop)]
+            unsafe fn put(mut self, mut f: impl FnMut(*mut u8, ::hecs::ComponentId, usize) -> bool) {
                 #(
-                    if f((&mut self.#fields as *mut #tys).cast::<u8>(), std::any::TypeId::of::<#tys>(), std::mem::size_of::<#tys>()) {
-                        std::mem::forget(self.#fields);
+                    if f((&mut self.#fields as *mut #tys).cast::<u8>(), ::hecs::ComponentId::of::<#tys>(), ::hecs::core::mem::size_of::<#tys>()) {
+                        ::hecs::core::mem::forget(self.#fields);
                     }
                 )*
             }
         }
 
-        impl ::hecs::Bundle for #ident {
-            fn with_static_ids<T>(f: impl FnOnce(&[std::any::TypeId]) -> T) -> T {
-                use std::any::TypeId;
-                use std::mem;
-
-                ::hecs::lazy_static::lazy_static! {
-                    static ref ELEMENTS: [TypeId; #n] = {
-                        let mut dedup = std::collections::HashSet::new();
-                        for &(ty, name) in [#((std::any::TypeId::of::<#tys>(), std::any::type_name::<#tys>())),*].iter() {
-                            if !dedup.insert(ty) {
-                                panic!("{} has multiple {} fields; each type must occur at most once!", stringify!(#ident), name);
-                            }
-                        }
-
-                        let mut tys = [#((mem::align_of::<#tys>(), TypeId::of::<#tys>())),*];
-                        tys.sort_unstable_by(|x, y| x.0.cmp(&y.0).reverse().then(x.1.cmp(&y.1)));
-                        let mut ids = [TypeId::of::<()>(); #n];
-                        for (id, info) in ids.iter_mut().zip(tys.iter()) {
-                            *id = info.1;
-                        }
-                        ids
-                    };
-                }
-
-                f(&*ELEMENTS)
+        impl #impl_generics ::hecs::Bundle for #ident #ty_generics #where_clause {
+            fn with_static_ids<__HecsT>(f: impl FnOnce(&[::hecs::ComponentId]) -> __HecsT) -> __HecsT {
+                #with_static_ids
             }
 
-            fn static_type_info() -> Vec<::hecs::TypeInfo> {
-                let mut info = vec![#(::hecs::TypeInfo::of::<#tys>()),*];
+            fn static_type_info() -> ::hecs::Vec<::hecs::TypeInfo> {
+                let mut info = ::hecs::Vec::with_capacity(#n);
+                #(info.push(::hecs::TypeInfo::of::<#tys>());)*
                 info.sort_unstable();
                 info
             }
 
             unsafe fn get(
-                mut f: impl FnMut(std::any::TypeId, usize) -> Option<std::ptr::NonNull<u8>>,
+                mut f: impl FnMut(::hecs::ComponentId, usize) -> Option<::hecs::core::ptr::NonNull<u8>>,
             ) -> Result<Self, ::hecs::MissingComponent> {
                 #(
-                    let #fields = f(std::any::TypeId::of::<#tys>(), std::mem::size_of::<#tys>())
+                    let #fields = f(::hecs::ComponentId::of::<#tys>(), ::hecs::core::mem::size_of::<#tys>())
                             .ok_or_else(::hecs::MissingComponent::new::<#tys>)?
                             .cast::<#tys>()
                         .as_ptr();
@@ -112,6 +135,200 @@ pub fn derive_bundle(input: TokenStream) -> TokenStream {
     TokenStream::from(code)
 }
 
+/// Implement `Query` for a struct whose fields are queries borrowing for the struct's lifetime
+///
+/// The struct must have exactly one lifetime parameter and no other generics. Each field's type
+/// may be any query, such as `&'a T`, `&'a mut T`, `Option<&'a T>` or `Entity`, and a field may be
+/// annotated with `#[with(T)]` or `#[without(T)]` to additionally require that entities have or
+/// lack a `T` component, as with `hecs::With` and `hecs::Without`.
+///
+/// # Example
+/// ```ignore
+/// #[derive(Query)]
+/// struct Kinematics<'a> {
+///     pos: &'a mut Position,
+///     #[without(Frozen)]
+///     vel: &'a Velocity,
+///     mass: Option<&'a Mass>,
+/// }
+///
+/// for (_, k) in world.query::<Kinematics>().iter() {
+///     k.pos.0 += k.vel.0;
+/// }
+/// ```
+#[proc_macro_derive(Query, attributes(with, without))]
+pub fn derive_query(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let lifetime = {
+        let mut params = input.generics.params.iter();
+        match (params.next(), params.next()) {
+            (Some(syn::GenericParam::Lifetime(x)), None) => x.lifetime.clone(),
+            _ => {
+                return TokenStream::from(quote! {
+                    compile_error!("derive(Query) requires exactly one lifetime parameter and no other generics");
+                })
+            }
+        }
+    };
+    let data = match input.data {
+        syn::Data::Struct(s) => s,
+        _ => {
+            return TokenStream::from(
+                quote! { compile_error!("derive(Query) only supports structs"); },
+            )
+        }
+    };
+    let ident = input.ident;
+    let vis = input.vis;
+    let fetch_ident = format_ident!("__HecsFetch{}", ident);
+
+    let mut members = Vec::new();
+    let mut fetches = Vec::new();
+    for (i, field) in data.fields.iter().enumerate() {
+        members.push(match field.ident {
+            Some(ref x) => syn::Member::Named(x.clone()),
+            None => syn::Member::Unnamed(i.into()),
+        });
+        // Fetch types can't depend on the lifetime of the items they produce
+        let mut ty = field.ty.clone();
+        ReplaceLifetime {
+            from: &lifetime,
+            to: Lifetime::new("'static", Span::call_site()),
+        }
+        .visit_type_mut(&mut ty);
+        let mut query = quote! { #ty };
+        for attr in &field.attrs {
+            let wrapper = if attr.path.is_ident("with") {
+                quote! { ::hecs::With }
+            } else if attr.path.is_ident("without") {
+                quote! { ::hecs::Without }
+            } else {
+                continue;
+            };
+            let component = match attr.parse_args::<syn::Type>() {
+                Ok(x) => x,
+                Err(e) => return TokenStream::from(e.to_compile_error()),
+            };
+            query = quote! { #wrapper<#component, #query> };
+        }
+        fetches.push(quote! { <#query as ::hecs::Query>::Fetch });
+    }
+    let indices = (0..fetches.len()).map(syn::Index::from).collect::<Vec<_>>();
+
+    let code = quote! {
+        impl<#lifetime> ::hecs::Query for #ident<#lifetime> {
+            type Fetch = #fetch_ident;
+        }
+
+        #[doc(hidden)]
+        #vis struct #fetch_ident(#(#fetches,)*);
+
+        impl ::hecs::Prepare for #fetch_ident {
+            type State = (#(<#fetches as ::hecs::Prepare>::State,)*);
+
+            #[allow(unused_variables)]
+            fn prepare(archetype: &::hecs::Archetype) -> Option<Self::State> {
+                Some((#(<#fetches as ::hecs::Prepare>::prepare(archetype)?,)*))
+            }
+        }
+
+        impl<'__hecs> ::hecs::Fetch<'__hecs> for #fetch_ident {
+            type Item = #ident<'__hecs>;
+
+            #[allow(unused_variables, unused_mut)]
+            fn access(archetype: &::hecs::Archetype) -> Option<::hecs::Access> {
+                let mut access = ::hecs::Access::Iterate;
+                #(
+                    access = access.max(<#fetches as ::hecs::Fetch<'__hecs>>::access(archetype)?);
+                )*
+                Some(access)
+            }
+
+            #[allow(unused_variables, unused_mut, unused_assignments)]
+            fn borrow(archetype: &::hecs::Archetype) -> Result<(), ::hecs::BorrowError> {
+                let mut result = Ok(());
+                let mut borrowed = 0;
+                #(
+                    if result.is_ok() {
+                        result = <#fetches as ::hecs::Fetch<'__hecs>>::borrow(archetype);
+                        borrowed += result.is_ok() as usize;
+                    }
+                )*
+                if result.is_err() {
+                    // Roll back the borrows acquired before the failure
+                    #(
+                        if #indices < borrowed {
+                            <#fetches as ::hecs::Fetch<'__hecs>>::release(archetype);
+                        }
+                    )*
+                }
+                result
+            }
+
+            #[allow(unused_variables)]
+            unsafe fn execute(
+                archetype: &'__hecs ::hecs::Archetype,
+                meta: &'__hecs [::hecs::EntityMeta],
+                state: Self::State,
+                offset: usize,
+                ticks: ::hecs::Ticks,
+            ) -> Self {
+                #fetch_ident(#(
+                    <#fetches as ::hecs::Fetch<'__hecs>>::execute(archetype, meta, state.#indices, offset, ticks),
+                )*)
+            }
+
+            #[allow(unused_variables)]
+            fn release(archetype: &::hecs::Archetype) {
+                #(<#fetches as ::hecs::Fetch<'__hecs>>::release(archetype);)*
+            }
+
+            #[allow(unused_variables, unused_mut)]
+            fn for_each_borrow(mut f: impl FnMut(::hecs::ComponentId, bool)) {
+                #(<#fetches as ::hecs::Fetch<'__hecs>>::for_each_borrow(&mut f);)*
+            }
+
+            #[allow(unused_variables)]
+            fn filters(state: Self::State) -> bool {
+                false #(|| <#fetches as ::hecs::Fetch<'__hecs>>::filters(state.#indices))*
+            }
+
+            unsafe fn should_skip(&self) -> bool {
+                false #(|| <#fetches as ::hecs::Fetch<'__hecs>>::should_skip(&self.#indices))*
+            }
+
+            unsafe fn present(&self) -> bool {
+                true #(&& <#fetches as ::hecs::Fetch<'__hecs>>::present(&self.#indices))*
+            }
+
+            unsafe fn next(&mut self) -> Self::Item {
+                #ident {
+                    #(#members: <#fetches as ::hecs::Fetch<'__hecs>>::next(&mut self.#indices),)*
+                }
+            }
+
+            unsafe fn skip(&mut self) {
+                #(<#fetches as ::hecs::Fetch<'__hecs>>::skip(&mut self.#indices);)*
+            }
+        }
+    };
+    TokenStream::from(code)
+}
+
+/// Replaces every occurrence of one lifetime with another
+struct ReplaceLifetime<'a> {
+    from: &'a Lifetime,
+    to: Lifetime,
+}
+
+impl VisitMut for ReplaceLifetime<'_> {
+    fn visit_lifetime_mut(&mut self, lifetime: &mut Lifetime) {
+        if lifetime == self.from {
+            *lifetime = self.to.clone();
+        }
+    }
+}
+
 fn struct_fields(fields: &syn::Fields) -> (Vec<&syn::Type>, Vec<syn::Ident>) {
     match fields {
         syn::Fields::Named(ref fields) => fields
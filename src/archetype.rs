@@ -18,27 +18,44 @@ use crate::alloc::{vec, vec::Vec};
 use core::any::{type_name, TypeId};
 use core::cell::UnsafeCell;
 use core::mem;
+use core::ops::Deref;
 use core::ptr::{self, NonNull};
+use core::slice;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use hashbrown::HashMap;
 
-use crate::borrow::Borrow;
+use crate::borrow::{Borrow, SharedBorrow};
 use crate::query::Fetch;
-use crate::{Access, Component, Query};
+use crate::sparse_set::SparseSet;
+use crate::{Access, BorrowError, CloneError, CloneRegistry, Component, Query};
 
 /// A collection of entities having the same component types
 ///
-/// Accessing `Archetype`s is only required for complex dynamic scheduling. To manipulate entities,
-/// go through the `World`.
+/// Inspecting `Archetype`s is only required for complex dynamic scheduling, or for diagnostics such
+/// as memory usage. To manipulate entities, go through the `World`.
+///
+/// Components of types stored sparsely, as chosen by `World::store_sparse`, don't affect which
+/// archetype an entity belongs to. They're kept in sparse sets alongside the archetype's columns,
+/// and aren't reflected by methods like `has`, `get` and `types`.
 pub struct Archetype {
     types: Vec<TypeInfo>,
-    state: HashMap<TypeId, TypeState>,
+    // Index of each type's column in `types` and `state`
+    index: HashMap<ComponentId, usize>,
+    state: Vec<TypeState>,
     len: u32,
     entities: Box<[u32]>,
     // UnsafeCell allows unique references into `data` to be constructed while shared references
     // containing the `Archetype` exist
     data: UnsafeCell<NonNull<u8>>,
     data_size: usize,
+    // Components of sparsely stored types belonging to some of the entities
+    sparse: Vec<SparseSet>,
+    sparse_index: HashMap<ComponentId, usize>,
+    // Archetypes reached by inserting or removing components of the types in each key, ordered
+    // like a bundle's IDs
+    insert_edges: HashMap<Vec<ComponentId>, u32>,
+    remove_edges: HashMap<Vec<ComponentId>, u32>,
 }
 
 impl Archetype {
@@ -47,13 +64,20 @@ impl Archetype {
             types.windows(2).all(|x| x[0] < x[1]),
             "type info unsorted or contains duplicates"
         );
+        let index = types.iter().enumerate().map(|(i, ty)| (ty.id, i)).collect();
+        let state = types.iter().map(|_| TypeState::new(0, 0, 0)).collect();
         Self {
             types,
-            state: HashMap::default(),
+            index,
+            state,
             entities: Box::new([]),
             len: 0,
             data: UnsafeCell::new(NonNull::dangling()),
             data_size: 0,
+            sparse: Vec::new(),
+            sparse_index: HashMap::new(),
+            insert_edges: HashMap::new(),
+            remove_edges: HashMap::new(),
         }
     }
 
@@ -69,62 +93,177 @@ impl Archetype {
                 }
             }
         }
+        for set in &mut self.sparse {
+            set.clear();
+        }
         self.len = 0;
     }
 
-    pub(crate) fn has<T: Component>(&self) -> bool {
-        self.has_dynamic(TypeId::of::<T>())
+    /// Whether this archetype contains `T` components
+    pub fn has<T: Component>(&self) -> bool {
+        self.has_dynamic(ComponentId::of::<T>())
     }
 
-    pub(crate) fn has_dynamic(&self, id: TypeId) -> bool {
-        self.state.contains_key(&id)
+    /// Whether this archetype contains `id` components
+    pub fn has_dynamic(&self, id: ComponentId) -> bool {
+        self.index.contains_key(&id)
     }
 
-    pub(crate) fn get<T: Component>(&self) -> Option<NonNull<T>> {
-        let state = self.state.get(&TypeId::of::<T>())?;
-        Some(unsafe {
-            NonNull::new_unchecked(
-                (*self.data.get()).as_ptr().add(state.offset).cast::<T>() as *mut T
-            )
+    /// Whether the entity at `index` has an `id` component, stored in a column or sparsely
+    pub(crate) fn has_component(&self, id: ComponentId, index: u32) -> bool {
+        match self.get_sparse(id) {
+            Some(x) => x.contains(self.entity_id(index)),
+            None => self.has_dynamic(id),
+        }
+    }
+
+    /// Whether entities in this archetype may have `id` components, stored in a column or sparsely
+    pub(crate) fn stores(&self, id: ComponentId) -> bool {
+        self.index.contains_key(&id) || self.sparse_index.contains_key(&id)
+    }
+
+    /// Borrow all of the archetype's `T` components, ordered like `ids`
+    ///
+    /// Panics if the components are already uniquely borrowed.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// world.spawn((1, true));
+    /// world.spawn((2, false));
+    /// let archetype = world.archetypes().find(|x| x.has::<bool>()).unwrap();
+    /// assert_eq!(archetype.len(), 2);
+    /// assert_eq!(archetype.get::<i32>().unwrap().iter().sum::<i32>(), 3);
+    /// assert!(archetype.get::<&str>().is_none());
+    /// ```
+    pub fn get<T: Component>(&self) -> Option<ArchetypeColumn<'_, T>> {
+        let ptr = self.get_ptr::<T>()?;
+        self.borrow::<T>();
+        Some(ArchetypeColumn {
+            archetype: self,
+            column: unsafe { slice::from_raw_parts(ptr.as_ptr(), self.len as usize) },
         })
     }
 
+    fn type_state(&self, id: ComponentId) -> Option<&TypeState> {
+        self.index.get(&id).map(|&i| &self.state[i])
+    }
+
+    pub(crate) fn get_ptr<T: Component>(&self) -> Option<NonNull<T>> {
+        Some(self.get_base(self.get_state::<T>()?))
+    }
+
+    /// Index of the column storing `T`, which remains valid for the archetype's lifetime
+    pub(crate) fn get_state<T: Component>(&self) -> Option<usize> {
+        self.get_state_dynamic(ComponentId::of::<T>())
+    }
+
+    /// Index of the column storing `id` components
+    pub(crate) fn get_state_dynamic(&self, id: ComponentId) -> Option<usize> {
+        self.index.get(&id).copied()
+    }
+
+    /// Pointer to the first `T` in the column at `state`, as returned by `get_state::<T>`
+    pub(crate) fn get_base<T: Component>(&self, state: usize) -> NonNull<T> {
+        debug_assert_eq!(self.types[state].id, ComponentId::of::<T>());
+        self.get_base_dynamic(state).cast::<T>()
+    }
+
+    /// Pointer to the first component in the column at `state`
+    pub(crate) fn get_base_dynamic(&self, state: usize) -> NonNull<u8> {
+        unsafe { NonNull::new_unchecked((*self.data.get()).as_ptr().add(self.state[state].offset)) }
+    }
+
+    /// Pointers to the first tick at which the components in the column at `state` were added and
+    /// mutably accessed, respectively
+    pub(crate) fn get_ticks_base(&self, state: usize) -> (NonNull<u64>, NonNull<u64>) {
+        let state = &self.state[state];
+        unsafe {
+            let base = (*self.data.get()).as_ptr();
+            (
+                NonNull::new_unchecked(base.add(state.added).cast::<u64>()),
+                NonNull::new_unchecked(base.add(state.mutated).cast::<u64>()),
+            )
+        }
+    }
+
     pub(crate) fn borrow<T: Component>(&self) {
-        if self
-            .state
-            .get(&TypeId::of::<T>())
-            .map_or(false, |x| !x.borrow.borrow())
-        {
-            panic!("{} already borrowed uniquely", type_name::<T>());
+        if let Err(e) = self.try_borrow::<T>() {
+            panic!("{}", e);
+        }
+    }
+
+    pub(crate) fn try_borrow<T: Component>(&self) -> Result<(), BorrowError> {
+        self.try_borrow_dynamic(ComponentId::of::<T>())
+    }
+
+    /// The dynamic borrow guarding `id` components and their type's name
+    fn borrow_state(&self, id: ComponentId) -> Option<(&Borrow, &'static str)> {
+        if let Some(&i) = self.index.get(&id) {
+            return Some((&self.state[i].borrow, self.types[i].type_name));
+        }
+        let set = &self.sparse[*self.sparse_index.get(&id)?];
+        Some((set.borrow_state(), set.ty().type_name))
+    }
+
+    pub(crate) fn try_borrow_dynamic(&self, id: ComponentId) -> Result<(), BorrowError> {
+        match self.borrow_state(id) {
+            Some((borrow, type_name)) if !borrow.borrow() => {
+                Err(BorrowError::new(type_name, Access::Write))
+            }
+            _ => Ok(()),
         }
     }
 
-    pub(crate) fn borrow_mut<T: Component>(&self) {
-        if self
-            .state
-            .get(&TypeId::of::<T>())
-            .map_or(false, |x| !x.borrow.borrow_mut())
-        {
-            panic!("{} already borrowed", type_name::<T>());
+    pub(crate) fn try_borrow_mut<T: Component>(&self) -> Result<(), BorrowError> {
+        self.try_borrow_mut_dynamic(ComponentId::of::<T>())
+    }
+
+    pub(crate) fn try_borrow_mut_dynamic(&self, id: ComponentId) -> Result<(), BorrowError> {
+        match self.borrow_state(id) {
+            Some((borrow, type_name)) if !borrow.borrow_mut() => Err(BorrowError::new(
+                type_name,
+                if borrow.is_unique() {
+                    Access::Write
+                } else {
+                    Access::Read
+                },
+            )),
+            _ => Ok(()),
         }
     }
 
     pub(crate) fn release<T: Component>(&self) {
-        if let Some(x) = self.state.get(&TypeId::of::<T>()) {
-            x.borrow.release();
+        self.release_dynamic(ComponentId::of::<T>());
+    }
+
+    pub(crate) fn release_dynamic(&self, id: ComponentId) {
+        if let Some((borrow, _)) = self.borrow_state(id) {
+            borrow.release();
         }
     }
 
     pub(crate) fn release_mut<T: Component>(&self) {
-        if let Some(x) = self.state.get(&TypeId::of::<T>()) {
-            x.borrow.release_mut();
+        self.release_mut_dynamic(ComponentId::of::<T>());
+    }
+
+    pub(crate) fn release_mut_dynamic(&self, id: ComponentId) {
+        if let Some((borrow, _)) = self.borrow_state(id) {
+            borrow.release_mut();
         }
     }
 
-    pub(crate) fn len(&self) -> u32 {
+    /// Number of entities in this archetype
+    pub fn len(&self) -> u32 {
         self.len
     }
 
+    /// Whether this archetype contains no entities
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
     pub(crate) fn entities(&self) -> NonNull<u32> {
         unsafe { NonNull::new_unchecked(self.entities.as_ptr() as *mut _) }
     }
@@ -133,26 +272,201 @@ impl Archetype {
         self.entities[index as usize]
     }
 
-    pub(crate) fn types(&self) -> &[TypeInfo] {
+    /// IDs of the entities in this archetype, as returned by `Entity::id`
+    pub fn ids(&self) -> &[u32] {
+        &self.entities[..self.len as usize]
+    }
+
+    /// The archetype's component types, ordered by descending alignment
+    pub fn types(&self) -> &[TypeInfo] {
         &self.types
     }
 
+    /// Number of bytes occupied by the archetype's `id` components, if it has any such column
+    pub fn column_size(&self, id: ComponentId) -> Option<usize> {
+        let ty = &self.types[*self.index.get(&id)?];
+        Some(ty.layout.size() * self.len as usize)
+    }
+
+    /// Pointer to the `ty` component of the entity at `index`, if it has one
+    ///
     /// `index` must be in-bounds
     pub(crate) unsafe fn get_dynamic(
         &self,
-        ty: TypeId,
+        ty: ComponentId,
         size: usize,
         index: u32,
     ) -> Option<NonNull<u8>> {
         debug_assert!(index < self.len);
+        let state = match self.type_state(ty) {
+            Some(x) => x,
+            None => {
+                let set = self.get_sparse(ty)?;
+                return Some(set.component(set.index(self.entity_id(index))?));
+            }
+        };
         Some(NonNull::new_unchecked(
             (*self.data.get())
                 .as_ptr()
-                .add(self.state.get(&ty)?.offset + size * index as usize)
+                .add(state.offset + size * index as usize)
                 .cast::<u8>(),
         ))
     }
 
+    /// Pointers to the `T` component of the entity at `index`, if it has one, and to the tick at
+    /// which it was last mutably accessed
+    ///
+    /// `index` must be in-bounds
+    pub(crate) unsafe fn get_at<T: Component>(
+        &self,
+        index: u32,
+    ) -> Option<(NonNull<T>, NonNull<u64>)> {
+        let id = ComponentId::of::<T>();
+        let ptr = self.get_dynamic(id, mem::size_of::<T>(), index)?;
+        let (_, mutated) = self.ticks_dynamic(id, index)?;
+        Some((ptr.cast::<T>(), NonNull::new_unchecked(mutated)))
+    }
+
+    /// Where `T` components are stored, if entities in this archetype may have any
+    pub(crate) fn get_storage<T: Component>(&self) -> Option<Storage> {
+        let id = ComponentId::of::<T>();
+        if let Some(&i) = self.index.get(&id) {
+            return Some(Storage::Column(i));
+        }
+        self.sparse_index.get(&id).map(|&i| Storage::Sparse(i))
+    }
+
+    /// The sparse set at `index`, as returned by `get_storage`
+    pub(crate) fn sparse_set(&self, index: usize) -> &SparseSet {
+        &self.sparse[index]
+    }
+
+    pub(crate) fn sparse_sets(&self) -> &[SparseSet] {
+        &self.sparse
+    }
+
+    /// Whether any entity in this archetype has a sparsely stored component
+    pub(crate) fn has_sparse_components(&self) -> bool {
+        self.sparse.iter().any(|x| !x.entities().is_empty())
+    }
+
+    pub(crate) fn get_sparse(&self, id: ComponentId) -> Option<&SparseSet> {
+        self.sparse_index.get(&id).map(|&i| &self.sparse[i])
+    }
+
+    fn get_sparse_mut(&mut self, id: ComponentId) -> Option<&mut SparseSet> {
+        let i = *self.sparse_index.get(&id)?;
+        Some(&mut self.sparse[i])
+    }
+
+    /// Store components of type `ty` in a sparse set, returning whether it was newly created
+    pub(crate) fn add_sparse(&mut self, ty: TypeInfo) -> bool {
+        debug_assert!(!self.index.contains_key(&ty.id));
+        if self.sparse_index.contains_key(&ty.id) {
+            return false;
+        }
+        self.sparse_index.insert(ty.id, self.sparse.len());
+        self.sparse.push(SparseSet::new(ty));
+        true
+    }
+
+    /// The types of the sparsely stored components of the entity at `index`
+    pub(crate) fn sparse_types(&self, index: u32) -> impl Iterator<Item = &TypeInfo> + '_ {
+        let id = self.entity_id(index);
+        self.sparse
+            .iter()
+            .filter(move |x| x.contains(id))
+            .map(|x| x.ty())
+    }
+
+    /// Remove the `ids` components of the entity at `index` from their sparse sets without
+    /// dropping them
+    pub(crate) unsafe fn forget_sparse(
+        &mut self,
+        ids: impl IntoIterator<Item = ComponentId>,
+        index: u32,
+    ) {
+        let id = self.entity_id(index);
+        for ty in ids {
+            if let Some(set) = self.get_sparse_mut(ty) {
+                set.forget(id);
+            }
+        }
+    }
+
+    /// Move the sparsely stored components of the entity with ID `id` into `target`, returning
+    /// whether any sparse sets had to be added to `target`
+    pub(crate) fn move_sparse(&mut self, id: u32, target: &mut Archetype) -> bool {
+        let mut added = false;
+        for set in &mut self.sparse {
+            if !set.contains(id) {
+                continue;
+            }
+            added |= target.add_sparse(*set.ty());
+            set.move_to(id, target.get_sparse_mut(set.ty().id).unwrap());
+        }
+        added
+    }
+
+    /// Move every sparsely stored component into `target`, returning whether any sparse sets had to
+    /// be added to `target`
+    pub(crate) fn move_all_sparse(&mut self, target: &mut Archetype) -> bool {
+        let mut added = false;
+        for set in &mut self.sparse {
+            if set.entities().is_empty() {
+                continue;
+            }
+            added |= target.add_sparse(*set.ty());
+            let target = target.get_sparse_mut(set.ty().id).unwrap();
+            while let Some(&id) = set.entities().last() {
+                set.move_to(id, target);
+            }
+        }
+        added
+    }
+
+    /// The archetype entities move to when components of the `ids` types are inserted, if known
+    pub(crate) fn insert_edge(&self, ids: &[ComponentId]) -> Option<u32> {
+        self.insert_edges.get(ids).copied()
+    }
+
+    pub(crate) fn set_insert_edge(&mut self, ids: &[ComponentId], target: u32) {
+        self.insert_edges.insert(ids.to_vec(), target);
+    }
+
+    /// The archetype entities move to when components of the `ids` types are removed, if known
+    pub(crate) fn remove_edge(&self, ids: &[ComponentId]) -> Option<u32> {
+        self.remove_edges.get(ids).copied()
+    }
+
+    pub(crate) fn set_remove_edge(&mut self, ids: &[ComponentId], target: u32) {
+        self.remove_edges.insert(ids.to_vec(), target);
+    }
+
+    /// Drop the `id` component of the entity at `index` in place, returning whether it had one
+    ///
+    /// The component must be overwritten or forgotten before it's next accessed.
+    pub(crate) unsafe fn drop_component(&self, id: ComponentId, index: u32) -> bool {
+        let (ty, ptr) = match self.index.get(&id) {
+            Some(&i) => {
+                let ty = &self.types[i];
+                (ty, self.get_dynamic(id, ty.layout().size(), index).unwrap())
+            }
+            None => {
+                let set = match self.get_sparse(id) {
+                    Some(x) => x,
+                    None => return false,
+                };
+                match set.index(self.entity_id(index)) {
+                    Some(i) => (set.ty(), set.component(i)),
+                    None => return false,
+                }
+            }
+        };
+        ty.drop(ptr.as_ptr());
+        true
+    }
+
     /// Every type must be written immediately after this call
     pub(crate) unsafe fn allocate(&mut self, id: u32) -> u32 {
         if self.len as usize == self.entities.len() {
@@ -164,13 +478,16 @@ impl Archetype {
         self.len - 1
     }
 
+    /// Ensure at least `additional` more entities fit without reallocating
     pub(crate) fn reserve(&mut self, additional: u32) {
         if additional > (self.capacity() - self.len()) {
-            self.grow(additional - (self.capacity() - self.len()));
+            // `grow` sizes relative to `len`, not the current capacity
+            self.grow(additional);
         }
     }
 
-    pub(crate) fn capacity(&self) -> u32 {
+    /// Number of entities this archetype can hold before it must allocate
+    pub fn capacity(&self) -> u32 {
         self.entities.len() as u32
     }
 
@@ -183,34 +500,48 @@ impl Archetype {
             self.entities = new_entities;
 
             let old_data_size = mem::replace(&mut self.data_size, 0);
-            let mut state = HashMap::with_capacity(self.types.len());
+            let mut state = Vec::with_capacity(self.types.len());
             for ty in &self.types {
                 self.data_size = align(self.data_size, ty.layout.align());
-                state.insert(ty.id, TypeState::new(self.data_size));
+                let offset = self.data_size;
                 self.data_size += ty.layout.size() * count;
+                self.data_size = align(self.data_size, mem::align_of::<u64>());
+                let added = self.data_size;
+                let mutated = added + mem::size_of::<u64>() * count;
+                self.data_size = mutated + mem::size_of::<u64>() * count;
+                state.push(TypeState::new(offset, added, mutated));
             }
             let new_data = if self.data_size == 0 {
                 NonNull::dangling()
             } else {
                 NonNull::new(alloc(
-                    Layout::from_size_align(
-                        self.data_size,
-                        self.types.first().map_or(1, |x| x.layout.align()),
-                    )
-                    .unwrap(),
+                    Layout::from_size_align(self.data_size, self.data_align()).unwrap(),
                 ))
                 .unwrap()
             };
             if old_data_size != 0 {
-                for ty in &self.types {
-                    let old_off = self.state.get(&ty.id).unwrap().offset;
-                    let new_off = state.get(&ty.id).unwrap().offset;
+                let old_data = (*self.data.get()).as_ptr();
+                for (ty, (old, new)) in self.types.iter().zip(self.state.iter().zip(&state)) {
                     ptr::copy_nonoverlapping(
-                        (*self.data.get()).as_ptr().add(old_off),
-                        new_data.as_ptr().add(new_off),
+                        old_data.add(old.offset),
+                        new_data.as_ptr().add(new.offset),
                         ty.layout.size() * old_count,
                     );
+                    ptr::copy_nonoverlapping(
+                        old_data.add(old.added).cast::<u64>(),
+                        new_data.as_ptr().add(new.added).cast::<u64>(),
+                        old_count,
+                    );
+                    ptr::copy_nonoverlapping(
+                        old_data.add(old.mutated).cast::<u64>(),
+                        new_data.as_ptr().add(new.mutated).cast::<u64>(),
+                        old_count,
+                    );
                 }
+                dealloc(
+                    old_data,
+                    Layout::from_size_align_unchecked(old_data_size, self.data_align()),
+                );
             }
 
             self.data = UnsafeCell::new(new_data);
@@ -218,9 +549,42 @@ impl Archetype {
         }
     }
 
+    /// Alignment of `data`, sufficient for every component and for change ticks
+    fn data_align(&self) -> usize {
+        self.types
+            .first()
+            .map_or(1, |x| x.layout.align())
+            .max(mem::align_of::<u64>())
+    }
+
+    /// Tick storage of `ty` at `index`
+    pub(crate) unsafe fn ticks_dynamic(
+        &self,
+        ty: ComponentId,
+        index: u32,
+    ) -> Option<(*mut u64, *mut u64)> {
+        let state = match self.type_state(ty) {
+            Some(x) => x,
+            None => {
+                let set = self.get_sparse(ty)?;
+                let (added, mutated) = set.ticks(set.index(self.entity_id(index))?);
+                return Some((added.as_ptr(), mutated.as_ptr()));
+            }
+        };
+        let base = (*self.data.get()).as_ptr();
+        Some((
+            base.add(state.added).cast::<u64>().add(index as usize),
+            base.add(state.mutated).cast::<u64>().add(index as usize),
+        ))
+    }
+
     /// Returns the ID of the entity moved into `index`, if any
     pub(crate) unsafe fn remove(&mut self, index: u32) -> Option<u32> {
         let last = self.len - 1;
+        let id = self.entity_id(index);
+        for set in &mut self.sparse {
+            set.remove(id);
+        }
         for ty in &self.types {
             let removed = self
                 .get_dynamic(ty.id, ty.layout.size(), index)
@@ -235,6 +599,7 @@ impl Archetype {
                     removed,
                     ty.layout.size(),
                 );
+                self.move_ticks(ty.id, last, index);
             }
         }
         self.len = last;
@@ -247,10 +612,13 @@ impl Archetype {
     }
 
     /// Returns the ID of the entity moved into `index`, if any
+    ///
+    /// `f` is passed each component's pointer, type, size, and added and mutated ticks. Sparsely
+    /// stored components must be moved beforehand with `move_sparse`.
     pub(crate) unsafe fn move_to(
         &mut self,
         index: u32,
-        mut f: impl FnMut(*mut u8, TypeId, usize),
+        mut f: impl FnMut(*mut u8, ComponentId, usize, u64, u64),
     ) -> Option<u32> {
         let last = self.len - 1;
         for ty in &self.types {
@@ -258,7 +626,8 @@ impl Archetype {
                 .get_dynamic(ty.id, ty.layout.size(), index)
                 .unwrap()
                 .as_ptr();
-            f(moved, ty.id(), ty.layout().size());
+            let (added, mutated) = self.ticks_dynamic(ty.id, index).unwrap();
+            f(moved, ty.id(), ty.layout().size(), *added, *mutated);
             if index != last {
                 ptr::copy_nonoverlapping(
                     self.get_dynamic(ty.id, ty.layout.size(), last)
@@ -267,6 +636,7 @@ impl Archetype {
                     moved,
                     ty.layout.size(),
                 );
+                self.move_ticks(ty.id, last, index);
             }
         }
         self.len -= 1;
@@ -278,19 +648,153 @@ impl Archetype {
         }
     }
 
+    /// Move every entity into `target`, returning the index of the first moved entity in `target`
+    ///
+    /// Each column is copied in one piece. Components of types `target` lacks are dropped, and
+    /// components of types `self` lacks must be written immediately after this call. Sparsely
+    /// stored components must be moved beforehand with `move_all_sparse`.
+    pub(crate) unsafe fn move_all(&mut self, target: &mut Archetype) -> u32 {
+        let count = self.len;
+        let start = target.len;
+        if count == 0 {
+            return start;
+        }
+        target.reserve(count);
+        target.entities[start as usize..(start + count) as usize].copy_from_slice(self.ids());
+        target.len += count;
+        for ty in &self.types {
+            let size = ty.layout.size();
+            let src = self.get_dynamic(ty.id, size, 0).unwrap().as_ptr();
+            if !target.has_dynamic(ty.id) {
+                for index in 0..count as usize {
+                    (ty.drop)(src.add(size * index));
+                }
+                continue;
+            }
+            let dst = target.get_dynamic(ty.id, size, start).unwrap().as_ptr();
+            ptr::copy_nonoverlapping(src, dst, size * count as usize);
+            let (src_added, src_mutated) = self.ticks_dynamic(ty.id, 0).unwrap();
+            let (dst_added, dst_mutated) = target.ticks_dynamic(ty.id, start).unwrap();
+            ptr::copy_nonoverlapping(src_added, dst_added, count as usize);
+            ptr::copy_nonoverlapping(src_mutated, dst_mutated, count as usize);
+        }
+        self.len = 0;
+        start
+    }
+
+    unsafe fn move_ticks(&self, ty: ComponentId, from: u32, to: u32) {
+        let (src_added, src_mutated) = self.ticks_dynamic(ty, from).unwrap();
+        let (dst_added, dst_mutated) = self.ticks_dynamic(ty, to).unwrap();
+        *dst_added = *src_added;
+        *dst_mutated = *src_mutated;
+    }
+
+    /// Move `component` into `index`, recording that it was added at tick `added` and last
+    /// mutably accessed at tick `mutated`
     pub(crate) unsafe fn put_dynamic(
         &mut self,
         component: *mut u8,
-        ty: TypeId,
+        ty: ComponentId,
         size: usize,
         index: u32,
+        added: u64,
+        mutated: u64,
     ) {
+        if !self.sparse.is_empty() {
+            if let Some(&i) = self.sparse_index.get(&ty) {
+                let id = self.entity_id(index);
+                self.sparse[i].insert(id, component, added, mutated);
+                return;
+            }
+        }
         let ptr = self
             .get_dynamic(ty, size, index)
             .unwrap()
             .as_ptr()
             .cast::<u8>();
         ptr::copy_nonoverlapping(component, ptr, size);
+        let (added_ptr, mutated_ptr) = self.ticks_dynamic(ty, index).unwrap();
+        *added_ptr = added;
+        *mutated_ptr = mutated;
+    }
+
+    /// The storage of every `ty` component, which must be `size` bytes each
+    pub(crate) unsafe fn column_bytes(&self, ty: ComponentId, size: usize) -> Option<&[u8]> {
+        let state = self.type_state(ty)?;
+        Some(slice::from_raw_parts(
+            (*self.data.get()).as_ptr().add(state.offset),
+            size * self.len as usize,
+        ))
+    }
+
+    /// Copy `count` consecutive `ty` components of `size` bytes each from `data` into the rows
+    /// starting at `index`, recording that they were added at tick `added`
+    pub(crate) unsafe fn put_column(
+        &mut self,
+        ty: ComponentId,
+        size: usize,
+        index: u32,
+        count: u32,
+        data: *const u8,
+        added: u64,
+    ) {
+        if count == 0 {
+            return;
+        }
+        let ptr = self.get_dynamic(ty, size, index).unwrap().as_ptr();
+        ptr::copy_nonoverlapping(data, ptr, size * count as usize);
+        let (added_ptr, mutated_ptr) = self.ticks_dynamic(ty, index).unwrap();
+        for i in 0..count as usize {
+            *added_ptr.add(i) = added;
+            *mutated_ptr.add(i) = 0;
+        }
+    }
+
+    /// Duplicate the archetype, cloning every component with the functions in `registry`
+    pub(crate) fn try_clone(&self, registry: &CloneRegistry) -> Result<Self, CloneError> {
+        let mut archetype = Self::new(self.types.clone());
+        archetype.sparse = self
+            .sparse
+            .iter()
+            .map(|x| x.try_clone(registry))
+            .collect::<Result<_, _>>()?;
+        archetype.sparse_index = self.sparse_index.clone();
+        archetype.insert_edges = self.insert_edges.clone();
+        archetype.remove_edges = self.remove_edges.clone();
+        if self.len == 0 {
+            return Ok(archetype);
+        }
+        let clones = self
+            .types
+            .iter()
+            .map(|ty| registry.get(ty.id).ok_or(CloneError::new(ty.type_name)))
+            .collect::<Result<Vec<_>, _>>()?;
+        archetype.grow(self.len);
+        unsafe {
+            let src = (*self.data.get()).as_ptr();
+            let dst = (*archetype.data.get()).as_ptr();
+            for (i, (ty, clone)) in self.types.iter().zip(clones).enumerate() {
+                let (old, new) = (&self.state[i], &archetype.state[i]);
+                let borrow = SharedBorrow::new(&old.borrow)
+                    .unwrap_or_else(|| panic!("{} already borrowed uniquely", ty.type_name));
+                clone(src.add(old.offset), dst.add(new.offset), self.len as usize);
+                drop(borrow);
+                ptr::copy_nonoverlapping(
+                    src.add(old.added).cast::<u64>(),
+                    dst.add(new.added).cast::<u64>(),
+                    self.len as usize,
+                );
+                ptr::copy_nonoverlapping(
+                    src.add(old.mutated).cast::<u64>(),
+                    dst.add(new.mutated).cast::<u64>(),
+                    self.len as usize,
+                );
+            }
+        }
+        // Set last, so that a panicking `clone` can only leak components
+        archetype.entities[..self.len as usize].copy_from_slice(self.ids());
+        archetype.len = self.len;
+        Ok(archetype)
     }
 
     /// How, if at all, `Q` will access entities in this archetype
@@ -306,36 +810,106 @@ impl Drop for Archetype {
             unsafe {
                 dealloc(
                     (*self.data.get()).as_ptr().cast(),
-                    Layout::from_size_align_unchecked(
-                        self.data_size,
-                        self.types.first().map_or(1, |x| x.layout.align()),
-                    ),
+                    Layout::from_size_align_unchecked(self.data_size, self.data_align()),
                 );
             }
         }
     }
 }
 
+/// Shared borrow of all of an `Archetype`'s components of one type
+pub struct ArchetypeColumn<'a, T: Component> {
+    archetype: &'a Archetype,
+    column: &'a [T],
+}
+
+unsafe impl<T: Component> Send for ArchetypeColumn<'_, T> {}
+unsafe impl<T: Component> Sync for ArchetypeColumn<'_, T> {}
+
+impl<T: Component> Deref for ArchetypeColumn<'_, T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        self.column
+    }
+}
+
+impl<T: Component> Clone for ArchetypeColumn<'_, T> {
+    fn clone(&self) -> Self {
+        self.archetype.borrow::<T>();
+        Self {
+            archetype: self.archetype,
+            column: self.column,
+        }
+    }
+}
+
+impl<T: Component> Drop for ArchetypeColumn<'_, T> {
+    fn drop(&mut self) {
+        self.archetype.release::<T>();
+    }
+}
+
+/// Where an archetype stores components of one type
+#[doc(hidden)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Storage {
+    /// The column at this index, holding a component for every entity
+    Column(usize),
+    /// The sparse set at this index, holding components for some entities
+    Sparse(usize),
+}
+
 struct TypeState {
     offset: usize,
     borrow: Borrow,
+    // Offsets of the ticks at which each component was added and last mutably accessed
+    added: usize,
+    mutated: usize,
 }
 
 impl TypeState {
-    fn new(offset: usize) -> Self {
+    fn new(offset: usize, added: usize, mutated: usize) -> Self {
         Self {
             offset,
             borrow: Borrow::new(),
+            added,
+            mutated,
         }
     }
 }
 
+/// Identifies a component type
+///
+/// Components of Rust types are identified by their `TypeId`. Component types defined at runtime
+/// with `TypeInfo::dynamic` are instead identified by a unique number.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum ComponentId {
+    /// The Rust type having this `TypeId`
+    Static(TypeId),
+    /// A component type defined at runtime
+    Dynamic(u64),
+}
+
+impl ComponentId {
+    /// Identifier of the Rust type `T`
+    pub fn of<T: 'static>() -> Self {
+        ComponentId::Static(TypeId::of::<T>())
+    }
+}
+
+impl From<TypeId> for ComponentId {
+    fn from(x: TypeId) -> Self {
+        ComponentId::Static(x)
+    }
+}
+
 /// Metadata required to store a component
 #[derive(Debug, Copy, Clone)]
 pub struct TypeInfo {
-    id: TypeId,
+    id: ComponentId,
     layout: Layout,
     drop: unsafe fn(*mut u8),
+    type_name: &'static str,
 }
 
 impl TypeInfo {
@@ -346,20 +920,68 @@ impl TypeInfo {
         }
 
         Self {
-            id: TypeId::of::<T>(),
+            id: ComponentId::of::<T>(),
             layout: Layout::new::<T>(),
             drop: drop_ptr::<T>,
+            type_name: type_name::<T>(),
         }
     }
 
-    pub(crate) fn id(&self) -> TypeId {
+    /// Metadata for a new component type that has no corresponding Rust type
+    ///
+    /// Every call defines a distinct component type with a new `ComponentId::Dynamic` id, so the
+    /// result should be stored and reused for every component of the type. Components are moved
+    /// by copying their `layout.size()` bytes, and if `drop` is supplied it's called with a
+    /// pointer to each component that's dropped by the world. Components of the new type can only
+    /// be accessed through raw APIs such as `World::insert_raw` and `World::get_raw`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// # use core::alloc::Layout;
+    /// let health = TypeInfo::dynamic("health", Layout::new::<f32>(), None);
+    /// let mut world = World::new();
+    /// let e = world.spawn((123,));
+    /// let mut value = 100.0f32;
+    /// unsafe {
+    ///     world.insert_raw(e, health, (&mut value as *mut f32).cast()).unwrap();
+    ///     assert_eq!(*world.get_raw(e, health).unwrap().cast::<f32>().as_ptr(), 100.0);
+    /// }
+    /// ```
+    pub fn dynamic(name: &'static str, layout: Layout, drop: Option<unsafe fn(*mut u8)>) -> Self {
+        unsafe fn drop_nothing(_: *mut u8) {}
+
+        // Pointer-sized to support targets without AtomicU64
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let id = NEXT_ID
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| x.checked_add(1))
+            .expect("too many dynamic component types");
+        Self {
+            id: ComponentId::Dynamic(id as u64),
+            layout,
+            drop: drop.unwrap_or(drop_nothing),
+            type_name: name,
+        }
+    }
+
+    /// Identifier of the component type
+    pub fn id(&self) -> ComponentId {
         self.id
     }
 
-    pub(crate) fn layout(&self) -> Layout {
+    /// Size and alignment of each component
+    pub fn layout(&self) -> Layout {
         self.layout
     }
 
+    /// Name of the component type, for diagnostics
+    ///
+    /// For Rust types, this is the name given by `core::any::type_name`, which isn't guaranteed to
+    /// be unique or stable.
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
     pub(crate) unsafe fn drop(&self, data: *mut u8) {
         (self.drop)(data)
     }
@@ -372,7 +994,7 @@ impl PartialOrd for TypeInfo {
 }
 
 impl Ord for TypeInfo {
-    /// Order by alignment, descending. Ties broken with TypeId.
+    /// Order by alignment, descending. Ties broken with ComponentId.
     fn cmp(&self, other: &Self) -> core::cmp::Ordering {
         self.layout
             .align()
@@ -390,7 +1012,7 @@ impl PartialEq for TypeInfo {
 
 impl Eq for TypeInfo {}
 
-fn align(x: usize, alignment: usize) -> usize {
+pub(crate) fn align(x: usize, alignment: usize) -> usize {
     debug_assert!(alignment.is_power_of_two());
     (x + alignment - 1) & (!alignment + 1)
 }
@@ -12,11 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use core::fmt;
 use core::ops::{Deref, DerefMut};
 use core::ptr::NonNull;
 
-use crate::archetype::Archetype;
-use crate::{Component, MissingComponent};
+#[cfg(feature = "std")]
+use std::error::Error;
+
+use crate::archetype::{Archetype, TypeInfo};
+use crate::{Access, Component, MissingComponent, TryGetError};
 
 #[cfg_attr(feature = "single_threaded", allow(dead_code))]
 mod atomic {
@@ -50,6 +54,10 @@ mod atomic {
                 .is_ok()
         }
 
+        pub fn is_unique(&self) -> bool {
+            self.0.load(Ordering::Relaxed) & UNIQUE_BIT != 0
+        }
+
         pub fn release(&self) {
             let value = self.0.fetch_sub(1, Ordering::Release);
             debug_assert!(value != 0, "unbalanced release");
@@ -98,6 +106,10 @@ mod single_threaded {
             }
         }
 
+        pub fn is_unique(&self) -> bool {
+            self.0.get() & UNIQUE_BIT != 0
+        }
+
         pub fn release(&self) {
             let value = self.0.get();
             debug_assert!(value != 0, "unbalanced release");
@@ -119,7 +131,65 @@ pub use atomic::Borrow;
 #[cfg(feature = "single_threaded")]
 pub use single_threaded::Borrow;
 
-const UNIQUE_BIT: usize = !(usize::max_value() >> 1);
+const UNIQUE_BIT: usize = !(usize::MAX >> 1);
+
+/// A shared borrow released when dropped, for holding across code that may panic
+pub(crate) struct SharedBorrow<'a>(&'a Borrow);
+
+impl<'a> SharedBorrow<'a> {
+    /// Acquire a shared borrow, or return `None` if `borrow` is uniquely borrowed
+    pub(crate) fn new(borrow: &'a Borrow) -> Option<Self> {
+        if borrow.borrow() {
+            Some(Self(borrow))
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for SharedBorrow<'_> {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+/// Error indicating that a component couldn't be borrowed due to a conflicting borrow
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct BorrowError {
+    type_name: &'static str,
+    conflict: Access,
+}
+
+impl BorrowError {
+    pub(crate) fn new(type_name: &'static str, conflict: Access) -> Self {
+        Self {
+            type_name,
+            conflict,
+        }
+    }
+
+    /// Name of the component type that couldn't be borrowed
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    /// The kind of pre-existing borrow that prevented access, either `Read` or `Write`
+    pub fn conflict(&self) -> Access {
+        self.conflict
+    }
+}
+
+impl fmt::Display for BorrowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.conflict {
+            Access::Write => write!(f, "{} already borrowed uniquely", self.type_name),
+            _ => write!(f, "{} already borrowed", self.type_name),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for BorrowError {}
 
 /// Shared borrow of an entity's component
 #[derive(Clone)]
@@ -133,14 +203,21 @@ impl<'a, T: Component> Ref<'a, T> {
         archetype: &'a Archetype,
         index: u32,
     ) -> Result<Self, MissingComponent> {
-        let target = NonNull::new_unchecked(
-            archetype
-                .get::<T>()
-                .ok_or_else(MissingComponent::new::<T>)?
-                .as_ptr()
-                .add(index as usize),
-        );
-        archetype.borrow::<T>();
+        match Self::try_new(archetype, index) {
+            Ok(x) => Ok(x),
+            Err(TryGetError::MissingComponent(e)) => Err(e),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub(crate) unsafe fn try_new(
+        archetype: &'a Archetype,
+        index: u32,
+    ) -> Result<Self, TryGetError> {
+        let (target, _) = archetype
+            .get_at::<T>(index)
+            .ok_or_else(MissingComponent::new::<T>)?;
+        archetype.try_borrow::<T>()?;
         Ok(Self { archetype, target })
     }
 }
@@ -162,25 +239,44 @@ impl<'a, T: Component> Deref for Ref<'a, T> {
 }
 
 /// Unique borrow of an entity's component
+///
+/// Mutably dereferencing a `RefMut` marks the component as mutated at the change tick it was
+/// obtained at.
 pub struct RefMut<'a, T: Component> {
     archetype: &'a Archetype,
     target: NonNull<T>,
+    mutated: NonNull<u64>,
+    tick: u64,
 }
 
 impl<'a, T: Component> RefMut<'a, T> {
     pub(crate) unsafe fn new(
         archetype: &'a Archetype,
         index: u32,
+        tick: u64,
     ) -> Result<Self, MissingComponent> {
-        let target = NonNull::new_unchecked(
-            archetype
-                .get::<T>()
-                .ok_or_else(MissingComponent::new::<T>)?
-                .as_ptr()
-                .add(index as usize),
-        );
-        archetype.borrow_mut::<T>();
-        Ok(Self { archetype, target })
+        match Self::try_new(archetype, index, tick) {
+            Ok(x) => Ok(x),
+            Err(TryGetError::MissingComponent(e)) => Err(e),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub(crate) unsafe fn try_new(
+        archetype: &'a Archetype,
+        index: u32,
+        tick: u64,
+    ) -> Result<Self, TryGetError> {
+        let (target, mutated) = archetype
+            .get_at::<T>(index)
+            .ok_or_else(MissingComponent::new::<T>)?;
+        archetype.try_borrow_mut::<T>()?;
+        Ok(Self {
+            archetype,
+            target,
+            mutated,
+            tick,
+        })
     }
 }
 
@@ -202,7 +298,10 @@ impl<'a, T: Component> Deref for RefMut<'a, T> {
 
 impl<'a, T: Component> DerefMut for RefMut<'a, T> {
     fn deref_mut(&mut self) -> &mut T {
-        unsafe { self.target.as_mut() }
+        unsafe {
+            *self.mutated.as_ptr() = self.tick;
+            self.target.as_mut()
+        }
     }
 }
 
@@ -211,6 +310,7 @@ impl<'a, T: Component> DerefMut for RefMut<'a, T> {
 pub struct EntityRef<'a> {
     archetype: Option<&'a Archetype>,
     index: u32,
+    tick: u64,
 }
 
 impl<'a> EntityRef<'a> {
@@ -219,13 +319,15 @@ impl<'a> EntityRef<'a> {
         Self {
             archetype: None,
             index: 0,
+            tick: 0,
         }
     }
 
-    pub(crate) unsafe fn new(archetype: &'a Archetype, index: u32) -> Self {
+    pub(crate) unsafe fn new(archetype: &'a Archetype, index: u32, tick: u64) -> Self {
         Self {
             archetype: Some(archetype),
             index,
+            tick,
         }
     }
 
@@ -241,7 +343,69 @@ impl<'a> EntityRef<'a> {
     ///
     /// Panics if the component is already borrowed from another entity with the same components.
     pub fn get_mut<T: Component>(&self) -> Option<RefMut<'a, T>> {
-        Some(unsafe { RefMut::new(self.archetype?, self.index).ok()? })
+        Some(unsafe { RefMut::new(self.archetype?, self.index, self.tick).ok()? })
+    }
+
+    /// Borrow the component of type `T`, if it exists, or fail if it's already uniquely borrowed
+    pub fn try_get<T: Component>(&self) -> Result<Option<Ref<'a, T>>, BorrowError> {
+        let archetype = match self.archetype {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        match unsafe { Ref::try_new(archetype, self.index) } {
+            Ok(x) => Ok(Some(x)),
+            Err(TryGetError::BorrowConflict(e)) => Err(e),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Uniquely borrow the component of type `T`, if it exists, or fail if it's already borrowed
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// let a = world.spawn((123,));
+    /// let entity = world.entity(a).unwrap();
+    /// let x = entity.get::<i32>().unwrap();
+    /// assert!(entity.try_get_mut::<i32>().is_err());
+    /// drop(x);
+    /// assert!(entity.try_get_mut::<i32>().unwrap().is_some());
+    /// assert!(entity.try_get_mut::<bool>().unwrap().is_none());
+    /// ```
+    pub fn try_get_mut<T: Component>(&self) -> Result<Option<RefMut<'a, T>>, BorrowError> {
+        let archetype = match self.archetype {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        match unsafe { RefMut::try_new(archetype, self.index, self.tick) } {
+            Ok(x) => Ok(Some(x)),
+            Err(TryGetError::BorrowConflict(e)) => Err(e),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// The types of the entity's components, in no particular order
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// let a = world.spawn((123, true));
+    /// let mut names = world
+    ///     .entity(a)
+    ///     .unwrap()
+    ///     .component_types()
+    ///     .map(|ty| ty.type_name())
+    ///     .collect::<Vec<_>>();
+    /// names.sort();
+    /// assert_eq!(names, &["bool", "i32"]);
+    /// ```
+    pub fn component_types(&self) -> impl Iterator<Item = &'a TypeInfo> + 'a {
+        let index = self.index;
+        self.archetype
+            .into_iter()
+            .flat_map(move |x| x.types().iter().chain(x.sparse_types(index)))
     }
 }
 
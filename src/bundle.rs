@@ -12,19 +12,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::alloc::{vec, vec::Vec};
+use crate::alloc::{boxed::Box, vec, vec::Vec};
 use core::any::{type_name, TypeId};
 use core::ptr::NonNull;
 use core::{fmt, mem};
 
-use crate::archetype::TypeInfo;
+use hashbrown::HashMap;
+
+use crate::archetype::{ComponentId, TypeInfo};
+use crate::lock::Lock;
 use crate::Component;
 
 /// A dynamically typed collection of components
 pub trait DynamicBundle {
     /// Invoke a callback on the fields' type IDs, sorted by descending alignment then id
     #[doc(hidden)]
-    fn with_ids<T>(&self, f: impl FnOnce(&[TypeId]) -> T) -> T;
+    fn with_ids<T>(&self, f: impl FnOnce(&[ComponentId]) -> T) -> T;
     /// Obtain the fields' TypeInfos, sorted by descending alignment then id
     #[doc(hidden)]
     fn type_info(&self) -> Vec<TypeInfo>;
@@ -33,13 +36,13 @@ pub trait DynamicBundle {
     /// Must invoke `f` only with a valid pointer, its type, and the pointee's size. A `false`
     /// return value indicates that the value was not moved and should be dropped.
     #[doc(hidden)]
-    unsafe fn put(self, f: impl FnMut(*mut u8, TypeId, usize) -> bool);
+    unsafe fn put(self, f: impl FnMut(*mut u8, ComponentId, usize) -> bool);
 }
 
 /// A statically typed collection of components
 pub trait Bundle: DynamicBundle {
     #[doc(hidden)]
-    fn with_static_ids<T>(f: impl FnOnce(&[TypeId]) -> T) -> T;
+    fn with_static_ids<T>(f: impl FnOnce(&[ComponentId]) -> T) -> T;
 
     /// Obtain the fields' TypeInfos, sorted by descending alignment then id
     #[doc(hidden)]
@@ -53,7 +56,7 @@ pub trait Bundle: DynamicBundle {
     /// pointers if any call to `f` returns `None`.
     #[doc(hidden)]
     unsafe fn get(
-        f: impl FnMut(TypeId, usize) -> Option<NonNull<u8>>,
+        f: impl FnMut(ComponentId, usize) -> Option<NonNull<u8>>,
     ) -> Result<Self, MissingComponent>
     where
         Self: Sized;
@@ -68,6 +71,10 @@ impl MissingComponent {
     pub fn new<T: Component>() -> Self {
         Self(type_name::<T>())
     }
+
+    pub(crate) fn from_type_info(ty: &TypeInfo) -> Self {
+        Self(ty.type_name())
+    }
 }
 
 impl fmt::Display for MissingComponent {
@@ -79,10 +86,45 @@ impl fmt::Display for MissingComponent {
 #[cfg(feature = "std")]
 impl std::error::Error for MissingComponent {}
 
+/// Sorted component IDs of each instantiation of a generic `derive(Bundle)` struct
+///
+/// Statics can't depend on generic parameters, so a derived impl keeps one of these in a static
+/// and looks up its IDs by the type of `Self`.
+#[cfg(feature = "macros")]
+#[doc(hidden)]
+pub struct BundleIdCache {
+    ids: Lock<HashMap<ComponentId, &'static [ComponentId]>>,
+}
+
+#[cfg(feature = "macros")]
+impl BundleIdCache {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self {
+            ids: Lock::new(HashMap::default()),
+        }
+    }
+
+    /// Get the IDs of `B`, calling `compute` to find them on first use
+    pub fn get<B: 'static>(
+        &self,
+        compute: impl FnOnce() -> Vec<ComponentId>,
+    ) -> &'static [ComponentId] {
+        let key = ComponentId::of::<B>();
+        if let Some(ids) = self.ids.lock(|ids| ids.get(&key).copied()) {
+            return ids;
+        }
+        // Computed without holding the lock, since `compute` may panic. Threads racing to compute
+        // the same IDs each leak a copy, but only the first is kept.
+        let ids: &'static [ComponentId] = Box::leak(compute().into_boxed_slice());
+        self.ids.lock(move |x| *x.entry(key).or_insert(ids))
+    }
+}
+
 macro_rules! tuple_impl {
     ($($name: ident),*) => {
         impl<$($name: Component),*> DynamicBundle for ($($name,)*) {
-            fn with_ids<T>(&self, f: impl FnOnce(&[TypeId]) -> T) -> T {
+            fn with_ids<T>(&self, f: impl FnOnce(&[ComponentId]) -> T) -> T {
                 Self::with_static_ids(f)
             }
 
@@ -91,13 +133,13 @@ macro_rules! tuple_impl {
             }
 
             #[allow(unused_variables, unused_mut)]
-            unsafe fn put(self, mut f: impl FnMut(*mut u8, TypeId, usize) -> bool) {
+            unsafe fn put(self, mut f: impl FnMut(*mut u8, ComponentId, usize) -> bool) {
                 #[allow(non_snake_case)]
                 let ($(mut $name,)*) = self;
                 $(
                     if f(
                         (&mut $name as *mut $name).cast::<u8>(),
-                        TypeId::of::<$name>(),
+                        ComponentId::of::<$name>(),
                         mem::size_of::<$name>()
                     ) {
                         mem::forget($name)
@@ -107,11 +149,11 @@ macro_rules! tuple_impl {
         }
 
         impl<$($name: Component),*> Bundle for ($($name,)*) {
-            fn with_static_ids<T>(f: impl FnOnce(&[TypeId]) -> T) -> T {
+            fn with_static_ids<T>(f: impl FnOnce(&[ComponentId]) -> T) -> T {
                 const N: usize = count!($($name),*);
-                let mut xs: [(usize, TypeId); N] = [$((mem::align_of::<$name>(), TypeId::of::<$name>())),*];
+                let mut xs: [(usize, ComponentId); N] = [$((mem::align_of::<$name>(), ComponentId::of::<$name>())),*];
                 xs.sort_unstable_by(|x, y| x.0.cmp(&y.0).reverse().then(x.1.cmp(&y.1)));
-                let mut ids = [TypeId::of::<()>(); N];
+                let mut ids = [ComponentId::of::<()>(); N];
                 for (slot, &(_, id)) in ids.iter_mut().zip(xs.iter()) {
                     *slot = id;
                 }
@@ -125,10 +167,10 @@ macro_rules! tuple_impl {
             }
 
             #[allow(unused_variables, unused_mut)]
-            unsafe fn get(mut f: impl FnMut(TypeId, usize) -> Option<NonNull<u8>>) -> Result<Self, MissingComponent> {
+            unsafe fn get(mut f: impl FnMut(ComponentId, usize) -> Option<NonNull<u8>>) -> Result<Self, MissingComponent> {
                 #[allow(non_snake_case)]
                 let ($(mut $name,)*) = ($(
-                    f(TypeId::of::<$name>(), mem::size_of::<$name>()).ok_or_else(MissingComponent::new::<$name>)?
+                    f(ComponentId::of::<$name>(), mem::size_of::<$name>()).ok_or_else(MissingComponent::new::<$name>)?
                         .as_ptr()
                         .cast::<$name>(),)*
                 );
@@ -138,6 +180,118 @@ macro_rules! tuple_impl {
     }
 }
 
+/// A bundle made of other bundles, holding all of their components
+///
+/// Tuples of components are limited to 15 elements, and a tuple of tuples is a bundle of
+/// tuple-typed components. Wrapping a tuple of up to 15 bundles in `Flatten` instead yields every
+/// component of every bundle, so entities of any size can be spawned without an `EntityBuilder`.
+/// Each bundle must be statically typed, and bundles may not share component types.
+///
+/// ```
+/// # use hecs::*;
+/// let mut world = World::new();
+/// let e = world.spawn(Flatten(((123, "abc"), (true, 4.0f32))));
+/// assert_eq!(*world.get::<&str>(e).unwrap(), "abc");
+/// assert_eq!(*world.get::<f32>(e).unwrap(), 4.0);
+/// let Flatten(((x, _), _)) = world.remove::<Flatten<((i32, &str), (bool,))>>(e).unwrap();
+/// assert_eq!(x, 123);
+/// ```
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Flatten<T>(pub T);
+
+/// Sorted TypeInfos and IDs of each instantiation of `Flatten`
+#[allow(clippy::type_complexity)]
+static FLATTENED: Lock<Option<HashMap<TypeId, (&'static [TypeInfo], &'static [ComponentId])>>> =
+    Lock::new(None);
+
+/// Get the sorted TypeInfos and IDs of the flattened bundle `B`, calling `compute` to collect its
+/// TypeInfos on first use
+fn flatten_types<B: 'static>(
+    compute: impl FnOnce() -> Vec<TypeInfo>,
+) -> (&'static [TypeInfo], &'static [ComponentId]) {
+    let key = TypeId::of::<B>();
+    if let Some(x) = FLATTENED.lock(|x| x.get_or_insert_with(HashMap::default).get(&key).copied()) {
+        return x;
+    }
+    // Computed without holding the lock, since this panics if the bundles share a component type
+    let mut xs = compute();
+    xs.sort_unstable();
+    assert!(
+        xs.windows(2).all(|x| x[0].id() != x[1].id()),
+        "flattened bundles share a component type"
+    );
+    let ids = xs.iter().map(|x| x.id()).collect::<Vec<_>>();
+    let entry = (
+        &*Box::leak(xs.into_boxed_slice()),
+        &*Box::leak(ids.into_boxed_slice()),
+    );
+    FLATTENED.lock(move |x| {
+        *x.get_or_insert_with(HashMap::default)
+            .entry(key)
+            .or_insert(entry)
+    })
+}
+
+macro_rules! flatten_impl {
+    ($($name: ident),*) => {
+        impl<$($name: Bundle + 'static),*> DynamicBundle for Flatten<($($name,)*)> {
+            fn with_ids<T>(&self, f: impl FnOnce(&[ComponentId]) -> T) -> T {
+                Self::with_static_ids(f)
+            }
+
+            fn type_info(&self) -> Vec<TypeInfo> {
+                Self::static_type_info()
+            }
+
+            #[allow(unused_variables, unused_mut)]
+            unsafe fn put(self, mut f: impl FnMut(*mut u8, ComponentId, usize) -> bool) {
+                #[allow(non_snake_case)]
+                let ($($name,)*) = self.0;
+                $($name.put(&mut f);)*
+            }
+        }
+
+        impl<$($name: Bundle + 'static),*> Flatten<($($name,)*)> {
+            fn flattened() -> (&'static [TypeInfo], &'static [ComponentId]) {
+                flatten_types::<Self>(|| {
+                    #[allow(unused_mut)]
+                    let mut xs = Vec::new();
+                    $(xs.extend($name::static_type_info());)*
+                    xs
+                })
+            }
+        }
+
+        impl<$($name: Bundle + 'static),*> Bundle for Flatten<($($name,)*)> {
+            fn with_static_ids<T>(f: impl FnOnce(&[ComponentId]) -> T) -> T {
+                f(Self::flattened().1)
+            }
+
+            fn static_type_info() -> Vec<TypeInfo> {
+                Self::flattened().0.to_vec()
+            }
+
+            #[allow(unused_variables, unused_mut)]
+            unsafe fn get(mut f: impl FnMut(ComponentId, usize) -> Option<NonNull<u8>>) -> Result<Self, MissingComponent> {
+                // Look up every component before reading any, so none are moved out on failure
+                let ptrs = Self::flattened()
+                    .0
+                    .iter()
+                    .map(|ty| {
+                        f(ty.id(), ty.layout().size())
+                            .map(|ptr| (ty.id(), ptr))
+                            .ok_or_else(|| MissingComponent::from_type_info(ty))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut lookup = |id: ComponentId, _: usize| ptrs.iter().find(|x| x.0 == id).map(|x| x.1);
+                Ok(Flatten(($($name::get(&mut lookup)?,)*)))
+            }
+        }
+    }
+}
+
+smaller_tuples_too!(flatten_impl, O, N, M, L, K, J, I, H, G, F, E, D, C, B, A);
+
 macro_rules! count {
     () => { 0 };
     ($x: ident $(, $rest: ident)*) => { 1 + count!($($rest),*) };
@@ -0,0 +1,96 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::fmt;
+
+#[cfg(feature = "std")]
+use std::error::Error;
+
+use hashbrown::HashMap;
+
+use crate::{Component, ComponentId};
+
+/// Clones `count` consecutive values from `src` into uninitialized memory at `dst`
+type CloneFn = unsafe fn(*const u8, *mut u8, usize);
+
+/// The component types that `World::try_clone` knows how to clone
+///
+/// Components maintained by the world count too: worlds using the hierarchy must register
+/// `Parent` and `Children`, and worlds using relations must register each `Relation<R>`.
+///
+/// # Example
+/// ```
+/// # use hecs::*;
+/// let registry = CloneRegistry::new().register::<i32>().register::<String>();
+/// let mut world = World::new();
+/// let a = world.spawn((123, "abc".to_string()));
+/// let clone = world.try_clone(&registry).unwrap();
+/// *world.get_mut::<i32>(a).unwrap() = 42;
+/// assert_eq!(*clone.get::<i32>(a).unwrap(), 123);
+/// assert_eq!(*clone.get::<String>(a).unwrap(), "abc");
+/// ```
+#[derive(Default)]
+pub struct CloneRegistry {
+    types: HashMap<ComponentId, CloneFn>,
+}
+
+impl CloneRegistry {
+    /// Create a registry with no component types
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Clone components of type `T` using its `Clone` impl
+    pub fn register<T: Component + Clone>(mut self) -> Self {
+        unsafe fn clone<T: Clone>(src: *const u8, dst: *mut u8, count: usize) {
+            let (src, dst) = (src.cast::<T>(), dst.cast::<T>());
+            for i in 0..count {
+                dst.add(i).write((*src.add(i)).clone());
+            }
+        }
+
+        self.types.insert(ComponentId::of::<T>(), clone::<T>);
+        self
+    }
+
+    pub(crate) fn get(&self, id: ComponentId) -> Option<CloneFn> {
+        self.types.get(&id).copied()
+    }
+}
+
+/// Error indicating that a world contains a component type that isn't in a `CloneRegistry`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CloneError {
+    type_name: &'static str,
+}
+
+impl CloneError {
+    pub(crate) fn new(type_name: &'static str) -> Self {
+        Self { type_name }
+    }
+
+    /// Name of the unregistered component type
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+}
+
+impl fmt::Display for CloneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is not registered for cloning", self.type_name)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for CloneError {}
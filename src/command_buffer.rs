@@ -0,0 +1,295 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::alloc::alloc::{alloc, dealloc, Layout};
+use crate::alloc::vec::Vec;
+use core::mem;
+use core::ops::Range;
+use core::ptr::{self, NonNull};
+
+use crate::archetype::{align, ComponentId, TypeInfo};
+use crate::{Bundle, Component, DynamicBundle, Entity, World};
+
+/// Records operations that require unique access to a `World` for later execution
+///
+/// Spawning, inserting, removing and despawning all take `&mut World`, so they can't be performed
+/// while a query is being iterated. Recording them in a `CommandBuffer` instead and then passing it
+/// to `World::run_commands` applies them in the order they were recorded.
+///
+/// Prefer reusing the same buffer over creating new ones repeatedly.
+///
+/// # Example
+/// ```
+/// # use hecs::*;
+/// let mut world = World::new();
+/// let a = world.spawn((123, true));
+/// let b = world.spawn((-1, false));
+/// let mut cmd = CommandBuffer::new();
+/// for (id, &x) in &mut world.query::<&i32>() {
+///     if x < 0 {
+///         cmd.despawn(id);
+///     } else {
+///         cmd.insert_one(id, "abc");
+///     }
+/// }
+/// world.run_commands(&mut cmd); // cmd can now be reused
+/// assert!(!world.contains(b));
+/// assert_eq!(*world.get::<&str>(a).unwrap(), "abc");
+/// ```
+pub struct CommandBuffer {
+    commands: Vec<Command>,
+    storage: NonNull<u8>,
+    layout: Layout,
+    cursor: usize,
+    components: Vec<(TypeInfo, usize)>,
+    ids: Vec<ComponentId>,
+}
+
+impl CommandBuffer {
+    /// Create an empty command buffer
+    pub fn new() -> Self {
+        Self {
+            commands: Vec::new(),
+            storage: NonNull::dangling(),
+            layout: Layout::from_size_align(0, 1).unwrap(),
+            cursor: 0,
+            components: Vec::new(),
+            ids: Vec::new(),
+        }
+    }
+
+    /// Reserve an entity in `world` and record a command to give it `components`
+    ///
+    /// The returned handle can be used immediately, e.g. in other recorded commands or stored in
+    /// components, but the entity will have no components until the buffer is run on `world`.
+    /// The buffer must not be run on any other world.
+    pub fn spawn(&mut self, world: &World, components: impl DynamicBundle) -> Entity {
+        let entity = world.reserve_entity();
+        self.insert(entity, components);
+        entity
+    }
+
+    /// Record a command to add `components` to `entity`
+    ///
+    /// See `World::insert`.
+    pub fn insert(&mut self, entity: Entity, components: impl DynamicBundle) {
+        let start = self.components.len();
+        let info = components.type_info();
+        unsafe {
+            components.put(|ptr, ty, _| {
+                let ty = *info.iter().find(|x| x.id() == ty).unwrap();
+                self.push(ptr, ty);
+                true
+            });
+        }
+        self.components[start..].sort_unstable_by_key(|x| x.0);
+        for (id, &(ty, _)) in self.ids[start..].iter_mut().zip(&self.components[start..]) {
+            *id = ty.id();
+        }
+        self.commands.push(Command::Insert {
+            entity,
+            components: start..self.components.len(),
+        });
+    }
+
+    /// Record a command to add `component` to `entity`
+    ///
+    /// See `insert`.
+    pub fn insert_one(&mut self, entity: Entity, component: impl Component) {
+        self.insert(entity, (component,));
+    }
+
+    /// Record a command to remove and drop the components `T` from `entity`
+    ///
+    /// See `World::remove`.
+    pub fn remove<T: Bundle>(&mut self, entity: Entity) {
+        fn remove<T: Bundle>(world: &mut World, entity: Entity) {
+            let _ = world.remove::<T>(entity);
+        }
+        self.commands.push(Command::Remove {
+            entity,
+            remove: remove::<T>,
+        });
+    }
+
+    /// Record a command to remove and drop the `T` component from `entity`
+    ///
+    /// See `remove`.
+    pub fn remove_one<T: Component>(&mut self, entity: Entity) {
+        self.remove::<(T,)>(entity);
+    }
+
+    /// Record a command to destroy `entity` and all its components
+    pub fn despawn(&mut self, entity: Entity) {
+        self.commands.push(Command::Despawn(entity));
+    }
+
+    /// Whether no commands have been recorded
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Drop all recorded commands without running them
+    ///
+    /// The buffer is cleared implicitly when it's run, so this doesn't usually need to be called.
+    pub fn clear(&mut self) {
+        for cmd in self.commands.drain(..) {
+            if let Command::Insert { components, .. } = cmd {
+                for &(ty, offset) in &self.components[components] {
+                    unsafe {
+                        ty.drop(self.storage.as_ptr().add(offset));
+                    }
+                }
+            }
+        }
+        self.components.clear();
+        self.ids.clear();
+        self.cursor = 0;
+    }
+
+    /// Apply all recorded commands to `world` in order, leaving the buffer empty
+    ///
+    /// Commands whose entity no longer exists, and removals of components an entity doesn't have,
+    /// are skipped.
+    pub(crate) fn run_on(&mut self, world: &mut World) {
+        // Taken so that a panic partway through can't lead to components being dropped twice
+        let mut commands = mem::take(&mut self.commands);
+        for cmd in commands.drain(..) {
+            match cmd {
+                Command::Insert { entity, components } => {
+                    let _ = world.insert(
+                        entity,
+                        RecordedEntity {
+                            storage: self.storage,
+                            components: &self.components[components.clone()],
+                            ids: &self.ids[components],
+                        },
+                    );
+                }
+                Command::Remove { entity, remove } => remove(world, entity),
+                Command::Despawn(entity) => {
+                    let _ = world.despawn(entity);
+                }
+            }
+        }
+        self.commands = commands;
+        self.components.clear();
+        self.ids.clear();
+        self.cursor = 0;
+    }
+
+    /// Move the value of type `ty` at `ptr` into storage
+    unsafe fn push(&mut self, ptr: *mut u8, ty: TypeInfo) {
+        let offset = align(self.cursor, ty.layout().align());
+        let end = offset + ty.layout().size();
+        if end > self.layout.size() || ty.layout().align() > self.layout.align() {
+            self.grow(end, ty.layout().align());
+        }
+        ptr::copy_nonoverlapping(ptr, self.storage.as_ptr().add(offset), ty.layout().size());
+        self.components.push((ty, offset));
+        self.ids.push(ty.id());
+        self.cursor = end;
+    }
+
+    fn grow(&mut self, min_size: usize, min_align: usize) {
+        let layout = Layout::from_size_align(
+            min_size.next_power_of_two().max(64),
+            self.layout.align().max(min_align),
+        )
+        .unwrap();
+        unsafe {
+            let storage = NonNull::new(alloc(layout)).unwrap();
+            if self.layout.size() != 0 {
+                ptr::copy_nonoverlapping(self.storage.as_ptr(), storage.as_ptr(), self.cursor);
+                dealloc(self.storage.as_ptr(), self.layout);
+            }
+            self.storage = storage;
+        }
+        self.layout = layout;
+    }
+}
+
+// Only components are stored, which needn't be `Send + Sync` under `single_threaded`
+#[cfg(not(feature = "single_threaded"))]
+unsafe impl Send for CommandBuffer {}
+#[cfg(not(feature = "single_threaded"))]
+unsafe impl Sync for CommandBuffer {}
+
+impl Drop for CommandBuffer {
+    fn drop(&mut self) {
+        // Ensure buffered components aren't leaked
+        self.clear();
+        if self.layout.size() != 0 {
+            unsafe {
+                dealloc(self.storage.as_ptr(), self.layout);
+            }
+        }
+    }
+}
+
+impl Default for CommandBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+enum Command {
+    Insert {
+        entity: Entity,
+        components: Range<usize>,
+    },
+    Remove {
+        entity: Entity,
+        remove: fn(&mut World, Entity),
+    },
+    Despawn(Entity),
+}
+
+/// The components of a recorded insert, moved out of a `CommandBuffer`'s storage by `put`
+struct RecordedEntity<'a> {
+    storage: NonNull<u8>,
+    components: &'a [(TypeInfo, usize)],
+    ids: &'a [ComponentId],
+}
+
+impl DynamicBundle for RecordedEntity<'_> {
+    fn with_ids<T>(&self, f: impl FnOnce(&[ComponentId]) -> T) -> T {
+        f(self.ids)
+    }
+
+    fn type_info(&self) -> Vec<TypeInfo> {
+        self.components.iter().map(|x| x.0).collect()
+    }
+
+    unsafe fn put(self, mut f: impl FnMut(*mut u8, ComponentId, usize) -> bool) {
+        for &(ty, offset) in self.components {
+            let ptr = self.storage.as_ptr().add(offset);
+            if !f(ptr, ty.id(), ty.layout().size()) {
+                ty.drop(ptr);
+            }
+        }
+        mem::forget(self);
+    }
+}
+
+impl Drop for RecordedEntity<'_> {
+    fn drop(&mut self) {
+        // Reached only if `put` was never called, e.g. because the entity was despawned
+        for &(ty, offset) in self.components {
+            unsafe {
+                ty.drop(self.storage.as_ptr().add(offset));
+            }
+        }
+    }
+}
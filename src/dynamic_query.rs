@@ -0,0 +1,429 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::alloc::vec::Vec;
+use core::ptr::{self, NonNull};
+use core::slice;
+
+use crate::archetype::{Archetype, ComponentId};
+use crate::entities::EntityMeta;
+use crate::sparse_set::SparseSet;
+use crate::{BorrowError, Entity, World};
+
+/// A query whose component types are only known at runtime
+///
+/// Each term added by `read`, `write`, `read_optional` or `write_optional` accesses the components
+/// of one type. Entities that lack the component of a non-optional term, or that have a component
+/// passed to `without`, are skipped. Components are borrowed dynamically when the query is
+/// iterated, just like `World::query`, and accessed through raw pointers.
+///
+/// # Example
+/// ```
+/// # use hecs::*;
+/// let mut world = World::new();
+/// let a = world.spawn((123, true));
+/// let b = world.spawn((456,));
+/// let query = DynamicQuery::new()
+///     .write(ComponentId::of::<i32>())
+///     .read_optional(ComponentId::of::<bool>());
+/// query.query(&world).for_each(|_, components| unsafe {
+///     let number = components[0].unwrap().cast::<i32>().as_ptr();
+///     if components[1].is_some() {
+///         *number += 1;
+///     }
+/// });
+/// assert_eq!(*world.get::<i32>(a).unwrap(), 124);
+/// assert_eq!(*world.get::<i32>(b).unwrap(), 456);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DynamicQuery {
+    terms: Vec<Term>,
+    without: Vec<ComponentId>,
+}
+
+impl DynamicQuery {
+    /// Create a query matching every entity, without accessing any components
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a term that borrows `id` components immutably, skipping entities that lack one
+    pub fn read(self, id: ComponentId) -> Self {
+        self.term(id, false, false)
+    }
+
+    /// Add a term that borrows `id` components uniquely, skipping entities that lack one
+    pub fn write(self, id: ComponentId) -> Self {
+        self.term(id, true, false)
+    }
+
+    /// Add a term that borrows `id` components immutably where present
+    pub fn read_optional(self, id: ComponentId) -> Self {
+        self.term(id, false, true)
+    }
+
+    /// Add a term that borrows `id` components uniquely where present
+    pub fn write_optional(self, id: ComponentId) -> Self {
+        self.term(id, true, true)
+    }
+
+    /// Skip entities having an `id` component
+    pub fn without(mut self, id: ComponentId) -> Self {
+        self.without.push(id);
+        self
+    }
+
+    fn term(mut self, id: ComponentId, write: bool, optional: bool) -> Self {
+        self.terms.push(Term {
+            id,
+            write,
+            optional,
+        });
+        self
+    }
+
+    /// Prepare to execute the query against `world`
+    pub fn query<'q>(&'q self, world: &'q World) -> DynamicQueryBorrow<'q> {
+        DynamicQueryBorrow {
+            query: self,
+            meta: world.entities_meta(),
+            archetypes: world.archetypes_inner(),
+            tick: world.change_tick(),
+            borrowed: false,
+        }
+    }
+
+    /// Whether entities in `archetype` may satisfy the query
+    fn matches(&self, archetype: &Archetype) -> bool {
+        self.terms
+            .iter()
+            .all(|term| term.optional || archetype.stores(term.id))
+            && !self.without.iter().any(|&id| archetype.has_dynamic(id))
+    }
+
+    /// The sparse sets in `archetype` deciding which of its entities satisfy the query, each
+    /// paired with whether entities must have a component in it
+    fn filters<'a>(
+        &'a self,
+        archetype: &'a Archetype,
+    ) -> impl Iterator<Item = (&'a SparseSet, bool)> + 'a {
+        let required = self
+            .terms
+            .iter()
+            .filter(|term| !term.optional)
+            .filter_map(move |term| Some((archetype.get_sparse(term.id)?, true)));
+        let excluded = self
+            .without
+            .iter()
+            .filter_map(move |&id| Some((archetype.get_sparse(id)?, false)));
+        required.chain(excluded)
+    }
+
+    /// Borrow every component in `archetype` accessed by the query, or none if any is already
+    /// borrowed in a conflicting manner
+    fn borrow(&self, archetype: &Archetype) -> Result<(), BorrowError> {
+        for (i, term) in self.terms.iter().enumerate() {
+            let result = if term.write {
+                archetype.try_borrow_mut_dynamic(term.id)
+            } else {
+                archetype.try_borrow_dynamic(term.id)
+            };
+            if let Err(e) = result {
+                self.release_terms(archetype, &self.terms[..i]);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn release(&self, archetype: &Archetype) {
+        self.release_terms(archetype, &self.terms);
+    }
+
+    fn release_terms(&self, archetype: &Archetype, terms: &[Term]) {
+        for term in terms {
+            if term.write {
+                archetype.release_mut_dynamic(term.id);
+            } else {
+                archetype.release_dynamic(term.id);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Term {
+    id: ComponentId,
+    write: bool,
+    optional: bool,
+}
+
+/// A borrow of a `World` sufficient to execute a `DynamicQuery`
+///
+/// Note that borrows are not released until this object is dropped.
+pub struct DynamicQueryBorrow<'q> {
+    query: &'q DynamicQuery,
+    meta: &'q [EntityMeta],
+    archetypes: &'q [Archetype],
+    tick: u64,
+    borrowed: bool,
+}
+
+impl<'q> DynamicQueryBorrow<'q> {
+    /// Execute the query, visiting each archetype containing matching entities
+    ///
+    /// Components of write terms are considered mutated by `Mutated` and `Changed` once their
+    /// archetype is visited. Must be called only once per query.
+    pub fn iter<'i>(&'i mut self) -> DynamicQueryIter<'i> {
+        self.borrow();
+        DynamicQueryIter {
+            query: self.query,
+            meta: self.meta,
+            archetypes: self.archetypes.iter(),
+            tick: self.tick,
+        }
+    }
+
+    /// Execute the query, calling `f` with each matching entity and pointers to its components
+    ///
+    /// The pointers are ordered like the query's terms, and are `None` for absent optional
+    /// components. Must be called only once per query.
+    pub fn for_each(&mut self, mut f: impl FnMut(Entity, &[Option<NonNull<u8>>])) {
+        let mut components = Vec::with_capacity(self.query.terms.len());
+        for chunk in self.iter() {
+            for row in 0..chunk.len() {
+                components.clear();
+                components.extend((0..chunk.columns.len()).map(|term| chunk.get(row, term)));
+                f(chunk.entity(row), &components);
+            }
+        }
+    }
+
+    fn borrow(&mut self) {
+        if self.borrowed {
+            panic!(
+                "called DynamicQueryBorrow::iter twice on the same borrow; construct a new query \
+                 instead"
+            );
+        }
+        for (i, archetype) in self.archetypes.iter().enumerate() {
+            if !self.query.matches(archetype) {
+                continue;
+            }
+            if let Err(e) = self.query.borrow(archetype) {
+                for archetype in &self.archetypes[..i] {
+                    if self.query.matches(archetype) {
+                        self.query.release(archetype);
+                    }
+                }
+                panic!("{}", e);
+            }
+        }
+        self.borrowed = true;
+    }
+}
+
+unsafe impl<'q> Send for DynamicQueryBorrow<'q> {}
+unsafe impl<'q> Sync for DynamicQueryBorrow<'q> {}
+
+impl<'q> Drop for DynamicQueryBorrow<'q> {
+    fn drop(&mut self) {
+        if self.borrowed {
+            for archetype in self.archetypes {
+                if self.query.matches(archetype) {
+                    self.query.release(archetype);
+                }
+            }
+        }
+    }
+}
+
+impl<'i, 'q> IntoIterator for &'i mut DynamicQueryBorrow<'q> {
+    type Item = DynamicChunk<'i>;
+    type IntoIter = DynamicQueryIter<'i>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over the archetypes containing entities matching a `DynamicQuery`
+pub struct DynamicQueryIter<'q> {
+    query: &'q DynamicQuery,
+    meta: &'q [EntityMeta],
+    archetypes: slice::Iter<'q, Archetype>,
+    tick: u64,
+}
+
+impl<'q> Iterator for DynamicQueryIter<'q> {
+    type Item = DynamicChunk<'q>;
+
+    fn next(&mut self) -> Option<DynamicChunk<'q>> {
+        loop {
+            let archetype = self.archetypes.next()?;
+            if archetype.is_empty() || !self.query.matches(archetype) {
+                continue;
+            }
+            // Sparsely stored components are only present for some entities
+            let rows = if self.query.filters(archetype).next().is_some() {
+                let rows = (0..archetype.len())
+                    .filter(|&row| {
+                        let id = archetype.entity_id(row);
+                        self.query
+                            .filters(archetype)
+                            .all(|(set, required)| set.contains(id) == required)
+                    })
+                    .collect::<Vec<_>>();
+                if rows.is_empty() {
+                    continue;
+                }
+                Some(rows)
+            } else {
+                None
+            };
+            let len = rows.as_ref().map_or(archetype.len() as usize, |x| x.len());
+            let row = |i: usize| rows.as_ref().map_or(i as u32, |x| x[i]);
+            let columns = self
+                .query
+                .terms
+                .iter()
+                .map(|term| {
+                    if let Some(state) = archetype.get_state_dynamic(term.id) {
+                        if term.write {
+                            let (_, mutated) = archetype.get_ticks_base(state);
+                            for i in 0..len {
+                                unsafe {
+                                    *mutated.as_ptr().add(row(i) as usize) = self.tick;
+                                }
+                            }
+                        }
+                        return Some(Column::Dense {
+                            base: archetype.get_base_dynamic(state),
+                            size: archetype.types()[state].layout().size(),
+                        });
+                    }
+                    let set = archetype.get_sparse(term.id)?;
+                    if term.write {
+                        for i in 0..len {
+                            if let Some(index) = set.index(archetype.entity_id(row(i))) {
+                                unsafe {
+                                    *set.ticks(index).1.as_ptr() = self.tick;
+                                }
+                            }
+                        }
+                    }
+                    Some(Column::Sparse(set))
+                })
+                .collect();
+            return Some(DynamicChunk {
+                archetype,
+                meta: self.meta,
+                rows,
+                columns,
+            });
+        }
+    }
+}
+
+/// The entities in one archetype matching a `DynamicQuery`, and their components
+///
+/// Components are stored in columns, one per term of the query, each holding `len` components
+/// contiguously, unless the query involves sparsely stored components. Such components are only
+/// available through `get`.
+pub struct DynamicChunk<'q> {
+    archetype: &'q Archetype,
+    meta: &'q [EntityMeta],
+    // Rows of the archetype holding the chunk's entities, if not all of them
+    rows: Option<Vec<u32>>,
+    columns: Vec<Option<Column<'q>>>,
+}
+
+impl<'q> DynamicChunk<'q> {
+    /// Number of entities in the chunk
+    pub fn len(&self) -> usize {
+        self.rows
+            .as_ref()
+            .map_or(self.archetype.len() as usize, |x| x.len())
+    }
+
+    /// Whether the chunk contains no entities
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The entity at `row`
+    pub fn entity(&self, row: usize) -> Entity {
+        let id = self.archetype.entity_id(self.row(row));
+        Entity {
+            id,
+            generation: self.meta[id as usize].generation,
+        }
+    }
+
+    /// The `term`th term's components, or `None` if its component type is optional and absent
+    ///
+    /// Also returns `None` if the components aren't stored contiguously, because they're stored
+    /// sparsely or the chunk holds only some of its archetype's entities.
+    pub fn column(&self, term: usize) -> Option<NonNull<[u8]>> {
+        if self.rows.is_some() {
+            return None;
+        }
+        let (base, size) = match self.columns[term]? {
+            Column::Dense { base, size } => (base, size),
+            Column::Sparse(_) => return None,
+        };
+        let len = size * self.len();
+        unsafe {
+            Some(NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(
+                base.as_ptr(),
+                len,
+            )))
+        }
+    }
+
+    /// Pointer to the `term`th term's component of the entity at `row`
+    ///
+    /// Returns `None` if the component type is optional and absent.
+    pub fn get(&self, row: usize, term: usize) -> Option<NonNull<u8>> {
+        assert!(row < self.len(), "row out of bounds");
+        let row = self.row(row);
+        match self.columns[term]? {
+            Column::Dense { base, size } => unsafe {
+                Some(NonNull::new_unchecked(
+                    base.as_ptr().add(size * row as usize),
+                ))
+            },
+            Column::Sparse(set) => {
+                let index = set.index(self.archetype.entity_id(row))?;
+                unsafe { Some(set.component(index)) }
+            }
+        }
+    }
+
+    /// The row of the archetype holding the entity at `row`
+    fn row(&self, row: usize) -> u32 {
+        self.rows.as_ref().map_or(row as u32, |x| x[row])
+    }
+}
+
+/// Where a term's components are found
+#[derive(Copy, Clone)]
+enum Column<'q> {
+    /// Pointer to the first component and size of each component
+    Dense {
+        base: NonNull<u8>,
+        size: usize,
+    },
+    Sparse(&'q SparseSet),
+}
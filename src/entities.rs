@@ -68,7 +68,54 @@ pub(crate) struct Entities {
     reserved_cursor: AtomicU32,
 }
 
+impl Clone for Entities {
+    fn clone(&self) -> Self {
+        let atomic = |x: &AtomicU32| AtomicU32::new(x.load(Ordering::Relaxed));
+        Self {
+            meta: self.meta.clone(),
+            pending: atomic(&self.pending),
+            free: self.free.clone(),
+            free_cursor: atomic(&self.free_cursor),
+            reserved: self.reserved.iter().map(atomic).collect(),
+            reserved_cursor: atomic(&self.reserved_cursor),
+        }
+    }
+}
+
 impl Entities {
+    /// Construct an allocator from the generation of every ID and the location of each live entity
+    pub fn restore(generations: &[u32], locations: &[Option<Location>]) -> Self {
+        debug_assert_eq!(generations.len(), locations.len());
+        let meta = generations
+            .iter()
+            .zip(locations)
+            .map(|(&generation, location)| EntityMeta {
+                generation,
+                location: location.unwrap_or(Location {
+                    archetype: 0,
+                    index: u32::MAX,
+                }),
+            })
+            .collect::<Box<[_]>>();
+        // Reversed so that the lowest free IDs are reused first, as after `grow`
+        let mut free = (0..meta.len() as u32)
+            .rev()
+            .filter(|&id| locations[id as usize].is_none())
+            .collect::<Vec<_>>();
+        let free_cursor = AtomicU32::new(free.len() as u32);
+        free.resize(meta.len(), 0);
+        let mut reserved = Vec::with_capacity(meta.len());
+        reserved.resize_with(meta.len(), || AtomicU32::new(0));
+        Self {
+            meta,
+            pending: AtomicU32::new(0),
+            free: free.into(),
+            free_cursor,
+            reserved: reserved.into(),
+            reserved_cursor: AtomicU32::new(0),
+        }
+    }
+
     /// Reserve an entity ID concurrently
     ///
     /// Storage for entity generation and location is lazily allocated by calling `flush`. Locations
@@ -160,13 +207,13 @@ impl Entities {
             Location {
                 archetype: 0,
                 // Guard against bugs in reservation handling
-                index: u32::max_value(),
+                index: u32::MAX,
             },
         );
         let index = self.free_cursor.fetch_add(1, Ordering::Relaxed); // Not racey due to &mut self
         self.free[index as usize] = entity.id;
         debug_assert!(
-            loc.index != u32::max_value(),
+            loc.index != u32::MAX,
             "free called on reserved entity without flush"
         );
         Ok(loc)
@@ -215,24 +262,18 @@ impl Entities {
         }
     }
 
-    /// Returns `Ok(Location { archetype: 0, index: undefined })` for pending entities
+    /// Returns `Ok(Location { archetype: 0, index: u32::MAX })` for pending entities
     pub fn get(&self, entity: Entity) -> Result<Location, NoSuchEntity> {
         if self.meta.len() <= entity.id as usize {
             return Ok(Location {
                 archetype: 0,
-                index: u32::max_value(),
+                index: u32::MAX,
             });
         }
         let meta = &self.meta[entity.id as usize];
         if meta.generation != entity.generation {
             return Err(NoSuchEntity);
         }
-        if meta.location.archetype == 0 {
-            return Ok(Location {
-                archetype: 0,
-                index: u32::max_value(),
-            });
-        }
         Ok(meta.location)
     }
 
@@ -280,7 +321,7 @@ impl Entities {
                 generation: 0,
                 location: Location {
                     archetype: 0,
-                    index: u32::max_value(), // dummy value, to be filled in
+                    index: u32::MAX, // dummy value, to be filled in
                 },
             },
         );
@@ -310,10 +351,12 @@ impl Entities {
     }
 }
 
+/// Where an entity's components are stored
+#[doc(hidden)]
 #[derive(Copy, Clone)]
-pub(crate) struct EntityMeta {
-    pub generation: u32,
-    pub location: Location,
+pub struct EntityMeta {
+    pub(crate) generation: u32,
+    pub(crate) location: Location,
 }
 
 #[derive(Copy, Clone)]
@@ -322,6 +365,14 @@ pub(crate) struct Location {
     pub index: u32,
 }
 
+impl Location {
+    /// Whether this is the location of an entity that was reserved but not yet flushed, and hence
+    /// has no components
+    pub fn is_pending(&self) -> bool {
+        self.index == u32::MAX
+    }
+}
+
 /// Error indicating that no entity with a particular ID exists
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct NoSuchEntity;
@@ -15,13 +15,12 @@
 use crate::alloc::alloc::{alloc, dealloc, Layout};
 use crate::alloc::boxed::Box;
 use crate::alloc::{vec, vec::Vec};
-use core::any::TypeId;
 use core::mem::{self, MaybeUninit};
 use core::ptr;
 
 use hashbrown::HashSet;
 
-use crate::archetype::TypeInfo;
+use crate::archetype::{ComponentId, TypeInfo};
 use crate::{Component, DynamicBundle};
 
 /// Helper for incrementally constructing a bundle of components with dynamic component types
@@ -41,8 +40,8 @@ pub struct EntityBuilder {
     storage: Box<[MaybeUninit<u8>]>,
     cursor: usize,
     info: Vec<(TypeInfo, usize)>,
-    ids: Vec<TypeId>,
-    id_set: HashSet<TypeId>,
+    ids: Vec<ComponentId>,
+    id_set: HashSet<ComponentId>,
 }
 
 impl EntityBuilder {
@@ -58,26 +57,77 @@ impl EntityBuilder {
     }
 
     /// Add `component` to the entity
-    pub fn add<T: Component>(&mut self, component: T) -> &mut Self {
-        if !self.id_set.insert(TypeId::of::<T>()) {
-            return self;
+    pub fn add<T: Component>(&mut self, mut component: T) -> &mut Self {
+        unsafe {
+            if self.put_raw((&mut component as *mut T).cast(), TypeInfo::of::<T>()) {
+                mem::forget(component);
+            }
+        }
+        self
+    }
+
+    /// Add every component in `bundle` to the entity
+    ///
+    /// Useful for composing entities from several bundles, since a tuple can hold at most 15
+    /// components. See also `Flatten`. Components of a type that was already added are ignored, as with `add`.
+    ///
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// let mut builder = EntityBuilder::new();
+    /// builder.add_bundle((123, "abc")).add_bundle((true, 4.0f32));
+    /// let e = world.spawn(builder.build());
+    /// assert_eq!(*world.get::<&str>(e).unwrap(), "abc");
+    /// assert_eq!(*world.get::<f32>(e).unwrap(), 4.0);
+    /// ```
+    pub fn add_bundle(&mut self, bundle: impl DynamicBundle) -> &mut Self {
+        let info = bundle.type_info();
+        unsafe {
+            bundle.put(|ptr, ty, _| {
+                let info = info.iter().find(|x| x.id() == ty).unwrap();
+                self.put_raw(ptr, *info)
+            });
+        }
+        self
+    }
+
+    /// Add the component of type `ty` stored at `component` to the entity
+    ///
+    /// The component is moved out of `component`, or dropped if a component of the same type was
+    /// already added. Useful for components of types defined with `TypeInfo::dynamic`.
+    ///
+    /// # Safety
+    ///
+    /// `component` must point to a valid component of type `ty`, which must not be used again.
+    /// Components of dynamic types must be safe to send and share between threads.
+    pub unsafe fn add_raw(&mut self, ty: TypeInfo, component: *mut u8) -> &mut Self {
+        if !self.put_raw(component, ty) {
+            ty.drop(component);
         }
-        let end = self.cursor + mem::size_of::<T>();
+        self
+    }
+
+    /// Move the component described by `info` out of `ptr`, returning `false` if a component of
+    /// the same type was already added, in which case it's left in place
+    unsafe fn put_raw(&mut self, ptr: *mut u8, info: TypeInfo) -> bool {
+        if !self.id_set.insert(info.id()) {
+            return false;
+        }
+        let size = info.layout().size();
+        let end = self.cursor + size;
         if end > self.storage.len() {
             self.grow(end);
         }
-        if mem::size_of::<T>() != 0 {
-            unsafe {
-                self.storage
-                    .as_mut_ptr()
-                    .add(self.cursor)
-                    .cast::<T>()
-                    .write_unaligned(component);
-            }
+        if size != 0 {
+            ptr::copy_nonoverlapping(
+                ptr,
+                self.storage.as_mut_ptr().add(self.cursor).cast::<u8>(),
+                size,
+            );
         }
-        self.info.push((TypeInfo::of::<T>(), self.cursor));
-        self.cursor += mem::size_of::<T>();
-        self
+        self.info.push((info, self.cursor));
+        self.cursor += size;
+        true
     }
 
     fn grow(&mut self, min_size: usize) {
@@ -160,7 +210,7 @@ pub struct BuiltEntity<'a> {
 }
 
 impl DynamicBundle for BuiltEntity<'_> {
-    fn with_ids<T>(&self, f: impl FnOnce(&[TypeId]) -> T) -> T {
+    fn with_ids<T>(&self, f: impl FnOnce(&[ComponentId]) -> T) -> T {
         f(&self.builder.ids)
     }
 
@@ -169,7 +219,7 @@ impl DynamicBundle for BuiltEntity<'_> {
         self.builder.info.iter().map(|x| x.0).collect()
     }
 
-    unsafe fn put(self, mut f: impl FnMut(*mut u8, TypeId, usize) -> bool) {
+    unsafe fn put(self, mut f: impl FnMut(*mut u8, ComponentId, usize) -> bool) {
         for (ty, offset) in self.builder.info.drain(..) {
             let ptr = self.builder.storage.as_mut_ptr().add(offset).cast();
             if !f(ptr, ty.id(), ty.layout().size()) {
@@ -0,0 +1,154 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::fmt;
+
+use hashbrown::HashMap;
+
+use crate::{Component, ComponentId, EntityRef};
+
+/// Formats the `T` component of an entity known to have one
+type FormatFn = fn(EntityRef<'_>, &mut fmt::Formatter<'_>) -> fmt::Result;
+
+/// The component types that can be formatted without being statically known
+///
+/// Useful for printing arbitrary entities, e.g. for debugging.
+///
+/// # Example
+/// ```
+/// # use hecs::*;
+/// let registry = FormatRegistry::new()
+///     .register_debug::<&str>()
+///     .register_display::<i32>();
+/// let mut world = World::new();
+/// let a = world.spawn((123, "abc", true));
+/// let entity = world.entity(a).unwrap();
+/// let number = registry.component(entity, ComponentId::of::<i32>()).unwrap();
+/// assert_eq!(number.to_string(), "123");
+/// let text = format!("{:?}", registry.entity(entity));
+/// assert!(text == r#"{&str: "abc", i32: 123}"# || text == r#"{i32: 123, &str: "abc"}"#);
+/// ```
+#[derive(Default)]
+pub struct FormatRegistry {
+    types: HashMap<ComponentId, FormatFn>,
+}
+
+impl FormatRegistry {
+    /// Create a registry with no component types
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Format components of type `T` using its `Debug` impl
+    pub fn register_debug<T: Component + fmt::Debug>(mut self) -> Self {
+        fn format<T: Component + fmt::Debug>(
+            entity: EntityRef<'_>,
+            f: &mut fmt::Formatter<'_>,
+        ) -> fmt::Result {
+            fmt::Debug::fmt(&*entity.get::<T>().unwrap(), f)
+        }
+
+        self.types.insert(ComponentId::of::<T>(), format::<T>);
+        self
+    }
+
+    /// Format components of type `T` using its `Display` impl
+    pub fn register_display<T: Component + fmt::Display>(mut self) -> Self {
+        fn format<T: Component + fmt::Display>(
+            entity: EntityRef<'_>,
+            f: &mut fmt::Formatter<'_>,
+        ) -> fmt::Result {
+            fmt::Display::fmt(&*entity.get::<T>().unwrap(), f)
+        }
+
+        self.types.insert(ComponentId::of::<T>(), format::<T>);
+        self
+    }
+
+    /// Formatter for the `id` component of `entity`
+    ///
+    /// Returns `None` if the entity has no such component or its type isn't registered.
+    /// Formatting panics if the component is uniquely borrowed.
+    pub fn component<'a>(
+        &'a self,
+        entity: EntityRef<'a>,
+        id: ComponentId,
+    ) -> Option<FormatComponent<'a>> {
+        if !entity.component_types().any(|ty| ty.id() == id) {
+            return None;
+        }
+        Some(FormatComponent {
+            entity,
+            format: *self.types.get(&id)?,
+        })
+    }
+
+    /// Formatter for every component of `entity` whose type is registered
+    ///
+    /// Formatted with `Debug` as a map from type names to components. Formatting panics if any of
+    /// the components is uniquely borrowed.
+    pub fn entity<'a>(&'a self, entity: EntityRef<'a>) -> FormatEntity<'a> {
+        FormatEntity {
+            registry: self,
+            entity,
+        }
+    }
+}
+
+/// Formats a single component of an entity, as returned by `FormatRegistry::component`
+///
+/// The `Debug` and `Display` impls both use the formatting registered for the component's type.
+#[derive(Copy, Clone)]
+pub struct FormatComponent<'a> {
+    entity: EntityRef<'a>,
+    format: FormatFn,
+}
+
+impl fmt::Debug for FormatComponent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (self.format)(self.entity, f)
+    }
+}
+
+impl fmt::Display for FormatComponent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (self.format)(self.entity, f)
+    }
+}
+
+/// Formats the registered components of an entity, as returned by `FormatRegistry::entity`
+#[derive(Copy, Clone)]
+pub struct FormatEntity<'a> {
+    registry: &'a FormatRegistry,
+    entity: EntityRef<'a>,
+}
+
+impl fmt::Debug for FormatEntity<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct Name(&'static str);
+
+        impl fmt::Debug for Name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.0)
+            }
+        }
+
+        f.debug_map()
+            .entries(self.entity.component_types().filter_map(|ty| {
+                let component = self.registry.component(self.entity, ty.id())?;
+                Some((Name(ty.type_name()), component))
+            }))
+            .finish()
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::alloc::collections::VecDeque;
+use crate::alloc::vec::Vec;
+use core::fmt;
+use core::ops::Deref;
+
+#[cfg(feature = "std")]
+use std::error::Error;
+
+use crate::{Entity, NoSuchEntity, World};
+
+/// Component recording the parent of an entity in a hierarchy
+///
+/// Maintained by `World::set_parent` and `World::remove_parent`, along with the parent's
+/// `Children`. Removing this component with `World::remove` or despawning the entity removes it
+/// from its parent's `Children` as well. Inserting one, for example after removing it from another
+/// entity, makes the entity a child of its parent as by `set_parent`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Parent(pub(crate) Entity);
+
+impl Parent {
+    /// The parent entity
+    pub fn entity(&self) -> Entity {
+        self.0
+    }
+}
+
+/// Component recording the children of an entity in a hierarchy, in the order they were added
+///
+/// Present only on entities having at least one child. Maintained alongside each child's `Parent`.
+/// Removing this component with `World::remove` or despawning the entity leaves its children
+/// without a parent. Inserting one makes each of its entities a child of the entity as by
+/// `set_parent`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Children(pub(crate) Vec<Entity>);
+
+impl Deref for Children {
+    type Target = [Entity];
+    fn deref(&self) -> &[Entity] {
+        &self.0
+    }
+}
+
+/// Error indicating that an entity couldn't be made a child of another
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum HierarchyError {
+    /// One of the entities was already despawned
+    NoSuchEntity,
+    /// The parent is the child itself or one of its descendants
+    Cycle,
+}
+
+#[cfg(feature = "std")]
+impl Error for HierarchyError {}
+
+impl fmt::Display for HierarchyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use HierarchyError::*;
+        match *self {
+            NoSuchEntity => f.write_str("no such entity"),
+            Cycle => f.write_str("hierarchy would contain a cycle"),
+        }
+    }
+}
+
+impl From<NoSuchEntity> for HierarchyError {
+    fn from(NoSuchEntity: NoSuchEntity) -> Self {
+        HierarchyError::NoSuchEntity
+    }
+}
+
+/// Iterator over the descendants of an entity, visiting each child's descendants before its next
+/// sibling
+///
+/// Returned by `World::descendants_depth_first`.
+pub struct DescendantsDepthFirst<'a> {
+    world: &'a World,
+    stack: Vec<Entity>,
+}
+
+impl<'a> DescendantsDepthFirst<'a> {
+    pub(crate) fn new(world: &'a World, root: Entity) -> Self {
+        let mut stack = Vec::new();
+        push_children(world, root, &mut stack);
+        stack.reverse();
+        Self { world, stack }
+    }
+}
+
+impl Iterator for DescendantsDepthFirst<'_> {
+    type Item = Entity;
+
+    fn next(&mut self) -> Option<Entity> {
+        let entity = self.stack.pop()?;
+        // Children are pushed in reverse so that the first child is visited first
+        let start = self.stack.len();
+        push_children(self.world, entity, &mut self.stack);
+        self.stack[start..].reverse();
+        Some(entity)
+    }
+}
+
+/// Iterator over the descendants of an entity, visiting all children before any grandchildren
+///
+/// Returned by `World::descendants_breadth_first`.
+pub struct DescendantsBreadthFirst<'a> {
+    world: &'a World,
+    queue: VecDeque<Entity>,
+}
+
+impl<'a> DescendantsBreadthFirst<'a> {
+    pub(crate) fn new(world: &'a World, root: Entity) -> Self {
+        let mut queue = VecDeque::new();
+        push_children(world, root, &mut queue);
+        Self { world, queue }
+    }
+}
+
+impl Iterator for DescendantsBreadthFirst<'_> {
+    type Item = Entity;
+
+    fn next(&mut self) -> Option<Entity> {
+        let entity = self.queue.pop_front()?;
+        push_children(self.world, entity, &mut self.queue);
+        Some(entity)
+    }
+}
+
+fn push_children(world: &World, entity: Entity, out: &mut impl Extend<Entity>) {
+    if let Ok(children) = world.get::<Children>(entity) {
+        out.extend(children.iter().copied());
+    }
+}
@@ -64,29 +64,63 @@ macro_rules! smaller_tuples_too {
 mod archetype;
 mod borrow;
 mod bundle;
+mod clone_registry;
+mod command_buffer;
+mod dynamic_query;
 mod entities;
 mod entity_builder;
+mod format_registry;
+mod hierarchy;
+mod lock;
+mod prepared_query;
 mod query;
 mod query_one;
+mod relation;
+#[cfg(feature = "serde")]
+pub mod serialize;
+pub mod snapshot;
+mod sparse_set;
 mod world;
 
-pub use archetype::Archetype;
-pub use borrow::{EntityRef, Ref, RefMut};
-pub use bundle::{Bundle, DynamicBundle, MissingComponent};
+pub use archetype::{Archetype, ArchetypeColumn, ComponentId, TypeInfo};
+pub use borrow::{BorrowError, EntityRef, Ref, RefMut};
+pub use bundle::{Bundle, DynamicBundle, Flatten, MissingComponent};
+pub use clone_registry::{CloneError, CloneRegistry};
+pub use command_buffer::CommandBuffer;
+pub use dynamic_query::{DynamicChunk, DynamicQuery, DynamicQueryBorrow, DynamicQueryIter};
 pub use entities::{Entity, NoSuchEntity};
 pub use entity_builder::{BuiltEntity, EntityBuilder};
-pub use query::{Access, BatchedIter, Query, QueryBorrow, QueryIter, With, Without};
-pub use query_one::QueryOne;
-pub use world::{ArchetypesGeneration, Component, ComponentError, Iter, SpawnBatchIter, World};
+pub use format_registry::{FormatComponent, FormatEntity, FormatRegistry};
+pub use hierarchy::{
+    Children, DescendantsBreadthFirst, DescendantsDepthFirst, HierarchyError, Parent,
+};
+pub use prepared_query::{PreparedQuery, PreparedQueryBorrow, PreparedQueryIter};
+#[cfg(feature = "rayon")]
+pub use query::ParIter;
+pub use query::{
+    Access, Added, BatchedIter, Changed, Mutated, Or, Query, QueryBorrow, QueryIter, QueryMut,
+    Satisfies, With, Without,
+};
+pub use query_one::{QueryOne, QueryOneError};
+pub use relation::Relation;
+pub use world::{
+    ArchetypesGeneration, Component, ComponentError, Iter, SpawnBatchIter, TryGetError, World,
+};
 
 // Unstable implementation details needed by the macros
+#[cfg(feature = "macros")]
+#[doc(hidden)]
+pub use bundle::BundleIdCache;
 #[doc(hidden)]
-pub use archetype::TypeInfo;
+pub use entities::EntityMeta;
 #[cfg(feature = "macros")]
 #[doc(hidden)]
 pub use lazy_static;
 #[doc(hidden)]
-pub use query::Fetch;
+pub use query::{Fetch, Prepare, Ticks};
+#[cfg(feature = "macros")]
+#[doc(hidden)]
+pub use {alloc::vec::Vec, core};
 
 #[cfg(feature = "macros")]
-pub use hecs_macros::Bundle;
+pub use hecs_macros::{Bundle, Query};
@@ -0,0 +1,288 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::alloc::vec::Vec;
+
+use crate::archetype::Archetype;
+use crate::entities::EntityMeta;
+use crate::query::{add_bounds, assert_borrow, bounds, ChunkIter, Fetch, Prepare, Ticks};
+use crate::{Access, ArchetypesGeneration, Entity, Query, World};
+
+/// A query that remembers which archetypes it matches, for efficient repeated execution
+///
+/// Executing a `QueryBorrow` examines every archetype in the world. A `PreparedQuery` instead
+/// caches the matching archetypes and where its components are stored within each, only examining
+/// archetypes that were added to the world since it was last executed. This makes it well suited
+/// to queries that run every frame against worlds with many archetypes.
+///
+/// A `PreparedQuery` may be used with any world, but is most efficient when repeatedly used with
+/// the same one.
+///
+/// # Example
+/// ```
+/// # use hecs::*;
+/// let mut world = World::new();
+/// let a = world.spawn((123, true));
+/// let mut query = PreparedQuery::<(&mut i32, &bool)>::new();
+/// for _ in 0..2 {
+///     for (_, (number, &flag)) in query.query(&world).iter() {
+///         if flag { *number += 1; }
+///     }
+/// }
+/// world.spawn((42, "abc"));
+/// assert_eq!(query.query_mut(&mut world).count(), 1);
+/// assert_eq!(*world.get::<i32>(a).unwrap(), 125);
+/// ```
+pub struct PreparedQuery<Q: Query> {
+    // Identifies the world `state` was computed for, or 0 if none
+    world: u64,
+    generation: Option<ArchetypesGeneration>,
+    // Number of the world's archetypes that have been examined
+    examined: usize,
+    // Index and state of every matching archetype
+    state: Vec<(usize, <Q::Fetch as Prepare>::State)>,
+}
+
+impl<Q: Query> PreparedQuery<Q> {
+    /// Create a prepared query that hasn't yet examined any world
+    pub fn new() -> Self {
+        Self {
+            world: 0,
+            generation: None,
+            examined: 0,
+            state: Vec::new(),
+        }
+    }
+
+    /// Query `world`, examining only archetypes added since the last query of the same world
+    ///
+    /// Like `World::query`, components are borrowed dynamically when the result is iterated.
+    pub fn query<'q>(&'q mut self, world: &'q World) -> PreparedQueryBorrow<'q, Q> {
+        self.prepare(world);
+        PreparedQueryBorrow {
+            meta: world.entities_meta(),
+            archetypes: world.archetypes_inner(),
+            state: &self.state,
+            ticks: world.ticks(),
+            borrowed: false,
+        }
+    }
+
+    /// Query a uniquely borrowed `world`, examining only archetypes added since the last query of
+    /// the same world
+    ///
+    /// Like `World::query_mut`, no dynamic borrow checks are performed, and this panics if `Q`
+    /// borrows a component type uniquely while also accessing it elsewhere.
+    pub fn query_mut<'q>(&'q mut self, world: &'q mut World) -> PreparedQueryIter<'q, Q> {
+        assert_borrow::<Q>();
+        self.prepare(world);
+        let world = &*world;
+        PreparedQueryIter::new(
+            world.entities_meta(),
+            world.archetypes_inner(),
+            &self.state,
+            world.ticks(),
+        )
+    }
+
+    /// Bring the cached state up to date with `world`'s archetypes
+    fn prepare(&mut self, world: &World) {
+        if self.world != world.id() {
+            self.world = world.id();
+            self.generation = None;
+            self.examined = 0;
+            self.state.clear();
+        }
+        let generation = world.archetypes_generation();
+        if self.generation == Some(generation) {
+            return;
+        }
+        // Archetypes are never removed or reordered, so only new ones need to be examined
+        let archetypes = world.archetypes_inner();
+        for (index, archetype) in archetypes.iter().enumerate().skip(self.examined) {
+            if let Some(state) = Q::Fetch::prepare(archetype) {
+                self.state.push((index, state));
+            }
+        }
+        self.examined = archetypes.len();
+        self.generation = Some(generation);
+    }
+}
+
+impl<Q: Query> Default for PreparedQuery<Q> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A borrow of a `World` sufficient to execute a `PreparedQuery`
+///
+/// Note that borrows are not released until this object is dropped.
+pub struct PreparedQueryBorrow<'q, Q: Query> {
+    meta: &'q [EntityMeta],
+    archetypes: &'q [Archetype],
+    state: &'q [(usize, <Q::Fetch as Prepare>::State)],
+    ticks: Ticks,
+    borrowed: bool,
+}
+
+impl<'q, Q: Query> PreparedQueryBorrow<'q, Q> {
+    /// Execute the query
+    ///
+    /// Must be called only once per query.
+    pub fn iter<'i>(&'i mut self) -> PreparedQueryIter<'i, Q> {
+        self.borrow();
+        PreparedQueryIter::new(self.meta, self.archetypes, self.state, self.ticks)
+    }
+
+    /// Only consider changes made after `tick` in `Added`, `Mutated` and `Changed` query elements
+    ///
+    /// See `QueryBorrow::since`.
+    pub fn since(mut self, tick: u64) -> Self {
+        self.ticks.since = tick;
+        self
+    }
+
+    fn borrow(&mut self) {
+        if self.borrowed {
+            panic!(
+                "called PreparedQueryBorrow::iter twice on the same borrow; construct a new query \
+                 instead"
+            );
+        }
+        for (i, &(index, _)) in self.state.iter().enumerate() {
+            let archetype = &self.archetypes[index];
+            if Q::Fetch::access(archetype) >= Some(Access::Read) {
+                if let Err(e) = Q::Fetch::borrow(archetype) {
+                    self.release(&self.state[..i]);
+                    panic!("{}", e);
+                }
+            }
+        }
+        self.borrowed = true;
+    }
+
+    fn release(&self, state: &[(usize, <Q::Fetch as Prepare>::State)]) {
+        for &(index, _) in state {
+            let archetype = &self.archetypes[index];
+            if Q::Fetch::access(archetype) >= Some(Access::Read) {
+                Q::Fetch::release(archetype);
+            }
+        }
+    }
+}
+
+unsafe impl<'q, Q: Query> Send for PreparedQueryBorrow<'q, Q> {}
+unsafe impl<'q, Q: Query> Sync for PreparedQueryBorrow<'q, Q> {}
+
+impl<'q, Q: Query> Drop for PreparedQueryBorrow<'q, Q> {
+    fn drop(&mut self) {
+        if self.borrowed {
+            self.release(self.state);
+        }
+    }
+}
+
+impl<'i, 'q, Q: Query> IntoIterator for &'i mut PreparedQueryBorrow<'q, Q> {
+    type Item = (Entity, <Q::Fetch as Fetch<'i>>::Item);
+    type IntoIter = PreparedQueryIter<'i, Q>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over the entities matching a `PreparedQuery`
+pub struct PreparedQueryIter<'q, Q: Query> {
+    meta: &'q [EntityMeta],
+    archetypes: &'q [Archetype],
+    state: &'q [(usize, <Q::Fetch as Prepare>::State)],
+    ticks: Ticks,
+    state_index: usize,
+    iter: Option<ChunkIter<Q>>,
+}
+
+impl<'q, Q: Query> PreparedQueryIter<'q, Q> {
+    fn new(
+        meta: &'q [EntityMeta],
+        archetypes: &'q [Archetype],
+        state: &'q [(usize, <Q::Fetch as Prepare>::State)],
+        ticks: Ticks,
+    ) -> Self {
+        Self {
+            meta,
+            archetypes,
+            state,
+            ticks,
+            state_index: 0,
+            iter: None,
+        }
+    }
+}
+
+unsafe impl<'q, Q: Query> Send for PreparedQueryIter<'q, Q> {}
+unsafe impl<'q, Q: Query> Sync for PreparedQueryIter<'q, Q> {}
+
+impl<'q, Q: Query> Iterator for PreparedQueryIter<'q, Q> {
+    type Item = (Entity, <Q::Fetch as Fetch<'q>>::Item);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.iter {
+                None => {
+                    let &(index, state) = self.state.get(self.state_index)?;
+                    self.state_index += 1;
+                    let archetype = &self.archetypes[index];
+                    self.iter = Some(ChunkIter {
+                        entities: archetype.entities(),
+                        fetch: unsafe {
+                            Q::Fetch::execute(archetype, self.meta, state, 0, self.ticks)
+                        },
+                        len: archetype.len(),
+                    });
+                }
+                Some(ref mut iter) => match unsafe { iter.next() } {
+                    None => {
+                        self.iter = None;
+                        continue;
+                    }
+                    Some((id, components)) => {
+                        return Some((
+                            Entity {
+                                id,
+                                generation: self.meta[id as usize].generation,
+                            },
+                            components,
+                        ));
+                    }
+                },
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let current = self.iter.as_ref().map(|iter| {
+            let (_, state) = self.state[self.state_index - 1];
+            bounds::<Q>(state, iter.len as usize)
+        });
+        let rest = self.state[self.state_index..]
+            .iter()
+            .map(|&(index, state)| bounds::<Q>(state, self.archetypes[index].len() as usize));
+        current
+            .into_iter()
+            .chain(rest)
+            .fold((0, Some(0)), add_bounds)
+    }
+}
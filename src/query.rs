@@ -12,21 +12,42 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(feature = "rayon")]
+use crate::alloc::vec::Vec;
+use core::any::TypeId;
 use core::marker::PhantomData;
 use core::ptr::NonNull;
 
-use crate::archetype::Archetype;
+use hashbrown::HashSet;
+
+use crate::archetype::{Archetype, ComponentId, Storage};
 use crate::entities::EntityMeta;
-use crate::{Component, Entity};
+use crate::lock::Lock;
+use crate::sparse_set::SparseSet;
+use crate::{BorrowError, Component, Entity};
 
 /// A collection of component types to fetch from a `World`
+///
+/// Tuples of up to 15 queries are themselves queries, and may be nested to fetch any number of
+/// components.
 pub trait Query {
     #[doc(hidden)]
-    type Fetch: for<'a> Fetch<'a>;
+    type Fetch: for<'a> Fetch<'a> + 'static;
+}
+
+/// Per-archetype information a `Fetch` looks up once and reuses for every traversal
+pub trait Prepare {
+    /// Where the fetched data is found within an archetype
+    type State: Copy;
+
+    /// Look up the state for `archetype` if it should be traversed
+    ///
+    /// The result remains valid for as long as `archetype` exists.
+    fn prepare(archetype: &Archetype) -> Option<Self::State>;
 }
 
 /// Streaming iterators over contiguous homogeneous ranges of components
-pub trait Fetch<'a>: Sized {
+pub trait Fetch<'a>: Prepare + Sized {
     /// Type of value to be fetched
     type Item;
 
@@ -34,15 +55,76 @@ pub trait Fetch<'a>: Sized {
     fn access(archetype: &Archetype) -> Option<Access>;
 
     /// Acquire dynamic borrows from `archetype`
-    fn borrow(archetype: &Archetype);
+    ///
+    /// On failure, no borrows remain acquired.
+    fn borrow(archetype: &Archetype) -> Result<(), BorrowError>;
+    /// Construct a `Fetch` for `archetype` from state previously returned by `prepare`
+    ///
+    /// `meta` is the metadata of the world's entities, indexed by entity ID.
+    ///
+    /// # Safety
+    /// `offset` must be in bounds of `archetype`, `state` must have come from `prepare` on the
+    /// same archetype, and `meta` must belong to the world `archetype` is from
+    unsafe fn execute(
+        archetype: &'a Archetype,
+        meta: &'a [EntityMeta],
+        state: Self::State,
+        offset: usize,
+        ticks: Ticks,
+    ) -> Self;
     /// Construct a `Fetch` for `archetype` if it should be traversed
     ///
     /// # Safety
-    /// `offset` must be in bounds of `archetype`
-    unsafe fn get(archetype: &'a Archetype, offset: usize) -> Option<Self>;
+    /// `offset` must be in bounds of `archetype`, and `meta` must belong to the world `archetype` is
+    /// from
+    unsafe fn get(
+        archetype: &'a Archetype,
+        meta: &'a [EntityMeta],
+        offset: usize,
+        ticks: Ticks,
+    ) -> Option<Self> {
+        Some(Self::execute(
+            archetype,
+            meta,
+            Self::prepare(archetype)?,
+            offset,
+            ticks,
+        ))
+    }
     /// Release dynamic borrows acquired by `borrow`
     fn release(archetype: &Archetype);
 
+    /// Invoke `f` for every component type that may be borrowed and whether the borrow is unique
+    fn for_each_borrow(f: impl FnMut(ComponentId, bool));
+
+    /// Whether `should_skip` may return `true` in the archetype `state` was prepared from
+    ///
+    /// Must be overridden by any `Fetch` that overrides `should_skip`.
+    #[allow(unused_variables)]
+    fn filters(state: Self::State) -> bool {
+        false
+    }
+
+    /// Whether the next item in this archetype should be passed over using `skip`
+    ///
+    /// # Safety
+    /// Bounds-checking must be performed externally
+    unsafe fn should_skip(&self) -> bool {
+        false
+    }
+
+    /// Whether the next item has every component this fetch requires, determined without
+    /// accessing any components or change ticks
+    ///
+    /// Used by `Satisfies`. Must be overridden by any `Fetch` whose `filters` may return `true`
+    /// because of sparsely stored components.
+    ///
+    /// # Safety
+    /// Bounds-checking must be performed externally
+    unsafe fn present(&self) -> bool {
+        true
+    }
+
     /// Access the next item in this archetype without bounds checking
     ///
     /// # Safety
@@ -51,10 +133,26 @@ pub trait Fetch<'a>: Sized {
     /// - Bounds-checking must be performed externally
     /// - Any resulting borrows must be legal (e.g. no &mut to something another iterator might access)
     unsafe fn next(&mut self) -> Self::Item;
+
+    /// Advance past the next item in this archetype without accessing it
+    ///
+    /// # Safety
+    /// Bounds-checking must be performed externally
+    unsafe fn skip(&mut self);
+}
+
+/// Change ticks a query is executed with
+#[doc(hidden)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Ticks {
+    /// The tick mutable accesses are recorded at
+    pub current: u64,
+    /// Changes at ticks after this one are visible to `Added`, `Mutated` and `Changed`
+    pub since: u64,
 }
 
 /// Type of access a `Query` may have to an `Archetype`
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub enum Access {
     /// Read entity IDs only, no components
     Iterate,
@@ -64,77 +162,210 @@ pub enum Access {
     Write,
 }
 
-impl<'a, T: Component> Query for &'a T {
+/// Query element yielding the ID of each entity
+///
+/// Useful for obtaining entity IDs in nested queries, `Or` and `Option`.
+///
+/// # Example
+/// ```
+/// # use hecs::*;
+/// let mut world = World::new();
+/// let a = world.spawn((123, true));
+/// let entities = world.query::<(&i32, Option<(Entity, &bool)>)>()
+///     .iter()
+///     .map(|(_, (&i, nested))| (i, nested.map(|(e, _)| e)))
+///     .collect::<Vec<_>>();
+/// assert_eq!(entities, &[(123, Some(a))]);
+/// ```
+impl Query for Entity {
+    type Fetch = FetchEntity;
+}
+
+#[doc(hidden)]
+pub struct FetchEntity {
+    entities: NonNull<u32>,
+    meta: NonNull<EntityMeta>,
+}
+
+impl Prepare for FetchEntity {
+    type State = ();
+
+    fn prepare(_: &Archetype) -> Option<()> {
+        Some(())
+    }
+}
+
+impl<'a> Fetch<'a> for FetchEntity {
+    type Item = Entity;
+
+    fn access(_: &Archetype) -> Option<Access> {
+        Some(Access::Iterate)
+    }
+
+    fn borrow(_: &Archetype) -> Result<(), BorrowError> {
+        Ok(())
+    }
+    unsafe fn execute(
+        archetype: &'a Archetype,
+        meta: &'a [EntityMeta],
+        _: (),
+        offset: usize,
+        _: Ticks,
+    ) -> Self {
+        Self {
+            entities: NonNull::new_unchecked(archetype.entities().as_ptr().add(offset)),
+            meta: NonNull::from(meta).cast(),
+        }
+    }
+    fn release(_: &Archetype) {}
+    fn for_each_borrow(_: impl FnMut(ComponentId, bool)) {}
+
+    unsafe fn next(&mut self) -> Entity {
+        let id = *self.entities.as_ptr();
+        self.skip();
+        Entity {
+            id,
+            generation: (*self.meta.as_ptr().add(id as usize)).generation,
+        }
+    }
+
+    unsafe fn skip(&mut self) {
+        self.entities = NonNull::new_unchecked(self.entities.as_ptr().add(1));
+    }
+}
+
+impl<T: Component> Query for &T {
     type Fetch = FetchRead<T>;
 }
 
 #[doc(hidden)]
-pub struct FetchRead<T>(NonNull<T>);
+pub struct FetchRead<T>(Cursor<T>);
+
+impl<T: Component> Prepare for FetchRead<T> {
+    type State = Storage;
+
+    fn prepare(archetype: &Archetype) -> Option<Storage> {
+        archetype.get_storage::<T>()
+    }
+}
 
 impl<'a, T: Component> Fetch<'a> for FetchRead<T> {
     type Item = &'a T;
 
     fn access(archetype: &Archetype) -> Option<Access> {
-        if archetype.has::<T>() {
-            Some(Access::Read)
-        } else {
-            None
-        }
+        archetype.get_storage::<T>().map(|_| Access::Read)
     }
 
-    fn borrow(archetype: &Archetype) {
-        archetype.borrow::<T>();
+    fn borrow(archetype: &Archetype) -> Result<(), BorrowError> {
+        archetype.try_borrow::<T>()
     }
-    unsafe fn get(archetype: &'a Archetype, offset: usize) -> Option<Self> {
-        archetype
-            .get::<T>()
-            .map(|x| Self(NonNull::new_unchecked(x.as_ptr().add(offset))))
+    unsafe fn execute(
+        archetype: &'a Archetype,
+        _: &'a [EntityMeta],
+        state: Storage,
+        offset: usize,
+        _: Ticks,
+    ) -> Self {
+        Self(Cursor::new(archetype, state, offset))
     }
     fn release(archetype: &Archetype) {
         archetype.release::<T>();
     }
+    fn for_each_borrow(mut f: impl FnMut(ComponentId, bool)) {
+        f(ComponentId::of::<T>(), false);
+    }
+
+    fn filters(state: Storage) -> bool {
+        matches!(state, Storage::Sparse(_))
+    }
+
+    unsafe fn should_skip(&self) -> bool {
+        !self.0.present()
+    }
+
+    unsafe fn present(&self) -> bool {
+        self.0.present()
+    }
 
     unsafe fn next(&mut self) -> &'a T {
-        let x = self.0.as_ptr();
-        self.0 = NonNull::new_unchecked(x.add(1));
-        &*x
+        let (x, _, _) = self.0.current();
+        self.0.advance();
+        &*x.as_ptr()
+    }
+
+    unsafe fn skip(&mut self) {
+        self.0.advance();
     }
 }
 
-impl<'a, T: Component> Query for &'a mut T {
+impl<T: Component> Query for &mut T {
     type Fetch = FetchWrite<T>;
 }
 
 #[doc(hidden)]
-pub struct FetchWrite<T>(NonNull<T>);
+pub struct FetchWrite<T> {
+    cursor: Cursor<T>,
+    tick: u64,
+}
+
+impl<T: Component> Prepare for FetchWrite<T> {
+    type State = Storage;
+
+    fn prepare(archetype: &Archetype) -> Option<Storage> {
+        archetype.get_storage::<T>()
+    }
+}
 
 impl<'a, T: Component> Fetch<'a> for FetchWrite<T> {
     type Item = &'a mut T;
 
     fn access(archetype: &Archetype) -> Option<Access> {
-        if archetype.has::<T>() {
-            Some(Access::Write)
-        } else {
-            None
-        }
+        archetype.get_storage::<T>().map(|_| Access::Write)
     }
 
-    fn borrow(archetype: &Archetype) {
-        archetype.borrow_mut::<T>();
+    fn borrow(archetype: &Archetype) -> Result<(), BorrowError> {
+        archetype.try_borrow_mut::<T>()
     }
-    unsafe fn get(archetype: &'a Archetype, offset: usize) -> Option<Self> {
-        archetype
-            .get::<T>()
-            .map(|x| Self(NonNull::new_unchecked(x.as_ptr().add(offset))))
+    unsafe fn execute(
+        archetype: &'a Archetype,
+        _: &'a [EntityMeta],
+        state: Storage,
+        offset: usize,
+        ticks: Ticks,
+    ) -> Self {
+        Self {
+            cursor: Cursor::new(archetype, state, offset),
+            tick: ticks.current,
+        }
     }
     fn release(archetype: &Archetype) {
         archetype.release_mut::<T>();
     }
+    fn for_each_borrow(mut f: impl FnMut(ComponentId, bool)) {
+        f(ComponentId::of::<T>(), true);
+    }
+
+    fn filters(state: Storage) -> bool {
+        matches!(state, Storage::Sparse(_))
+    }
+
+    unsafe fn should_skip(&self) -> bool {
+        !self.cursor.present()
+    }
+
+    unsafe fn present(&self) -> bool {
+        self.cursor.present()
+    }
 
     unsafe fn next(&mut self) -> &'a mut T {
-        let x = self.0.as_ptr();
-        self.0 = NonNull::new_unchecked(x.add(1));
-        &mut *x
+        let (x, _, mutated) = self.cursor.current();
+        *mutated.as_ptr() = self.tick;
+        self.cursor.advance();
+        &mut *x.as_ptr()
+    }
+
+    unsafe fn skip(&mut self) {
+        self.cursor.advance();
     }
 }
 
@@ -145,6 +376,14 @@ impl<T: Query> Query for Option<T> {
 #[doc(hidden)]
 pub struct TryFetch<T>(Option<T>);
 
+impl<T: Prepare> Prepare for TryFetch<T> {
+    type State = Option<T::State>;
+
+    fn prepare(archetype: &Archetype) -> Option<Self::State> {
+        Some(T::prepare(archetype))
+    }
+}
+
 impl<'a, T: Fetch<'a>> Fetch<'a> for TryFetch<T> {
     type Item = Option<T::Item>;
 
@@ -152,18 +391,39 @@ impl<'a, T: Fetch<'a>> Fetch<'a> for TryFetch<T> {
         Some(T::access(archetype).unwrap_or(Access::Iterate))
     }
 
-    fn borrow(archetype: &Archetype) {
+    fn borrow(archetype: &Archetype) -> Result<(), BorrowError> {
         T::borrow(archetype)
     }
-    unsafe fn get(archetype: &'a Archetype, offset: usize) -> Option<Self> {
-        Some(Self(T::get(archetype, offset)))
+    unsafe fn execute(
+        archetype: &'a Archetype,
+        meta: &'a [EntityMeta],
+        state: Option<T::State>,
+        offset: usize,
+        ticks: Ticks,
+    ) -> Self {
+        Self(state.map(|state| T::execute(archetype, meta, state, offset, ticks)))
     }
     fn release(archetype: &Archetype) {
         T::release(archetype)
     }
+    fn for_each_borrow(f: impl FnMut(ComponentId, bool)) {
+        T::for_each_borrow(f);
+    }
 
     unsafe fn next(&mut self) -> Option<T::Item> {
-        Some(self.0.as_mut()?.next())
+        let fetch = self.0.as_mut()?;
+        if fetch.should_skip() {
+            fetch.skip();
+            None
+        } else {
+            Some(fetch.next())
+        }
+    }
+
+    unsafe fn skip(&mut self) {
+        if let Some(ref mut fetch) = self.0 {
+            fetch.skip();
+        }
     }
 }
 
@@ -191,7 +451,19 @@ impl<T: Component, Q: Query> Query for Without<T, Q> {
 }
 
 #[doc(hidden)]
-pub struct FetchWithout<T, F>(F, PhantomData<fn(T)>);
+pub struct FetchWithout<T, F>(F, Option<Cursor<T>>);
+
+impl<T: Component, F: Prepare> Prepare for FetchWithout<T, F> {
+    type State = (F::State, Option<Storage>);
+
+    fn prepare(archetype: &Archetype) -> Option<Self::State> {
+        let storage = archetype.get_storage::<T>();
+        if let Some(Storage::Column(_)) = storage {
+            return None;
+        }
+        Some((F::prepare(archetype)?, storage))
+    }
+}
 
 impl<'a, T: Component, F: Fetch<'a>> Fetch<'a> for FetchWithout<T, F> {
     type Item = F::Item;
@@ -204,22 +476,53 @@ impl<'a, T: Component, F: Fetch<'a>> Fetch<'a> for FetchWithout<T, F> {
         }
     }
 
-    fn borrow(archetype: &Archetype) {
+    fn borrow(archetype: &Archetype) -> Result<(), BorrowError> {
         F::borrow(archetype)
     }
-    unsafe fn get(archetype: &'a Archetype, offset: usize) -> Option<Self> {
-        if archetype.has::<T>() {
-            return None;
-        }
-        Some(Self(F::get(archetype, offset)?, PhantomData))
+    unsafe fn execute(
+        archetype: &'a Archetype,
+        meta: &'a [EntityMeta],
+        (state, storage): Self::State,
+        offset: usize,
+        ticks: Ticks,
+    ) -> Self {
+        Self(
+            F::execute(archetype, meta, state, offset, ticks),
+            storage.map(|x| Cursor::new(archetype, x, offset)),
+        )
     }
     fn release(archetype: &Archetype) {
         F::release(archetype)
     }
+    fn for_each_borrow(f: impl FnMut(ComponentId, bool)) {
+        F::for_each_borrow(f);
+    }
+
+    fn filters((state, storage): Self::State) -> bool {
+        storage.is_some() || F::filters(state)
+    }
+
+    unsafe fn should_skip(&self) -> bool {
+        matches!(self.1, Some(ref x) if x.present()) || self.0.should_skip()
+    }
+
+    unsafe fn present(&self) -> bool {
+        !matches!(self.1, Some(ref x) if x.present()) && self.0.present()
+    }
 
     unsafe fn next(&mut self) -> F::Item {
+        if let Some(ref mut x) = self.1 {
+            x.advance();
+        }
         self.0.next()
     }
+
+    unsafe fn skip(&mut self) {
+        if let Some(ref mut x) = self.1 {
+            x.advance();
+        }
+        self.0.skip()
+    }
 }
 
 /// Query transformer skipping entities that do not have a `T` component
@@ -248,52 +551,490 @@ impl<T: Component, Q: Query> Query for With<T, Q> {
 }
 
 #[doc(hidden)]
-pub struct FetchWith<T, F>(F, PhantomData<fn(T)>);
+pub struct FetchWith<T, F>(F, Cursor<T>);
+
+impl<T: Component, F: Prepare> Prepare for FetchWith<T, F> {
+    type State = (F::State, Storage);
+
+    fn prepare(archetype: &Archetype) -> Option<Self::State> {
+        let storage = archetype.get_storage::<T>()?;
+        Some((F::prepare(archetype)?, storage))
+    }
+}
 
 impl<'a, T: Component, F: Fetch<'a>> Fetch<'a> for FetchWith<T, F> {
     type Item = F::Item;
 
     fn access(archetype: &Archetype) -> Option<Access> {
-        if archetype.has::<T>() {
+        if archetype.get_storage::<T>().is_some() {
             F::access(archetype)
         } else {
             None
         }
     }
 
-    fn borrow(archetype: &Archetype) {
+    fn borrow(archetype: &Archetype) -> Result<(), BorrowError> {
         F::borrow(archetype)
     }
-    unsafe fn get(archetype: &'a Archetype, offset: usize) -> Option<Self> {
-        if !archetype.has::<T>() {
-            return None;
-        }
-        Some(Self(F::get(archetype, offset)?, PhantomData))
+    unsafe fn execute(
+        archetype: &'a Archetype,
+        meta: &'a [EntityMeta],
+        (state, storage): Self::State,
+        offset: usize,
+        ticks: Ticks,
+    ) -> Self {
+        Self(
+            F::execute(archetype, meta, state, offset, ticks),
+            Cursor::new(archetype, storage, offset),
+        )
     }
     fn release(archetype: &Archetype) {
         F::release(archetype)
     }
+    fn for_each_borrow(f: impl FnMut(ComponentId, bool)) {
+        F::for_each_borrow(f);
+    }
+
+    fn filters((state, storage): Self::State) -> bool {
+        matches!(storage, Storage::Sparse(_)) || F::filters(state)
+    }
+
+    unsafe fn should_skip(&self) -> bool {
+        !self.1.present() || self.0.should_skip()
+    }
+
+    unsafe fn present(&self) -> bool {
+        self.1.present() && self.0.present()
+    }
+
+    unsafe fn next(&mut self) -> F::Item {
+        self.1.advance();
+        self.0.next()
+    }
+
+    unsafe fn skip(&mut self) {
+        self.1.advance();
+        self.0.skip()
+    }
+}
+
+/// Query element borrowing a `T` component only if it was added since the query's change tick
+///
+/// Components are considered added when an entity is spawned with them or when they're passed to
+/// `World::insert`, including when replacing an existing component. See `QueryBorrow::since`.
+///
+/// # Example
+/// ```
+/// # use hecs::*;
+/// let mut world = World::new();
+/// let a = world.spawn((123, true));
+/// let tick = world.increment_change_tick();
+/// let b = world.spawn((456,));
+/// world.insert_one(a, false).unwrap();
+/// let added = world.query::<Added<i32>>()
+///     .since(tick)
+///     .iter()
+///     .map(|(e, &i)| (e, i))
+///     .collect::<Vec<_>>();
+/// assert_eq!(added, &[(b, 456)]);
+/// ```
+pub struct Added<T>(PhantomData<fn(T)>);
+
+impl<T: Component> Query for Added<T> {
+    type Fetch = FetchAdded<T>;
+}
+
+#[doc(hidden)]
+pub struct FetchAdded<T> {
+    cursor: Cursor<T>,
+    since: u64,
+}
+
+impl<T: Component> Prepare for FetchAdded<T> {
+    type State = Storage;
+
+    fn prepare(archetype: &Archetype) -> Option<Storage> {
+        archetype.get_storage::<T>()
+    }
+}
+
+impl<'a, T: Component> Fetch<'a> for FetchAdded<T> {
+    type Item = &'a T;
+
+    fn access(archetype: &Archetype) -> Option<Access> {
+        archetype.get_storage::<T>().map(|_| Access::Read)
+    }
+
+    fn borrow(archetype: &Archetype) -> Result<(), BorrowError> {
+        archetype.try_borrow::<T>()
+    }
+    unsafe fn execute(
+        archetype: &'a Archetype,
+        _: &'a [EntityMeta],
+        state: Storage,
+        offset: usize,
+        ticks: Ticks,
+    ) -> Self {
+        Self {
+            cursor: Cursor::new(archetype, state, offset),
+            since: ticks.since,
+        }
+    }
+    fn release(archetype: &Archetype) {
+        archetype.release::<T>();
+    }
+    fn for_each_borrow(mut f: impl FnMut(ComponentId, bool)) {
+        f(ComponentId::of::<T>(), false);
+    }
+
+    fn filters(_: Storage) -> bool {
+        true
+    }
+
+    unsafe fn should_skip(&self) -> bool {
+        match self.cursor.get() {
+            Some((_, added, _)) => *added.as_ptr() <= self.since,
+            None => true,
+        }
+    }
+
+    unsafe fn present(&self) -> bool {
+        self.cursor.present()
+    }
+
+    unsafe fn next(&mut self) -> &'a T {
+        let (x, _, _) = self.cursor.current();
+        self.cursor.advance();
+        &*x.as_ptr()
+    }
+
+    unsafe fn skip(&mut self) {
+        self.cursor.advance();
+    }
+}
+
+/// Query element borrowing a `T` component only if it was mutably accessed since the query's
+/// change tick
+///
+/// Adding a component doesn't count as mutating it. See `QueryBorrow::since`.
+///
+/// # Mutable access is mutation
+///
+/// Writes aren't observed directly. A component is considered mutated whenever it's accessed
+/// mutably, regardless of whether it's actually written: every entity yielded by a query
+/// containing `&mut T` has its `T` marked, as does every component a `RefMut` is mutably
+/// dereferenced for, and every component of a write term in a `DynamicQuery`. To keep `Mutated`
+/// and `Changed` precise, query with `&T` and use `World::get_mut` only for the entities that
+/// actually need to change.
+///
+/// # Example
+/// ```
+/// # use hecs::*;
+/// let mut world = World::new();
+/// let a = world.spawn((123,));
+/// let b = world.spawn((456,));
+/// let tick = world.increment_change_tick();
+/// *world.get_mut::<i32>(b).unwrap() += 1;
+/// let mutated = world.query::<Mutated<i32>>()
+///     .since(tick)
+///     .iter()
+///     .map(|(e, &i)| (e, i))
+///     .collect::<Vec<_>>();
+/// assert_eq!(mutated, &[(b, 457)]);
+/// ```
+pub struct Mutated<T>(PhantomData<fn(T)>);
+
+impl<T: Component> Query for Mutated<T> {
+    type Fetch = FetchMutated<T>;
+}
+
+#[doc(hidden)]
+pub struct FetchMutated<T> {
+    cursor: Cursor<T>,
+    since: u64,
+}
+
+impl<T: Component> Prepare for FetchMutated<T> {
+    type State = Storage;
+
+    fn prepare(archetype: &Archetype) -> Option<Storage> {
+        archetype.get_storage::<T>()
+    }
+}
+
+impl<'a, T: Component> Fetch<'a> for FetchMutated<T> {
+    type Item = &'a T;
+
+    fn access(archetype: &Archetype) -> Option<Access> {
+        archetype.get_storage::<T>().map(|_| Access::Read)
+    }
+
+    fn borrow(archetype: &Archetype) -> Result<(), BorrowError> {
+        archetype.try_borrow::<T>()
+    }
+    unsafe fn execute(
+        archetype: &'a Archetype,
+        _: &'a [EntityMeta],
+        state: Storage,
+        offset: usize,
+        ticks: Ticks,
+    ) -> Self {
+        Self {
+            cursor: Cursor::new(archetype, state, offset),
+            since: ticks.since,
+        }
+    }
+    fn release(archetype: &Archetype) {
+        archetype.release::<T>();
+    }
+    fn for_each_borrow(mut f: impl FnMut(ComponentId, bool)) {
+        f(ComponentId::of::<T>(), false);
+    }
+
+    fn filters(_: Storage) -> bool {
+        true
+    }
+
+    unsafe fn should_skip(&self) -> bool {
+        match self.cursor.get() {
+            Some((_, _, mutated)) => *mutated.as_ptr() <= self.since,
+            None => true,
+        }
+    }
+
+    unsafe fn present(&self) -> bool {
+        self.cursor.present()
+    }
+
+    unsafe fn next(&mut self) -> &'a T {
+        let (x, _, _) = self.cursor.current();
+        self.cursor.advance();
+        &*x.as_ptr()
+    }
+
+    unsafe fn skip(&mut self) {
+        self.cursor.advance();
+    }
+}
+
+/// Query element borrowing a `T` component only if it was added or mutably accessed since the
+/// query's change tick
+///
+/// Equivalent to the union of `Added<T>` and `Mutated<T>`, so any mutable access counts as a
+/// change, as described for `Mutated`.
+///
+/// # Example
+/// ```
+/// # use hecs::*;
+/// let mut world = World::new();
+/// let a = world.spawn((123,));
+/// let b = world.spawn((456,));
+/// let tick = world.increment_change_tick();
+/// *world.get_mut::<i32>(b).unwrap() += 1;
+/// let c = world.spawn((789,));
+/// let changed = world.query::<Changed<i32>>()
+///     .since(tick)
+///     .iter()
+///     .map(|(e, &i)| (e, i))
+///     .collect::<Vec<_>>();
+/// assert_eq!(changed.len(), 2);
+/// assert!(changed.contains(&(b, 457)));
+/// assert!(changed.contains(&(c, 789)));
+/// ```
+pub struct Changed<T>(PhantomData<fn(T)>);
+
+impl<T: Component> Query for Changed<T> {
+    type Fetch = FetchChanged<T>;
+}
+
+#[doc(hidden)]
+pub struct FetchChanged<T> {
+    cursor: Cursor<T>,
+    since: u64,
+}
+
+impl<T: Component> Prepare for FetchChanged<T> {
+    type State = Storage;
+
+    fn prepare(archetype: &Archetype) -> Option<Storage> {
+        archetype.get_storage::<T>()
+    }
+}
+
+impl<'a, T: Component> Fetch<'a> for FetchChanged<T> {
+    type Item = &'a T;
+
+    fn access(archetype: &Archetype) -> Option<Access> {
+        archetype.get_storage::<T>().map(|_| Access::Read)
+    }
+
+    fn borrow(archetype: &Archetype) -> Result<(), BorrowError> {
+        archetype.try_borrow::<T>()
+    }
+    unsafe fn execute(
+        archetype: &'a Archetype,
+        _: &'a [EntityMeta],
+        state: Storage,
+        offset: usize,
+        ticks: Ticks,
+    ) -> Self {
+        Self {
+            cursor: Cursor::new(archetype, state, offset),
+            since: ticks.since,
+        }
+    }
+    fn release(archetype: &Archetype) {
+        archetype.release::<T>();
+    }
+    fn for_each_borrow(mut f: impl FnMut(ComponentId, bool)) {
+        f(ComponentId::of::<T>(), false);
+    }
+
+    fn filters(_: Storage) -> bool {
+        true
+    }
+
+    unsafe fn should_skip(&self) -> bool {
+        match self.cursor.get() {
+            Some((_, added, mutated)) => {
+                *added.as_ptr() <= self.since && *mutated.as_ptr() <= self.since
+            }
+            None => true,
+        }
+    }
+
+    unsafe fn present(&self) -> bool {
+        self.cursor.present()
+    }
+
+    unsafe fn next(&mut self) -> &'a T {
+        let (x, _, _) = self.cursor.current();
+        self.cursor.advance();
+        &*x.as_ptr()
+    }
+
+    unsafe fn skip(&mut self) {
+        self.cursor.advance();
+    }
+}
+
+/// Query element yielding whether an entity satisfies `Q`, without borrowing anything
+///
+/// Only the components an entity has are considered, so per-entity filters like `Added` and
+/// `Mutated` within `Q` are ignored.
+///
+/// # Example
+/// ```
+/// # use hecs::*;
+/// let mut world = World::new();
+/// let a = world.spawn((123, true));
+/// let b = world.spawn((456,));
+/// let _flag = world.get_mut::<bool>(a).unwrap();
+/// let mut entities = world.query::<(&i32, Satisfies<&mut bool>)>()
+///     .iter()
+///     .map(|(e, (&i, flag))| (e, i, flag))
+///     .collect::<Vec<_>>();
+/// entities.sort_by_key(|&(_, i, _)| i);
+/// assert_eq!(entities, &[(a, 123, true), (b, 456, false)]);
+/// ```
+pub struct Satisfies<Q>(PhantomData<Q>);
+
+impl<Q: Query> Query for Satisfies<Q> {
+    type Fetch = FetchSatisfies<Q::Fetch>;
+}
+
+#[doc(hidden)]
+pub struct FetchSatisfies<F>(Option<F>);
+
+impl<F: Prepare> Prepare for FetchSatisfies<F> {
+    type State = Option<F::State>;
+
+    fn prepare(archetype: &Archetype) -> Option<Self::State> {
+        Some(F::prepare(archetype))
+    }
+}
+
+impl<'a, F: Fetch<'a>> Fetch<'a> for FetchSatisfies<F> {
+    type Item = bool;
+
+    fn access(_: &Archetype) -> Option<Access> {
+        Some(Access::Iterate)
+    }
+
+    fn borrow(_: &Archetype) -> Result<(), BorrowError> {
+        Ok(())
+    }
+    unsafe fn execute(
+        archetype: &'a Archetype,
+        meta: &'a [EntityMeta],
+        state: Self::State,
+        offset: usize,
+        ticks: Ticks,
+    ) -> Self {
+        Self(state.map(|state| F::execute(archetype, meta, state, offset, ticks)))
+    }
+    fn release(_: &Archetype) {}
+    fn for_each_borrow(_: impl FnMut(ComponentId, bool)) {}
+
+    unsafe fn next(&mut self) -> bool {
+        let fetch = match self.0 {
+            Some(ref mut fetch) => fetch,
+            None => return false,
+        };
+        let result = fetch.present();
+        fetch.skip();
+        result
+    }
 
-    unsafe fn next(&mut self) -> F::Item {
-        self.0.next()
+    unsafe fn skip(&mut self) {
+        if let Some(ref mut fetch) = self.0 {
+            fetch.skip();
+        }
     }
 }
 
+/// Query transformer matching entities that satisfy any of the queries in the tuple `Q`
+///
+/// Yields a tuple holding the result of each query, or `None` for those the entity doesn't
+/// satisfy. Components are only borrowed from archetypes that satisfy the query accessing them.
+///
+/// # Example
+/// ```
+/// # use hecs::*;
+/// let mut world = World::new();
+/// let a = world.spawn((123, true));
+/// let b = world.spawn((456, "abc"));
+/// let c = world.spawn((42,));
+/// let entities = world.query::<Or<(&bool, &&str)>>()
+///     .iter()
+///     .map(|(e, (flag, name))| (e, flag.copied(), name.copied()))
+///     .collect::<Vec<_>>();
+/// assert_eq!(entities.len(), 2);
+/// assert!(entities.contains(&(a, Some(true), None)));
+/// assert!(entities.contains(&(b, None, Some("abc"))));
+/// ```
+pub struct Or<Q>(PhantomData<Q>);
+
+#[doc(hidden)]
+pub struct FetchOr<T>(T);
+
 /// A borrow of a `World` sufficient to execute the query `Q`
 ///
 /// Note that borrows are not released until this object is dropped.
 pub struct QueryBorrow<'w, Q: Query> {
     meta: &'w [EntityMeta],
     archetypes: &'w [Archetype],
+    ticks: Ticks,
     borrowed: bool,
     _marker: PhantomData<Q>,
 }
 
 impl<'w, Q: Query> QueryBorrow<'w, Q> {
-    pub(crate) fn new(meta: &'w [EntityMeta], archetypes: &'w [Archetype]) -> Self {
+    pub(crate) fn new(meta: &'w [EntityMeta], archetypes: &'w [Archetype], ticks: Ticks) -> Self {
         Self {
             meta,
             archetypes,
+            ticks,
             borrowed: false,
             _marker: PhantomData,
         }
@@ -304,11 +1045,7 @@ impl<'w, Q: Query> QueryBorrow<'w, Q> {
     /// Must be called only once per query.
     pub fn iter<'q>(&'q mut self) -> QueryIter<'q, 'w, Q> {
         self.borrow();
-        QueryIter {
-            borrow: self,
-            archetype_index: 0,
-            iter: None,
-        }
+        QueryIter::new(self)
     }
 
     /// Like `iter`, but returns child iterators of at most `batch_size` elements
@@ -324,19 +1061,120 @@ impl<'w, Q: Query> QueryBorrow<'w, Q> {
         }
     }
 
+    /// Like `iter`, but distributes entities across rayon's thread pool
+    ///
+    /// Work is divided into batches of entities from the same archetype, sized so that every
+    /// thread receives several batches.
+    ///
+    /// Requires the `rayon` feature. Must be called only once per query.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// use rayon::iter::ParallelIterator;
+    /// let mut world = World::new();
+    /// world.spawn_batch((0..1_000).map(|i| (i, i as f32)));
+    /// let sum = world.query::<&i32>().par_iter().map(|(_, &i)| i).sum::<i32>();
+    /// assert_eq!(sum, 499_500);
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_iter<'q>(&'q mut self) -> ParIter<'q, 'w, Q> {
+        // Enough batches for work stealing to even out load, but not so many that per-batch
+        // overhead dominates
+        const MIN_BATCH_SIZE: u32 = 64;
+        let len = self
+            .archetypes
+            .iter()
+            .filter(|x| Q::Fetch::access(x).is_some())
+            .map(|x| x.len())
+            .sum::<u32>();
+        let batches = rayon::current_num_threads() as u32 * 4;
+        let batch_size = (len / batches).max(MIN_BATCH_SIZE);
+        ParIter {
+            batches: self.iter_batched(batch_size).collect(),
+        }
+    }
+
+    /// Call `f` on every entity matching the query, distributed across rayon's thread pool
+    ///
+    /// Shorthand for `par_iter().for_each(f)`. Requires the `rayon` feature. Must be called only
+    /// once per query.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// let a = world.spawn((1, 2.0f32));
+    /// world.query::<(&mut i32, &f32)>().par_for_each(|(_, (i, &f))| *i += f as i32);
+    /// assert_eq!(*world.get::<i32>(a).unwrap(), 3);
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_for_each<'q, F>(&'q mut self, f: F)
+    where
+        F: Fn((Entity, <Q::Fetch as Fetch<'q>>::Item)) + Send + Sync,
+        <Q::Fetch as Fetch<'q>>::Item: Send,
+    {
+        rayon::iter::ParallelIterator::for_each(self.par_iter(), f);
+    }
+
+    /// Like `iter`, but fails instead of panicking if a component is already borrowed in a
+    /// conflicting manner
+    ///
+    /// If the query can't be borrowed, no borrows are held afterwards and the query may be tried
+    /// again.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// let a = world.spawn((123, true));
+    /// let x = world.get_mut::<i32>(a).unwrap();
+    /// let mut query = world.query::<(&bool, &i32)>();
+    /// let err = query.try_iter().err().unwrap();
+    /// assert_eq!(err.conflict(), Access::Write);
+    /// drop(x);
+    /// assert_eq!(query.try_iter().unwrap().count(), 1);
+    /// ```
+    pub fn try_iter<'q>(&'q mut self) -> Result<QueryIter<'q, 'w, Q>, BorrowError> {
+        self.try_borrow()?;
+        Ok(QueryIter::new(self))
+    }
+
     fn borrow(&mut self) {
+        if let Err(e) = self.try_borrow() {
+            panic!("{}", e);
+        }
+    }
+
+    fn try_borrow(&mut self) -> Result<(), BorrowError> {
         if self.borrowed {
             panic!(
                 "called QueryBorrow::iter twice on the same borrow; construct a new query instead"
             );
         }
-        for x in self.archetypes {
-            // TODO: Release prior borrows on failure?
+        for (i, x) in self.archetypes.iter().enumerate() {
             if Q::Fetch::access(x) >= Some(Access::Read) {
-                Q::Fetch::borrow(x);
+                if let Err(e) = Q::Fetch::borrow(x) {
+                    for x in &self.archetypes[..i] {
+                        if Q::Fetch::access(x) >= Some(Access::Read) {
+                            Q::Fetch::release(x);
+                        }
+                    }
+                    return Err(e);
+                }
             }
         }
         self.borrowed = true;
+        Ok(())
+    }
+
+    /// Only consider changes made after `tick` in `Added`, `Mutated` and `Changed` query elements
+    ///
+    /// By default, only changes made since the most recent call to `World::increment_change_tick`
+    /// are considered.
+    pub fn since(mut self, tick: u64) -> Self {
+        self.ticks.since = tick;
+        self
     }
 
     /// Transform the query into one that requires a certain component without borrowing it
@@ -392,6 +1230,7 @@ impl<'w, Q: Query> QueryBorrow<'w, Q> {
         let x = QueryBorrow {
             meta: self.meta,
             archetypes: self.archetypes,
+            ticks: self.ticks,
             borrowed: self.borrowed,
             _marker: PhantomData,
         };
@@ -427,9 +1266,19 @@ impl<'q, 'w, Q: Query> IntoIterator for &'q mut QueryBorrow<'w, Q> {
 
 /// Iterator over the set of entities with the components in `Q`
 pub struct QueryIter<'q, 'w, Q: Query> {
-    borrow: &'q mut QueryBorrow<'w, Q>,
-    archetype_index: u32,
-    iter: Option<ChunkIter<Q>>,
+    // Held to keep the query's borrows alive
+    _borrow: &'q mut QueryBorrow<'w, Q>,
+    matches: Matches<'q, Q>,
+}
+
+impl<'q, 'w, Q: Query> QueryIter<'q, 'w, Q> {
+    fn new(borrow: &'q mut QueryBorrow<'w, Q>) -> Self {
+        let matches = Matches::new(borrow.meta, borrow.archetypes, borrow.ticks);
+        Self {
+            _borrow: borrow,
+            matches,
+        }
+    }
 }
 
 unsafe impl<'q, 'w, Q: Query> Send for QueryIter<'q, 'w, Q> {}
@@ -440,20 +1289,114 @@ impl<'q, 'w, Q: Query> Iterator for QueryIter<'q, 'w, Q> {
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
+        unsafe { self.matches.next() }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.matches.size_hint()
+    }
+}
+
+/// Iterator over the results of a query on a uniquely borrowed `World`
+///
+/// Obtained from `World::query_mut`. No dynamic borrow checking is performed, since the world
+/// can't be accessed by anything else while this exists.
+pub struct QueryMut<'q, Q: Query> {
+    matches: Matches<'q, Q>,
+}
+
+impl<'q, Q: Query> QueryMut<'q, Q> {
+    pub(crate) fn new(
+        meta: &'q [EntityMeta],
+        archetypes: &'q mut [Archetype],
+        ticks: Ticks,
+    ) -> Self {
+        assert_borrow::<Q>();
+        Self {
+            matches: Matches::new(meta, archetypes, ticks),
+        }
+    }
+}
+
+unsafe impl<'q, Q: Query> Send for QueryMut<'q, Q> {}
+unsafe impl<'q, Q: Query> Sync for QueryMut<'q, Q> {}
+
+impl<'q, Q: Query> Iterator for QueryMut<'q, Q> {
+    type Item = (Entity, <Q::Fetch as Fetch<'q>>::Item);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        unsafe { self.matches.next() }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.matches.size_hint()
+    }
+}
+
+/// Panics if `Q` borrows any component type uniquely while also accessing it elsewhere
+///
+/// Aliasing can't be ruled out at compile time, so this stands in for a static check. It's
+/// quadratic in the number of components `Q` accesses, so it's performed only once per query type.
+pub(crate) fn assert_borrow<Q: Query>() {
+    let key = TypeId::of::<Q::Fetch>();
+    if VALID_QUERIES.lock(|x| x.get_or_insert_with(HashSet::default).contains(&key)) {
+        return;
+    }
+    let mut i = 0;
+    Q::Fetch::for_each_borrow(|a, unique_a| {
+        let mut j = 0;
+        Q::Fetch::for_each_borrow(|b, unique_b| {
+            if i != j && a == b && (unique_a || unique_b) {
+                panic!("query violates a unique borrow");
+            }
+            j += 1;
+        });
+        i += 1;
+    });
+    VALID_QUERIES.lock(|x| x.get_or_insert_with(HashSet::default).insert(key));
+}
+
+/// Fetch types of the queries that passed `assert_borrow`
+static VALID_QUERIES: Lock<Option<HashSet<TypeId>>> = Lock::new(None);
+
+/// The entities of `archetypes` satisfying `Q`, shared by `QueryIter` and `QueryMut`
+struct Matches<'q, Q: Query> {
+    meta: &'q [EntityMeta],
+    archetypes: &'q [Archetype],
+    ticks: Ticks,
+    archetype_index: u32,
+    iter: Option<ChunkIter<Q>>,
+}
+
+impl<'q, Q: Query> Matches<'q, Q> {
+    fn new(meta: &'q [EntityMeta], archetypes: &'q [Archetype], ticks: Ticks) -> Self {
+        Self {
+            meta,
+            archetypes,
+            ticks,
+            archetype_index: 0,
+            iter: None,
+        }
+    }
+
+    /// # Safety
+    /// `Q` must be borrowed from `archetypes`, or the archetypes uniquely borrowed
+    #[inline]
+    unsafe fn next(&mut self) -> Option<(Entity, <Q::Fetch as Fetch<'q>>::Item)> {
         loop {
             match self.iter {
                 None => {
-                    let archetype = self.borrow.archetypes.get(self.archetype_index as usize)?;
+                    let archetype = self.archetypes.get(self.archetype_index as usize)?;
                     self.archetype_index += 1;
-                    unsafe {
-                        self.iter = Q::Fetch::get(archetype, 0).map(|fetch| ChunkIter {
+                    self.iter =
+                        Q::Fetch::get(archetype, self.meta, 0, self.ticks).map(|fetch| ChunkIter {
                             entities: archetype.entities(),
                             fetch,
                             len: archetype.len(),
                         });
-                    }
                 }
-                Some(ref mut iter) => match unsafe { iter.next() } {
+                Some(ref mut iter) => match iter.next() {
                     None => {
                         self.iter = None;
                         continue;
@@ -462,7 +1405,7 @@ impl<'q, 'w, Q: Query> Iterator for QueryIter<'q, 'w, Q> {
                         return Some((
                             Entity {
                                 id,
-                                generation: self.borrow.meta[id as usize].generation,
+                                generation: self.meta[id as usize].generation,
                             },
                             components,
                         ));
@@ -473,38 +1416,64 @@ impl<'q, 'w, Q: Query> Iterator for QueryIter<'q, 'w, Q> {
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        let n = self.len();
-        (n, Some(n))
+        let current = self.iter.as_ref().map(|iter| {
+            let archetype = &self.archetypes[self.archetype_index as usize - 1];
+            (archetype, iter.len as usize)
+        });
+        let rest = self.archetypes[self.archetype_index as usize..]
+            .iter()
+            .map(|x| (x, x.len() as usize));
+        current
+            .into_iter()
+            .chain(rest)
+            .filter_map(|(archetype, len)| Some(bounds::<Q>(Q::Fetch::prepare(archetype)?, len)))
+            .fold((0, Some(0)), add_bounds)
     }
 }
 
-impl<'q, 'w, Q: Query> ExactSizeIterator for QueryIter<'q, 'w, Q> {
-    fn len(&self) -> usize {
-        self.borrow
-            .archetypes
-            .iter()
-            .filter(|&x| Q::Fetch::access(x).is_some())
-            .map(|x| x.len() as usize)
-            .sum()
+/// Bounds on the number of entities satisfying `Q` among `len` rows of an archetype, given `state`
+/// prepared from it
+pub(crate) fn bounds<Q: Query>(
+    state: <Q::Fetch as Prepare>::State,
+    len: usize,
+) -> (usize, Option<usize>) {
+    if Q::Fetch::filters(state) {
+        (0, Some(len))
+    } else {
+        (len, Some(len))
     }
 }
 
-struct ChunkIter<Q: Query> {
-    entities: NonNull<u32>,
-    fetch: Q::Fetch,
-    len: u32,
+/// Sum of two `size_hint`s
+pub(crate) fn add_bounds(
+    x: (usize, Option<usize>),
+    y: (usize, Option<usize>),
+) -> (usize, Option<usize>) {
+    (x.0 + y.0, x.1.and_then(|a| Some(a + y.1?)))
+}
+
+pub(crate) struct ChunkIter<Q: Query> {
+    pub(crate) entities: NonNull<u32>,
+    pub(crate) fetch: Q::Fetch,
+    pub(crate) len: u32,
 }
 
 impl<Q: Query> ChunkIter<Q> {
     #[inline]
-    unsafe fn next<'a>(&mut self) -> Option<(u32, <Q::Fetch as Fetch<'a>>::Item)> {
-        if self.len == 0 {
-            return None;
+    pub(crate) unsafe fn next<'a>(&mut self) -> Option<(u32, <Q::Fetch as Fetch<'a>>::Item)> {
+        loop {
+            if self.len == 0 {
+                return None;
+            }
+            self.len -= 1;
+            let entity = self.entities.as_ptr();
+            self.entities = NonNull::new_unchecked(entity.add(1));
+            if self.fetch.should_skip() {
+                self.fetch.skip();
+                continue;
+            }
+            return Some((*entity, self.fetch.next()));
         }
-        self.len -= 1;
-        let entity = self.entities.as_ptr();
-        self.entities = NonNull::new_unchecked(entity.add(1));
-        Some((*entity, self.fetch.next()))
     }
 }
 
@@ -531,7 +1500,14 @@ impl<'q, 'w, Q: Query> Iterator for BatchedIter<'q, 'w, Q> {
                 self.batch = 0;
                 continue;
             }
-            if let Some(fetch) = unsafe { Q::Fetch::get(archetype, offset as usize) } {
+            if let Some(fetch) = unsafe {
+                Q::Fetch::get(
+                    archetype,
+                    self.borrow.meta,
+                    offset as usize,
+                    self.borrow.ticks,
+                )
+            } {
                 self.batch += 1;
                 return Some(Batch {
                     _marker: PhantomData,
@@ -583,8 +1559,142 @@ impl<'q, 'w, Q: Query> Iterator for Batch<'q, 'w, Q> {
 unsafe impl<'q, 'w, Q: Query> Send for Batch<'q, 'w, Q> {}
 unsafe impl<'q, 'w, Q: Query> Sync for Batch<'q, 'w, Q> {}
 
+/// Parallel version of `QueryIter`, obtained from `QueryBorrow::par_iter`
+///
+/// Requires the `rayon` feature.
+#[cfg(feature = "rayon")]
+pub struct ParIter<'q, 'w, Q: Query> {
+    batches: Vec<Batch<'q, 'w, Q>>,
+}
+
+#[cfg(feature = "rayon")]
+impl<'q, 'w, Q: Query> rayon::iter::ParallelIterator for ParIter<'q, 'w, Q>
+where
+    <Q::Fetch as Fetch<'q>>::Item: Send,
+{
+    type Item = (Entity, <Q::Fetch as Fetch<'q>>::Item);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: rayon::iter::plumbing::UnindexedConsumer<Self::Item>,
+    {
+        use rayon::iter::IntoParallelIterator;
+        self.batches
+            .into_par_iter()
+            .flat_map_iter(|batch| batch)
+            .drive_unindexed(consumer)
+    }
+}
+
+/// Position within the `T` components of an archetype's entities, wherever they're stored
+struct Cursor<T> {
+    row: usize,
+    storage: CursorStorage<T>,
+}
+
+enum CursorStorage<T> {
+    Column {
+        components: NonNull<T>,
+        added: NonNull<u64>,
+        mutated: NonNull<u64>,
+    },
+    Sparse {
+        set: NonNull<SparseSet>,
+        entities: NonNull<u32>,
+    },
+}
+
+impl<T: Component> Cursor<T> {
+    /// # Safety
+    /// `offset` must be in bounds of `archetype`, and `storage` must have come from
+    /// `get_storage::<T>` on the same archetype
+    unsafe fn new(archetype: &Archetype, storage: Storage, offset: usize) -> Self {
+        let storage = match storage {
+            Storage::Column(state) => {
+                let (added, mutated) = archetype.get_ticks_base(state);
+                CursorStorage::Column {
+                    components: archetype.get_base::<T>(state),
+                    added,
+                    mutated,
+                }
+            }
+            Storage::Sparse(index) => CursorStorage::Sparse {
+                set: NonNull::from(archetype.sparse_set(index)),
+                entities: archetype.entities(),
+            },
+        };
+        Self {
+            row: offset,
+            storage,
+        }
+    }
+
+    /// The current entity's component and the ticks at which it was added and last mutably
+    /// accessed, if it has one
+    ///
+    /// # Safety
+    /// Bounds-checking must be performed externally
+    #[inline]
+    unsafe fn get(&self) -> Option<(NonNull<T>, NonNull<u64>, NonNull<u64>)> {
+        match self.storage {
+            CursorStorage::Column {
+                components,
+                added,
+                mutated,
+            } => Some((
+                NonNull::new_unchecked(components.as_ptr().add(self.row)),
+                NonNull::new_unchecked(added.as_ptr().add(self.row)),
+                NonNull::new_unchecked(mutated.as_ptr().add(self.row)),
+            )),
+            CursorStorage::Sparse { set, entities } => {
+                let set = set.as_ref();
+                let index = set.index(*entities.as_ptr().add(self.row))?;
+                let (added, mutated) = set.ticks(index);
+                Some((set.component(index).cast::<T>(), added, mutated))
+            }
+        }
+    }
+
+    /// Like `get`, for an entity known to have a component
+    #[inline]
+    unsafe fn current(&self) -> (NonNull<T>, NonNull<u64>, NonNull<u64>) {
+        match self.get() {
+            Some(x) => x,
+            None => core::hint::unreachable_unchecked(),
+        }
+    }
+
+    /// Whether the current entity has a component, determined without accessing any components
+    ///
+    /// # Safety
+    /// Bounds-checking must be performed externally
+    #[inline]
+    unsafe fn present(&self) -> bool {
+        match self.storage {
+            CursorStorage::Column { .. } => true,
+            CursorStorage::Sparse { set, entities } => {
+                set.as_ref().contains(*entities.as_ptr().add(self.row))
+            }
+        }
+    }
+
+    #[inline]
+    fn advance(&mut self) {
+        self.row += 1;
+    }
+}
+
 macro_rules! tuple_impl {
     ($($name: ident),*) => {
+        impl<$($name: Prepare),*> Prepare for ($($name,)*) {
+            type State = ($($name::State,)*);
+
+            #[allow(unused_variables)]
+            fn prepare(archetype: &Archetype) -> Option<Self::State> {
+                Some(($($name::prepare(archetype)?,)*))
+            }
+        }
+
         impl<'a, $($name: Fetch<'a>),*> Fetch<'a> for ($($name,)*) {
             type Item = ($($name::Item,)*);
 
@@ -597,24 +1707,74 @@ macro_rules! tuple_impl {
                 Some(access)
             }
 
-            #[allow(unused_variables)]
-            fn borrow(archetype: &Archetype) {
-                $($name::borrow(archetype);)*
+            #[allow(unused_variables, unused_mut, unused_assignments)]
+            fn borrow(archetype: &Archetype) -> Result<(), BorrowError> {
+                let mut result = Ok(());
+                let mut borrowed = 0;
+                $(
+                    if result.is_ok() {
+                        result = $name::borrow(archetype);
+                        borrowed += result.is_ok() as usize;
+                    }
+                )*
+                if result.is_err() {
+                    // Roll back the borrows acquired before the failure
+                    let mut i = 0;
+                    $(
+                        if i < borrowed {
+                            $name::release(archetype);
+                        }
+                        i += 1;
+                    )*
+                }
+                result
             }
-            #[allow(unused_variables)]
-            unsafe fn get(archetype: &'a Archetype, offset: usize) -> Option<Self> {
-                Some(($($name::get(archetype, offset)?,)*))
+            #[allow(unused_variables, clippy::unused_unit)]
+            unsafe fn execute(archetype: &'a Archetype, meta: &'a [EntityMeta], state: Self::State, offset: usize, ticks: Ticks) -> Self {
+                #[allow(non_snake_case)]
+                let ($($name,)*) = state;
+                ($($name::execute(archetype, meta, $name, offset, ticks),)*)
             }
             #[allow(unused_variables)]
             fn release(archetype: &Archetype) {
                 $($name::release(archetype);)*
             }
+            #[allow(unused_variables, unused_mut)]
+            fn for_each_borrow(mut f: impl FnMut(ComponentId, bool)) {
+                $($name::for_each_borrow(&mut f);)*
+            }
+
+            #[allow(unused_variables)]
+            fn filters(state: Self::State) -> bool {
+                #[allow(non_snake_case)]
+                let ($($name,)*) = state;
+                false $(|| $name::filters($name))*
+            }
+
+            unsafe fn should_skip(&self) -> bool {
+                #[allow(non_snake_case)]
+                let ($($name,)*) = self;
+                false $(|| $name.should_skip())*
+            }
+
+            unsafe fn present(&self) -> bool {
+                #[allow(non_snake_case)]
+                let ($($name,)*) = self;
+                true $(&& $name.present())*
+            }
 
+            #[allow(clippy::unused_unit)]
             unsafe fn next(&mut self) -> Self::Item {
                 #[allow(non_snake_case)]
                 let ($($name,)*) = self;
                 ($($name.next(),)*)
             }
+
+            unsafe fn skip(&mut self) {
+                #[allow(non_snake_case)]
+                let ($($name,)*) = self;
+                $($name.skip();)*
+            }
         }
 
         impl<$($name: Query),*> Query for ($($name,)*) {
@@ -626,6 +1786,133 @@ macro_rules! tuple_impl {
 //smaller_tuples_too!(tuple_impl, B, A);
 smaller_tuples_too!(tuple_impl, O, N, M, L, K, J, I, H, G, F, E, D, C, B, A);
 
+macro_rules! or_impl {
+    ($($name: ident),*) => {
+        impl<$($name: Prepare),*> Prepare for FetchOr<($(Option<$name>,)*)> {
+            type State = ($(Option<$name::State>,)*);
+
+            #[allow(unused_variables)]
+            fn prepare(archetype: &Archetype) -> Option<Self::State> {
+                let state = ($($name::prepare(archetype),)*);
+                #[allow(non_snake_case)]
+                let ($(ref $name,)*) = state;
+                if false $(|| $name.is_some())* {
+                    Some(state)
+                } else {
+                    None
+                }
+            }
+        }
+
+        impl<'a, $($name: Fetch<'a>),*> Fetch<'a> for FetchOr<($(Option<$name>,)*)> {
+            type Item = ($(Option<$name::Item>,)*);
+
+            #[allow(unused_variables, unused_mut)]
+            fn access(archetype: &Archetype) -> Option<Access> {
+                let mut access = None;
+                $(
+                    access = access.max($name::access(archetype));
+                )*
+                access
+            }
+
+            #[allow(unused_variables, unused_mut, unused_assignments)]
+            fn borrow(archetype: &Archetype) -> Result<(), BorrowError> {
+                let mut result = Ok(());
+                let mut borrowed = 0;
+                $(
+                    if result.is_ok() {
+                        if $name::access(archetype).is_some() {
+                            result = $name::borrow(archetype);
+                        }
+                        borrowed += result.is_ok() as usize;
+                    }
+                )*
+                if result.is_err() {
+                    // Roll back the borrows acquired before the failure
+                    let mut i = 0;
+                    $(
+                        if i < borrowed && $name::access(archetype).is_some() {
+                            $name::release(archetype);
+                        }
+                        i += 1;
+                    )*
+                }
+                result
+            }
+            #[allow(unused_variables, clippy::unused_unit)]
+            unsafe fn execute(archetype: &'a Archetype, meta: &'a [EntityMeta], state: Self::State, offset: usize, ticks: Ticks) -> Self {
+                #[allow(non_snake_case)]
+                let ($($name,)*) = state;
+                FetchOr(($($name.map(|state| $name::execute(archetype, meta, state, offset, ticks)),)*))
+            }
+            #[allow(unused_variables)]
+            fn release(archetype: &Archetype) {
+                $(
+                    if $name::access(archetype).is_some() {
+                        $name::release(archetype);
+                    }
+                )*
+            }
+            #[allow(unused_variables, unused_mut)]
+            fn for_each_borrow(mut f: impl FnMut(ComponentId, bool)) {
+                $($name::for_each_borrow(&mut f);)*
+            }
+
+            #[allow(unused_variables)]
+            fn filters(state: Self::State) -> bool {
+                #[allow(non_snake_case)]
+                let ($($name,)*) = state;
+                true $(&& $name.map_or(true, $name::filters))*
+            }
+
+            unsafe fn should_skip(&self) -> bool {
+                #[allow(non_snake_case)]
+                let ($($name,)*) = &self.0;
+                true $(&& $name.as_ref().map_or(true, |fetch| fetch.should_skip()))*
+            }
+
+            unsafe fn present(&self) -> bool {
+                #[allow(non_snake_case)]
+                let ($($name,)*) = &self.0;
+                false $(|| $name.as_ref().map_or(false, |fetch| fetch.present()))*
+            }
+
+            #[allow(clippy::unused_unit)]
+            unsafe fn next(&mut self) -> Self::Item {
+                #[allow(non_snake_case)]
+                let ($($name,)*) = &mut self.0;
+                ($(
+                    $name.as_mut().and_then(|fetch| {
+                        if fetch.should_skip() {
+                            fetch.skip();
+                            None
+                        } else {
+                            Some(fetch.next())
+                        }
+                    }),
+                )*)
+            }
+
+            unsafe fn skip(&mut self) {
+                #[allow(non_snake_case)]
+                let ($($name,)*) = &mut self.0;
+                $(
+                    if let Some(fetch) = $name {
+                        fetch.skip();
+                    }
+                )*
+            }
+        }
+
+        impl<$($name: Query),*> Query for Or<($($name,)*)> {
+            type Fetch = FetchOr<($(Option<$name::Fetch>,)*)>;
+        }
+    };
+}
+
+smaller_tuples_too!(or_impl, O, N, M, L, K, J, I, H, G, F, E, D, C, B, A);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,12 +1,19 @@
+use core::fmt;
 use core::marker::PhantomData;
 
-use crate::query::{Fetch, With, Without};
-use crate::{Archetype, Component, Query};
+#[cfg(feature = "std")]
+use std::error::Error;
+
+use crate::entities::EntityMeta;
+use crate::query::{Fetch, Ticks, With, Without};
+use crate::{Archetype, Component, NoSuchEntity, Query};
 
 /// A borrow of a `World` sufficient to execute the query `Q` on a single entity
 pub struct QueryOne<'a, Q: Query> {
+    meta: &'a [EntityMeta],
     archetype: &'a Archetype,
     index: u32,
+    ticks: Ticks,
     borrowed: bool,
     _marker: PhantomData<Q>,
 }
@@ -16,11 +23,19 @@ impl<'a, Q: Query> QueryOne<'a, Q> {
     ///
     /// # Safety
     ///
-    /// `index` must be in-bounds for `archetype`
-    pub(crate) unsafe fn new(archetype: &'a Archetype, index: u32) -> Self {
+    /// `index` must be in-bounds for `archetype`, or `u32::MAX` for an entity that was reserved but
+    /// not yet flushed
+    pub(crate) unsafe fn new(
+        meta: &'a [EntityMeta],
+        archetype: &'a Archetype,
+        index: u32,
+        ticks: Ticks,
+    ) -> Self {
         Self {
+            meta,
             archetype,
             index,
+            ticks,
             borrowed: false,
             _marker: PhantomData,
         }
@@ -36,14 +51,32 @@ impl<'a, Q: Query> QueryOne<'a, Q> {
         if self.borrowed {
             panic!("called QueryOnce::get twice; construct a new query instead");
         }
+        if self.index >= self.archetype.len() {
+            // The entity is still pending, so it has no components and no place in `archetype`
+            return None;
+        }
         unsafe {
-            let mut fetch = Q::Fetch::get(self.archetype, self.index as usize)?;
+            let mut fetch =
+                Q::Fetch::get(self.archetype, self.meta, self.index as usize, self.ticks)?;
+            if let Err(e) = Q::Fetch::borrow(self.archetype) {
+                panic!("{}", e);
+            }
             self.borrowed = true;
-            Q::Fetch::borrow(self.archetype);
+            if fetch.should_skip() {
+                return None;
+            }
             Some(fetch.next())
         }
     }
 
+    /// Only consider changes made after `tick` in `Added`, `Mutated` and `Changed` query elements
+    ///
+    /// See `QueryBorrow::since` for details.
+    pub fn since(mut self, tick: u64) -> Self {
+        self.ticks.since = tick;
+        self
+    }
+
     /// Transform the query into one that requires a certain component without borrowing it
     ///
     /// See `QueryBorrow::with` for details.
@@ -61,8 +94,10 @@ impl<'a, Q: Query> QueryOne<'a, Q> {
     /// Helper to change the type of the query
     fn transform<R: Query>(mut self) -> QueryOne<'a, R> {
         let x = QueryOne {
+            meta: self.meta,
             archetype: self.archetype,
             index: self.index,
+            ticks: self.ticks,
             borrowed: self.borrowed,
             _marker: PhantomData,
         };
@@ -82,3 +117,31 @@ impl<Q: Query> Drop for QueryOne<'_, Q> {
 
 unsafe impl<Q: Query> Send for QueryOne<'_, Q> {}
 unsafe impl<Q: Query> Sync for QueryOne<'_, Q> {}
+
+/// Errors that arise when querying a single entity
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum QueryOneError {
+    /// The entity was already despawned
+    NoSuchEntity,
+    /// The entity exists but does not satisfy the query
+    Unsatisfied,
+}
+
+#[cfg(feature = "std")]
+impl Error for QueryOneError {}
+
+impl fmt::Display for QueryOneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use QueryOneError::*;
+        match *self {
+            NoSuchEntity => f.write_str("no such entity"),
+            Unsatisfied => f.write_str("unsatisfied"),
+        }
+    }
+}
+
+impl From<NoSuchEntity> for QueryOneError {
+    fn from(NoSuchEntity: NoSuchEntity) -> Self {
+        QueryOneError::NoSuchEntity
+    }
+}
@@ -0,0 +1,238 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+
+use hashbrown::HashMap;
+
+use crate::archetype::Archetype;
+use crate::entities::EntityMeta;
+use crate::query::{Fetch, FetchRead, Prepare, Ticks};
+use crate::{Access, BorrowError, Component, ComponentId, Entity, Query, World};
+
+/// Component relating an entity to a target entity, with data of type `R` describing the relation
+///
+/// An entity has at most one relation of each type, created by `World::relate`. The world indexes
+/// relations by target, so that `World::related_to` can efficiently find every entity related to a
+/// given target. Despawning the target removes the relation from every entity related to it.
+/// Inserting a relation, for example one removed from another entity, indexes it as `relate` would,
+/// unless its target was despawned, in which case it's dropped.
+///
+/// As a query element, `Relation<R>` yields the target of each entity's relation, while
+/// `&Relation<R>` and `&mut Relation<R>` also provide access to its data.
+///
+/// # Example
+/// ```
+/// # use hecs::*;
+/// struct Targets;
+/// let mut world = World::new();
+/// let a = world.spawn(());
+/// let b = world.spawn(());
+/// let c = world.spawn(());
+/// world.relate(a, c, Targets).unwrap();
+/// world.relate(b, c, Targets).unwrap();
+/// assert_eq!(world.related_to::<Targets>(c).count(), 2);
+/// for (e, target) in world.query::<Relation<Targets>>().iter() {
+///     assert_eq!(target, c);
+/// }
+/// world.despawn(c).unwrap();
+/// assert!(world.get::<Relation<Targets>>(a).is_err());
+/// ```
+pub struct Relation<R> {
+    target: Entity,
+    data: R,
+}
+
+impl<R> Relation<R> {
+    pub(crate) fn new(target: Entity, data: R) -> Self {
+        Self { target, data }
+    }
+
+    /// The entity this relation refers to
+    pub fn target(&self) -> Entity {
+        self.target
+    }
+
+    /// Take the data describing the relation
+    pub fn into_inner(self) -> R {
+        self.data
+    }
+}
+
+impl<R: Clone> Clone for Relation<R> {
+    fn clone(&self) -> Self {
+        Self {
+            target: self.target,
+            data: self.data.clone(),
+        }
+    }
+}
+
+impl<R> Deref for Relation<R> {
+    type Target = R;
+    fn deref(&self) -> &R {
+        &self.data
+    }
+}
+
+impl<R> DerefMut for Relation<R> {
+    fn deref_mut(&mut self) -> &mut R {
+        &mut self.data
+    }
+}
+
+impl<R: Component> Query for Relation<R> {
+    type Fetch = FetchRelation<R>;
+}
+
+#[doc(hidden)]
+pub struct FetchRelation<R>(FetchRead<Relation<R>>);
+
+impl<R: Component> Prepare for FetchRelation<R> {
+    type State = <FetchRead<Relation<R>> as Prepare>::State;
+
+    fn prepare(archetype: &Archetype) -> Option<Self::State> {
+        FetchRead::<Relation<R>>::prepare(archetype)
+    }
+}
+
+impl<'a, R: Component> Fetch<'a> for FetchRelation<R> {
+    type Item = Entity;
+
+    fn access(archetype: &Archetype) -> Option<Access> {
+        FetchRead::<Relation<R>>::access(archetype)
+    }
+
+    fn borrow(archetype: &Archetype) -> Result<(), BorrowError> {
+        FetchRead::<Relation<R>>::borrow(archetype)
+    }
+    unsafe fn execute(
+        archetype: &'a Archetype,
+        meta: &'a [EntityMeta],
+        state: Self::State,
+        offset: usize,
+        ticks: Ticks,
+    ) -> Self {
+        Self(FetchRead::execute(archetype, meta, state, offset, ticks))
+    }
+    fn release(archetype: &Archetype) {
+        FetchRead::<Relation<R>>::release(archetype)
+    }
+    fn for_each_borrow(f: impl FnMut(ComponentId, bool)) {
+        FetchRead::<Relation<R>>::for_each_borrow(f)
+    }
+
+    fn filters(state: Self::State) -> bool {
+        FetchRead::<Relation<R>>::filters(state)
+    }
+
+    unsafe fn should_skip(&self) -> bool {
+        self.0.should_skip()
+    }
+
+    unsafe fn present(&self) -> bool {
+        self.0.present()
+    }
+
+    unsafe fn next(&mut self) -> Entity {
+        self.0.next().target
+    }
+
+    unsafe fn skip(&mut self) {
+        self.0.skip();
+    }
+}
+
+/// Index of every relation of one type
+#[derive(Clone)]
+pub(crate) struct RelationIndex {
+    /// Target of each related entity
+    targets: HashMap<Entity, Entity>,
+    /// Entities related to each target, in the order they were related
+    sources: HashMap<Entity, Vec<Entity>>,
+    /// Removes the relation from an entity without updating the index
+    remove: fn(&mut World, Entity),
+    /// Reads the target of an entity's relation, if it has one
+    target: fn(&World, Entity) -> Option<Entity>,
+}
+
+impl RelationIndex {
+    pub(crate) fn new<R: Component>() -> Self {
+        fn remove<R: Component>(world: &mut World, source: Entity) {
+            let removed = [ComponentId::of::<Relation<R>>()];
+            let _ = world.remove_unlinked::<(Relation<R>,)>(source, &removed);
+        }
+
+        fn target<R: Component>(world: &World, source: Entity) -> Option<Entity> {
+            world.get::<Relation<R>>(source).ok().map(|x| x.target)
+        }
+
+        Self {
+            targets: HashMap::new(),
+            sources: HashMap::new(),
+            remove: remove::<R>,
+            target: target::<R>,
+        }
+    }
+
+    /// The target of the relation stored in `source`, whether or not it's indexed
+    pub(crate) fn target_of(&self, world: &World, source: Entity) -> Option<Entity> {
+        (self.target)(world, source)
+    }
+
+    /// The function that removes the relation from an entity without updating the index
+    pub(crate) fn remover(&self) -> fn(&mut World, Entity) {
+        self.remove
+    }
+
+    pub(crate) fn insert(&mut self, source: Entity, target: Entity) {
+        self.unlink(source);
+        self.targets.insert(source, target);
+        self.sources.entry(target).or_default().push(source);
+    }
+
+    /// Forget the relation of `source`, if any
+    pub(crate) fn unlink(&mut self, source: Entity) {
+        if let Some(target) = self.targets.remove(&source) {
+            let sources = self.sources.get_mut(&target).unwrap();
+            sources.retain(|&x| x != source);
+            if sources.is_empty() {
+                self.sources.remove(&target);
+            }
+        }
+    }
+
+    /// Forget every relation targeting `target`, returning the function that removes each
+    /// relation's component and the entities it must be removed from
+    pub(crate) fn unlink_target(
+        &mut self,
+        target: Entity,
+    ) -> (fn(&mut World, Entity), Vec<Entity>) {
+        let sources = self.sources.remove(&target).unwrap_or_default();
+        for source in &sources {
+            self.targets.remove(source);
+        }
+        (self.remove, sources)
+    }
+
+    pub(crate) fn sources(&self, target: Entity) -> &[Entity] {
+        self.sources.get(&target).map_or(&[], |x| &x[..])
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.targets.clear();
+        self.sources.clear();
+    }
+}
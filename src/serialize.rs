@@ -0,0 +1,521 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Serialization of entire `World`s via serde
+//!
+//! Component types are identified in serialized data by stable names assigned in a `Registry`.
+//! Worlds are written archetype by archetype, with each component type stored as a contiguous
+//! column, alongside the generation of every entity ID so that `Entity` handles stored elsewhere,
+//! including inside components, remain valid after a round trip.
+//!
+//! Requires the `serde` feature.
+
+use crate::alloc::boxed::Box;
+use crate::alloc::string::String;
+use crate::alloc::{vec, vec::Vec};
+use core::fmt;
+use core::marker::PhantomData;
+use core::{mem, slice};
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, SeqAccess, Visitor};
+use serde::ser::{self, SerializeSeq, SerializeTuple};
+use serde::{Deserializer, Serialize, Serializer};
+
+use crate::entities::Location;
+use crate::{Archetype, Component, ComponentId, TypeInfo, World};
+
+/// Associates component types with the names they're serialized under
+///
+/// Built by chaining calls to `register`. Every component type present in a world must be
+/// registered to serialize it.
+///
+/// # Example
+/// ```
+/// # use hecs::{*, serialize::Registry};
+/// let registry = Registry::new()
+///     .register::<i32>("number")
+///     .register::<String>("name");
+/// let mut world = World::new();
+/// let a = world.spawn((42, "a".to_string()));
+/// let b = world.spawn((7,));
+/// world.despawn(b).unwrap();
+///
+/// let json = serde_json::to_string(&registry.serialize(&world)).unwrap();
+/// let world = registry
+///     .deserialize(&mut serde_json::Deserializer::from_str(&json))
+///     .unwrap();
+/// assert_eq!(*world.get::<i32>(a).unwrap(), 42);
+/// assert_eq!(*world.get::<String>(a).unwrap(), "a");
+/// assert!(!world.contains(b));
+/// ```
+pub struct Registry<E = ()> {
+    entries: E,
+}
+
+impl Registry {
+    /// Create a registry with no component types
+    pub fn new() -> Self {
+        Self { entries: () }
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: Entries> Registry<E> {
+    /// Serialize components of type `T` under `name`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` or `name` is already registered.
+    pub fn register<T>(self, name: &'static str) -> Registry<(Entry<T>, E)>
+    where
+        T: Component + Serialize + DeserializeOwned,
+    {
+        assert!(
+            self.entries.name(ComponentId::of::<T>()).is_none(),
+            "component type registered twice"
+        );
+        assert!(
+            self.entries.info(name).is_none(),
+            "component name {:?} registered twice",
+            name
+        );
+        Registry {
+            entries: (
+                Entry {
+                    name,
+                    marker: PhantomData,
+                },
+                self.entries,
+            ),
+        }
+    }
+
+    /// Prepare `world` for serialization
+    ///
+    /// Serializing the result fails if `world` contains any unregistered component type, and
+    /// panics if a component is uniquely borrowed. Entities reserved since the last
+    /// `World::flush` are not included.
+    pub fn serialize<'a>(&'a self, world: &'a World) -> SerializeWorld<'a, E> {
+        SerializeWorld {
+            entries: &self.entries,
+            world,
+        }
+    }
+
+    /// Reconstruct a world previously serialized with a registry using the same names
+    ///
+    /// Change ticks aren't preserved; every component is considered to have been added at the new
+    /// world's initial change tick.
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        &self,
+        deserializer: D,
+    ) -> Result<World, D::Error> {
+        deserializer.deserialize_tuple(2, WorldVisitor(&self.entries))
+    }
+}
+
+/// A `World` that can be serialized, obtained from `Registry::serialize`
+pub struct SerializeWorld<'a, E> {
+    entries: &'a E,
+    world: &'a World,
+}
+
+impl<E: Entries> Serialize for SerializeWorld<'_, E> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(2)?;
+        tuple.serialize_element(&Generations(self.world))?;
+        tuple.serialize_element(&Archetypes {
+            entries: self.entries,
+            world: self.world,
+        })?;
+        tuple.end()
+    }
+}
+
+struct Generations<'a>(&'a World);
+
+impl Serialize for Generations<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.generations())
+    }
+}
+
+struct Archetypes<'a, E> {
+    entries: &'a E,
+    world: &'a World,
+}
+
+impl<E: Entries> Serialize for Archetypes<'_, E> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let archetypes = || self.world.archetypes().filter(|x| !x.is_empty());
+        let mut seq = serializer.serialize_seq(Some(archetypes().count()))?;
+        for archetype in archetypes() {
+            seq.serialize_element(&SerializeArchetype {
+                entries: self.entries,
+                archetype,
+            })?;
+        }
+        seq.end()
+    }
+}
+
+struct SerializeArchetype<'a, E> {
+    entries: &'a E,
+    archetype: &'a Archetype,
+}
+
+impl<E: Entries> Serialize for SerializeArchetype<'_, E> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use ser::Error;
+
+        if self.archetype.has_sparse_components() {
+            return Err(S::Error::custom(
+                "sparsely stored components can't be serialized",
+            ));
+        }
+        let names = self
+            .archetype
+            .types()
+            .iter()
+            .map(|ty| self.entries.name(ty.id()))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| S::Error::custom("unregistered component type"))?;
+        let mut tuple = serializer.serialize_tuple(3)?;
+        tuple.serialize_element(&names)?;
+        tuple.serialize_element(self.archetype.ids())?;
+        tuple.serialize_element(&Columns {
+            entries: self.entries,
+            archetype: self.archetype,
+        })?;
+        tuple.end()
+    }
+}
+
+struct Columns<'a, E> {
+    entries: &'a E,
+    archetype: &'a Archetype,
+}
+
+impl<E: Entries> Serialize for Columns<'_, E> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(self.archetype.types().len())?;
+        for ty in self.archetype.types() {
+            self.entries
+                .serialize_column(ty.id(), self.archetype, &mut tuple)?;
+        }
+        tuple.end()
+    }
+}
+
+struct WorldVisitor<'a, E>(&'a E);
+
+impl<'de, E: Entries> Visitor<'de> for WorldVisitor<'_, E> {
+    type Value = World;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a serialized world")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<World, A::Error> {
+        let generations: Vec<u32> = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let mut world = World::new();
+        let mut locations = vec![None; generations.len()];
+        seq.next_element_seed(ArchetypesSeed {
+            entries: self.0,
+            world: &mut world,
+            locations: &mut locations,
+        })?
+        .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        world.restore_entities(&generations, &locations);
+        Ok(world)
+    }
+}
+
+struct ArchetypesSeed<'a, E> {
+    entries: &'a E,
+    world: &'a mut World,
+    locations: &'a mut [Option<Location>],
+}
+
+impl<'de, E: Entries> DeserializeSeed<'de> for ArchetypesSeed<'_, E> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, E: Entries> Visitor<'de> for ArchetypesSeed<'_, E> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a sequence of archetypes")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(archetype) = seq.next_element_seed(ArchetypeSeed(self.entries))? {
+            let tick = self.world.change_tick();
+            let (target, base) = self
+                .world
+                .allocate_restored(archetype.types, &archetype.ids, self.locations)
+                .ok_or_else(|| de::Error::custom("invalid or duplicate entity ID"))?;
+            for column in archetype.columns {
+                unsafe {
+                    column.move_into(target, base, tick);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The contents of an archetype, fully deserialized
+struct ArchetypeData {
+    types: Vec<TypeInfo>,
+    ids: Vec<u32>,
+    columns: Vec<Box<dyn Column>>,
+}
+
+struct ArchetypeSeed<'a, E>(&'a E);
+
+impl<'de, E: Entries> DeserializeSeed<'de> for ArchetypeSeed<'_, E> {
+    type Value = ArchetypeData;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<ArchetypeData, D::Error> {
+        deserializer.deserialize_tuple(3, self)
+    }
+}
+
+impl<'de, E: Entries> Visitor<'de> for ArchetypeSeed<'_, E> {
+    type Value = ArchetypeData;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("an archetype")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ArchetypeData, A::Error> {
+        let names: Vec<String> = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let mut types = names
+            .iter()
+            .map(|name| {
+                self.0.info(name).ok_or_else(|| {
+                    de::Error::custom(format_args!("unknown component type {:?}", name))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let ids: Vec<u32> = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        let columns = seq
+            .next_element_seed(ColumnsSeed {
+                entries: self.0,
+                types: &types,
+                len: ids.len(),
+            })?
+            .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+        types.sort_unstable();
+        if types.windows(2).any(|x| x[0] == x[1]) {
+            return Err(de::Error::custom("duplicate component type"));
+        }
+        Ok(ArchetypeData {
+            types,
+            ids,
+            columns,
+        })
+    }
+}
+
+struct ColumnsSeed<'a, E> {
+    entries: &'a E,
+    types: &'a [TypeInfo],
+    len: usize,
+}
+
+impl<'de, E: Entries> DeserializeSeed<'de> for ColumnsSeed<'_, E> {
+    type Value = Vec<Box<dyn Column>>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_tuple(self.types.len(), self)
+    }
+}
+
+impl<'de, E: Entries> Visitor<'de> for ColumnsSeed<'_, E> {
+    type Value = Vec<Box<dyn Column>>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} component columns", self.types.len())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        self.types
+            .iter()
+            .map(|ty| self.entries.deserialize_column(ty.id(), self.len, &mut seq))
+            .collect()
+    }
+}
+
+/// A registered component type, see `Registry::register`
+#[doc(hidden)]
+pub struct Entry<T> {
+    name: &'static str,
+    marker: PhantomData<fn(T)>,
+}
+
+/// Type-level list of registered component types
+///
+/// Implemented for `()` and `(Entry<T>, E)` where `E: Entries`, so that serialization can remain
+/// generic over the serde data format.
+#[doc(hidden)]
+pub trait Entries {
+    fn name(&self, id: ComponentId) -> Option<&'static str>;
+    fn info(&self, name: &str) -> Option<TypeInfo>;
+    fn serialize_column<S: SerializeTuple>(
+        &self,
+        id: ComponentId,
+        archetype: &Archetype,
+        out: &mut S,
+    ) -> Result<(), S::Error>;
+    fn deserialize_column<'de, A: SeqAccess<'de>>(
+        &self,
+        id: ComponentId,
+        len: usize,
+        seq: &mut A,
+    ) -> Result<Box<dyn Column>, A::Error>;
+}
+
+impl Entries for () {
+    fn name(&self, _: ComponentId) -> Option<&'static str> {
+        None
+    }
+
+    fn info(&self, _: &str) -> Option<TypeInfo> {
+        None
+    }
+
+    fn serialize_column<S: SerializeTuple>(
+        &self,
+        _: ComponentId,
+        _: &Archetype,
+        _: &mut S,
+    ) -> Result<(), S::Error> {
+        Err(ser::Error::custom("unregistered component type"))
+    }
+
+    fn deserialize_column<'de, A: SeqAccess<'de>>(
+        &self,
+        _: ComponentId,
+        _: usize,
+        _: &mut A,
+    ) -> Result<Box<dyn Column>, A::Error> {
+        Err(de::Error::custom("unregistered component type"))
+    }
+}
+
+impl<T, E> Entries for (Entry<T>, E)
+where
+    T: Component + Serialize + DeserializeOwned,
+    E: Entries,
+{
+    fn name(&self, id: ComponentId) -> Option<&'static str> {
+        if id == ComponentId::of::<T>() {
+            Some(self.0.name)
+        } else {
+            self.1.name(id)
+        }
+    }
+
+    fn info(&self, name: &str) -> Option<TypeInfo> {
+        if name == self.0.name {
+            Some(TypeInfo::of::<T>())
+        } else {
+            self.1.info(name)
+        }
+    }
+
+    fn serialize_column<S: SerializeTuple>(
+        &self,
+        id: ComponentId,
+        archetype: &Archetype,
+        out: &mut S,
+    ) -> Result<(), S::Error> {
+        if id != ComponentId::of::<T>() {
+            return self.1.serialize_column(id, archetype, out);
+        }
+        archetype.borrow::<T>();
+        let column = unsafe {
+            slice::from_raw_parts(
+                archetype.get_ptr::<T>().unwrap().as_ptr(),
+                archetype.len() as usize,
+            )
+        };
+        let result = out.serialize_element(column);
+        archetype.release::<T>();
+        result
+    }
+
+    fn deserialize_column<'de, A: SeqAccess<'de>>(
+        &self,
+        id: ComponentId,
+        len: usize,
+        seq: &mut A,
+    ) -> Result<Box<dyn Column>, A::Error> {
+        if id != ComponentId::of::<T>() {
+            return self.1.deserialize_column(id, len, seq);
+        }
+        let column: Vec<T> = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::custom("missing component column"))?;
+        if column.len() != len {
+            return Err(de::Error::invalid_length(
+                column.len(),
+                &"one component per entity",
+            ));
+        }
+        Ok(Box::new(column))
+    }
+}
+
+/// Deserialized components of a single type, awaiting insertion into an archetype
+#[doc(hidden)]
+pub trait Column {
+    /// Move every component into consecutive rows of `archetype` starting at `base`
+    ///
+    /// The rows must have been allocated but not yet written.
+    unsafe fn move_into(self: Box<Self>, archetype: &mut Archetype, base: u32, tick: u64);
+}
+
+impl<T: Component> Column for Vec<T> {
+    unsafe fn move_into(self: Box<Self>, archetype: &mut Archetype, base: u32, tick: u64) {
+        for (i, mut component) in self.into_iter().enumerate() {
+            archetype.put_dynamic(
+                (&mut component as *mut T).cast::<u8>(),
+                ComponentId::of::<T>(),
+                mem::size_of::<T>(),
+                base + i as u32,
+                tick,
+                0,
+            );
+            mem::forget(component);
+        }
+    }
+}
@@ -0,0 +1,348 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compact binary snapshots of `World`s whose components are plain old data
+//!
+//! Each archetype's columns are written out as raw bytes and loaded back with a single copy per
+//! column, making snapshots much faster than per-entity encoding for large worlds. The entity
+//! table is included, so `Entity` handles remain valid after a round trip.
+//!
+//! Snapshots begin with a versioned header and a checksum of their contents, so truncated or
+//! corrupted data is reported as an error. Component bytes are stored in native byte order, and
+//! snapshots written on a platform with a different byte order are rejected.
+
+use crate::alloc::string::{String, ToString};
+use crate::alloc::{vec, vec::Vec};
+use core::convert::TryInto;
+use core::{fmt, str};
+
+#[cfg(feature = "std")]
+use std::error::Error;
+
+use hashbrown::HashMap;
+
+use crate::{Archetype, Component, ComponentId, TypeInfo, World};
+
+/// Types that can be safely copied to and from arbitrary bytes
+///
+/// # Safety
+///
+/// Implementers must contain no padding bytes, pointers or references, and every bit pattern of
+/// their size must be a valid value.
+pub unsafe trait Pod: Component + Copy {}
+
+macro_rules! impl_pod {
+    ($($ty:ty),*) => {
+        $(unsafe impl Pod for $ty {})*
+    };
+}
+
+impl_pod!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+const MAGIC: &[u8; 8] = b"HECSSNAP";
+const VERSION: u32 = 1;
+const BYTE_ORDER: u8 = if cfg!(target_endian = "little") { 1 } else { 2 };
+const HEADER_LEN: usize = 8 + 4 + 1 + 8 + 8;
+
+/// Associates plain-old-data component types with the names they're stored under in snapshots
+///
+/// # Example
+/// ```
+/// # use hecs::{*, snapshot::Registry};
+/// let registry = Registry::new()
+///     .register::<u32>("id")
+///     .register::<[f32; 2]>("position");
+/// let mut world = World::new();
+/// let a = world.spawn((7u32, [1.0f32, 2.0]));
+/// let data = registry.save(&world).unwrap();
+/// let world = registry.load(&data).unwrap();
+/// assert_eq!(*world.get::<[f32; 2]>(a).unwrap(), [1.0, 2.0]);
+/// ```
+#[derive(Default)]
+pub struct Registry {
+    types: HashMap<ComponentId, Entry>,
+    names: HashMap<&'static str, ComponentId>,
+}
+
+impl Registry {
+    /// Create a registry with no component types
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store components of type `T` under `name`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` or `name` is already registered.
+    pub fn register<T: Pod>(mut self, name: &'static str) -> Self {
+        fn borrow<T: Component>(archetype: &Archetype) {
+            archetype.borrow::<T>();
+        }
+        fn release<T: Component>(archetype: &Archetype) {
+            archetype.release::<T>();
+        }
+
+        assert!(
+            self.names.insert(name, ComponentId::of::<T>()).is_none(),
+            "component name {:?} registered twice",
+            name
+        );
+        let old = self.types.insert(
+            ComponentId::of::<T>(),
+            Entry {
+                name,
+                info: TypeInfo::of::<T>(),
+                borrow: borrow::<T>,
+                release: release::<T>,
+            },
+        );
+        assert!(old.is_none(), "component type registered twice");
+        self
+    }
+
+    /// Write every entity in `world` to a new snapshot
+    ///
+    /// Fails if `world` contains any unregistered or sparsely stored component type. Panics if a component is
+    /// uniquely borrowed. Entities reserved since the last `World::flush` are not included.
+    pub fn save(&self, world: &World) -> Result<Vec<u8>, SnapshotError> {
+        let mut out = vec![0; HEADER_LEN];
+        let generations = world.generations();
+        write_u32(&mut out, generations.len() as u32);
+        for generation in generations {
+            write_u32(&mut out, generation);
+        }
+
+        let archetypes = world
+            .archetypes()
+            .filter(|x| !x.is_empty())
+            .collect::<Vec<_>>();
+        write_u32(&mut out, archetypes.len() as u32);
+        for archetype in archetypes {
+            if archetype.has_sparse_components() {
+                return Err(SnapshotError::SparseComponent);
+            }
+            let entries = archetype
+                .types()
+                .iter()
+                .map(|ty| self.types.get(&ty.id()))
+                .collect::<Option<Vec<_>>>()
+                .ok_or(SnapshotError::UnregisteredComponent)?;
+            write_u32(&mut out, entries.len() as u32);
+            for entry in &entries {
+                write_u32(&mut out, entry.name.len() as u32);
+                out.extend_from_slice(entry.name.as_bytes());
+                write_u32(&mut out, entry.info.layout().size() as u32);
+            }
+            write_u32(&mut out, archetype.len());
+            for &id in archetype.ids() {
+                write_u32(&mut out, id);
+            }
+            for entry in &entries {
+                (entry.borrow)(archetype);
+                out.extend_from_slice(unsafe {
+                    archetype
+                        .column_bytes(entry.info.id(), entry.info.layout().size())
+                        .unwrap()
+                });
+                (entry.release)(archetype);
+            }
+        }
+
+        let payload_len = (out.len() - HEADER_LEN) as u64;
+        let checksum = checksum(&out[HEADER_LEN..]);
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(MAGIC);
+        write_u32(&mut header, VERSION);
+        header.push(BYTE_ORDER);
+        header.extend_from_slice(&payload_len.to_le_bytes());
+        header.extend_from_slice(&checksum.to_le_bytes());
+        out[..HEADER_LEN].copy_from_slice(&header);
+        Ok(out)
+    }
+
+    /// Reconstruct a world from a snapshot written by a registry using the same names
+    ///
+    /// Change ticks aren't preserved; every component is considered to have been added at the new
+    /// world's initial change tick.
+    pub fn load(&self, data: &[u8]) -> Result<World, SnapshotError> {
+        let mut header = Reader(data);
+        let header_err = |_| SnapshotError::InvalidHeader;
+        if header.bytes(MAGIC.len()).map_err(header_err)? != MAGIC {
+            return Err(SnapshotError::InvalidHeader);
+        }
+        let version = header.u32().map_err(header_err)?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        if header.bytes(1).map_err(header_err)? != [BYTE_ORDER] {
+            return Err(SnapshotError::InvalidHeader);
+        }
+        let payload_len = header.u64().map_err(header_err)?;
+        let expected_checksum = header.u64().map_err(header_err)?;
+        let payload = header.0;
+        if payload.len() as u64 != payload_len {
+            return Err(SnapshotError::Corrupt);
+        }
+        if checksum(payload) != expected_checksum {
+            return Err(SnapshotError::ChecksumMismatch);
+        }
+
+        let mut data = Reader(payload);
+        let generations = (0..data.u32()?)
+            .map(|_| data.u32())
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut locations = vec![None; generations.len()];
+        let mut world = World::new();
+        let tick = world.change_tick();
+        for _ in 0..data.u32()? {
+            let columns = (0..data.u32()?)
+                .map(|_| {
+                    let len = data.u32()? as usize;
+                    let name =
+                        str::from_utf8(data.bytes(len)?).map_err(|_| SnapshotError::Corrupt)?;
+                    let size = data.u32()? as usize;
+                    self.names
+                        .get(name)
+                        .map(|id| self.types[id].info)
+                        .filter(|info| info.layout().size() == size)
+                        .ok_or_else(|| SnapshotError::UnknownComponent(name.to_string()))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let count = data.u32()?;
+            let ids = data
+                .bytes(count as usize * 4)?
+                .chunks_exact(4)
+                .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
+                .collect::<Vec<_>>();
+            let column_data = columns
+                .iter()
+                .map(|info| {
+                    let len = info
+                        .layout()
+                        .size()
+                        .checked_mul(count as usize)
+                        .ok_or(SnapshotError::Corrupt)?;
+                    data.bytes(len)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut types = columns.clone();
+            types.sort_unstable();
+            if types.windows(2).any(|x| x[0] == x[1]) {
+                return Err(SnapshotError::Corrupt);
+            }
+            let (archetype, base) = world
+                .allocate_restored(types, &ids, &mut locations)
+                .ok_or(SnapshotError::Corrupt)?;
+            for (info, bytes) in columns.iter().zip(column_data) {
+                unsafe {
+                    archetype.put_column(
+                        info.id(),
+                        info.layout().size(),
+                        base,
+                        count,
+                        bytes.as_ptr(),
+                        tick,
+                    );
+                }
+            }
+        }
+        if !data.0.is_empty() {
+            return Err(SnapshotError::Corrupt);
+        }
+        world.restore_entities(&generations, &locations);
+        Ok(world)
+    }
+}
+
+struct Entry {
+    name: &'static str,
+    info: TypeInfo,
+    borrow: fn(&Archetype),
+    release: fn(&Archetype),
+}
+
+/// Error indicating that a snapshot couldn't be saved or loaded
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SnapshotError {
+    /// The world contains a component type that isn't registered
+    UnregisteredComponent,
+    /// The world contains a component stored sparsely, see `World::store_sparse`
+    SparseComponent,
+    /// The data doesn't begin with a snapshot header, or was written on a platform with a different
+    /// byte order
+    InvalidHeader,
+    /// The snapshot was written in an unsupported version of the format
+    UnsupportedVersion(u32),
+    /// The snapshot's contents don't match its checksum
+    ChecksumMismatch,
+    /// The snapshot contains a component type that isn't registered under this name, or whose size
+    /// differs from the registered type
+    UnknownComponent(String),
+    /// The snapshot is truncated or malformed
+    Corrupt,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use SnapshotError::*;
+        match *self {
+            UnregisteredComponent => f.write_str("unregistered component type"),
+            SparseComponent => f.write_str("sparsely stored component"),
+            InvalidHeader => f.write_str("invalid snapshot header"),
+            UnsupportedVersion(x) => write!(f, "unsupported snapshot version {}", x),
+            ChecksumMismatch => f.write_str("snapshot checksum mismatch"),
+            UnknownComponent(ref name) => write!(f, "unknown component type {:?}", name),
+            Corrupt => f.write_str("corrupt snapshot"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for SnapshotError {}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], SnapshotError> {
+        if n > self.0.len() {
+            return Err(SnapshotError::Corrupt);
+        }
+        let (x, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(x)
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+fn write_u32(out: &mut Vec<u8>, x: u32) {
+    out.extend_from_slice(&x.to_le_bytes());
+}
+
+/// 64-bit FNV-1a
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
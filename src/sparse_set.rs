@@ -0,0 +1,266 @@
+// Copyright 2019 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::alloc::alloc::{alloc, dealloc, Layout};
+use crate::alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::ptr::{self, NonNull};
+
+use hashbrown::HashMap;
+
+use crate::archetype::TypeInfo;
+use crate::borrow::{Borrow, SharedBorrow};
+use crate::{CloneError, CloneRegistry};
+
+/// The components of one type belonging to some of an archetype's entities, keyed by entity ID
+///
+/// Components are stored contiguously in the order they were inserted, except that removing one
+/// moves the last component into its place.
+pub(crate) struct SparseSet {
+    ty: TypeInfo,
+    borrow: Borrow,
+    // Position of each entity's component in `data`, `added` and `mutated`
+    index: HashMap<u32, u32>,
+    entities: Vec<u32>,
+    capacity: usize,
+    // UnsafeCell allows unique references into `data` and the ticks to be constructed while shared
+    // references containing the `SparseSet` exist
+    data: UnsafeCell<NonNull<u8>>,
+    added: UnsafeCell<Vec<u64>>,
+    mutated: UnsafeCell<Vec<u64>>,
+}
+
+impl SparseSet {
+    pub(crate) fn new(ty: TypeInfo) -> Self {
+        Self {
+            ty,
+            borrow: Borrow::new(),
+            index: HashMap::new(),
+            entities: Vec::new(),
+            capacity: 0,
+            data: UnsafeCell::new(dangling(ty.layout())),
+            added: UnsafeCell::new(Vec::new()),
+            mutated: UnsafeCell::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn ty(&self) -> &TypeInfo {
+        &self.ty
+    }
+
+    pub(crate) fn borrow_state(&self) -> &Borrow {
+        &self.borrow
+    }
+
+    /// IDs of the entities having components in the set
+    pub(crate) fn entities(&self) -> &[u32] {
+        &self.entities
+    }
+
+    /// Position of the component of the entity with ID `id`, if it has one
+    #[inline]
+    pub(crate) fn index(&self, id: u32) -> Option<usize> {
+        self.index.get(&id).map(|&i| i as usize)
+    }
+
+    pub(crate) fn contains(&self, id: u32) -> bool {
+        self.index.contains_key(&id)
+    }
+
+    /// Pointer to the component at `index`, which must be in bounds
+    #[inline]
+    pub(crate) unsafe fn component(&self, index: usize) -> NonNull<u8> {
+        NonNull::new_unchecked(
+            (*self.data.get())
+                .as_ptr()
+                .add(self.ty.layout().size() * index),
+        )
+    }
+
+    /// Pointers to the ticks at which the component at `index`, which must be in bounds, was added
+    /// and last mutably accessed, respectively
+    #[inline]
+    pub(crate) unsafe fn ticks(&self, index: usize) -> (NonNull<u64>, NonNull<u64>) {
+        (
+            NonNull::new_unchecked((*self.added.get()).as_mut_ptr().add(index)),
+            NonNull::new_unchecked((*self.mutated.get()).as_mut_ptr().add(index)),
+        )
+    }
+
+    /// Move `component` into the set as the component of the entity with ID `id`, recording that it
+    /// was added at tick `added` and last mutably accessed at tick `mutated`
+    ///
+    /// An existing component of the entity is overwritten without being dropped.
+    pub(crate) unsafe fn insert(&mut self, id: u32, component: *mut u8, added: u64, mutated: u64) {
+        let index = match self.index(id) {
+            Some(i) => i,
+            None => {
+                let i = self.entities.len();
+                if i == self.capacity {
+                    self.grow();
+                }
+                self.index.insert(id, i as u32);
+                self.entities.push(id);
+                self.added.get_mut().push(0);
+                self.mutated.get_mut().push(0);
+                i
+            }
+        };
+        ptr::copy_nonoverlapping(
+            component,
+            self.component(index).as_ptr(),
+            self.ty.layout().size(),
+        );
+        let (added_ptr, mutated_ptr) = self.ticks(index);
+        *added_ptr.as_ptr() = added;
+        *mutated_ptr.as_ptr() = mutated;
+    }
+
+    /// Remove the component of the entity with ID `id` without dropping it, returning whether it
+    /// had one
+    pub(crate) unsafe fn forget(&mut self, id: u32) -> bool {
+        let index = match self.index.remove(&id) {
+            Some(i) => i as usize,
+            None => return false,
+        };
+        let last = self.entities.len() - 1;
+        if index != last {
+            ptr::copy_nonoverlapping(
+                self.component(last).as_ptr(),
+                self.component(index).as_ptr(),
+                self.ty.layout().size(),
+            );
+            let moved = self.entities[last];
+            self.index.insert(moved, index as u32);
+        }
+        self.entities.swap_remove(index);
+        self.added.get_mut().swap_remove(index);
+        self.mutated.get_mut().swap_remove(index);
+        true
+    }
+
+    /// Drop the component of the entity with ID `id`, if it has one
+    pub(crate) fn remove(&mut self, id: u32) {
+        if let Some(index) = self.index(id) {
+            unsafe {
+                self.ty.drop(self.component(index).as_ptr());
+                self.forget(id);
+            }
+        }
+    }
+
+    /// Move the component of the entity with ID `id`, if it has one, into `target`
+    pub(crate) fn move_to(&mut self, id: u32, target: &mut SparseSet) {
+        debug_assert_eq!(self.ty.id(), target.ty.id());
+        if let Some(index) = self.index(id) {
+            unsafe {
+                let (added, mutated) = self.ticks(index);
+                target.insert(
+                    id,
+                    self.component(index).as_ptr(),
+                    *added.as_ptr(),
+                    *mutated.as_ptr(),
+                );
+                self.forget(id);
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        for index in 0..self.entities.len() {
+            unsafe {
+                self.ty.drop(self.component(index).as_ptr());
+            }
+        }
+        self.index.clear();
+        self.entities.clear();
+        self.added.get_mut().clear();
+        self.mutated.get_mut().clear();
+    }
+
+    fn grow(&mut self) {
+        let capacity = (self.capacity * 2).max(4);
+        let size = self.ty.layout().size();
+        if size != 0 {
+            unsafe {
+                let new_data = NonNull::new(alloc(self.data_layout(capacity))).unwrap();
+                let old_data = *self.data.get_mut();
+                ptr::copy_nonoverlapping(
+                    old_data.as_ptr(),
+                    new_data.as_ptr(),
+                    size * self.entities.len(),
+                );
+                if self.capacity != 0 {
+                    dealloc(old_data.as_ptr(), self.data_layout(self.capacity));
+                }
+                *self.data.get_mut() = new_data;
+            }
+        }
+        self.capacity = capacity;
+    }
+
+    fn data_layout(&self, capacity: usize) -> Layout {
+        let layout = self.ty.layout();
+        Layout::from_size_align(layout.size() * capacity, layout.align()).unwrap()
+    }
+
+    /// Duplicate the set, cloning every component with the functions in `registry`
+    pub(crate) fn try_clone(&self, registry: &CloneRegistry) -> Result<Self, CloneError> {
+        let mut set = Self::new(self.ty);
+        if self.entities.is_empty() {
+            return Ok(set);
+        }
+        let clone = registry
+            .get(self.ty.id())
+            .ok_or_else(|| CloneError::new(self.ty.type_name()))?;
+        while set.capacity < self.entities.len() {
+            set.grow();
+        }
+        let borrow = SharedBorrow::new(&self.borrow)
+            .unwrap_or_else(|| panic!("{} already borrowed uniquely", self.ty.type_name()));
+        unsafe {
+            clone(
+                (*self.data.get()).as_ptr(),
+                set.data.get_mut().as_ptr(),
+                self.entities.len(),
+            );
+            set.added = UnsafeCell::new((*self.added.get()).clone());
+            set.mutated = UnsafeCell::new((*self.mutated.get()).clone());
+        }
+        drop(borrow);
+        // Set last, so that a panicking `clone` can only leak components
+        set.index = self.index.clone();
+        set.entities = self.entities.clone();
+        Ok(set)
+    }
+}
+
+impl Drop for SparseSet {
+    fn drop(&mut self) {
+        self.clear();
+        if self.capacity != 0 && self.ty.layout().size() != 0 {
+            unsafe {
+                dealloc(
+                    self.data.get_mut().as_ptr(),
+                    self.data_layout(self.capacity),
+                );
+            }
+        }
+    }
+}
+
+/// A well-aligned pointer suitable for zero-sized allocations of `layout`
+fn dangling(layout: Layout) -> NonNull<u8> {
+    unsafe { NonNull::new_unchecked(layout.align() as *mut u8) }
+}
@@ -12,21 +12,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::alloc::vec::Vec;
-use core::any::TypeId;
+use crate::alloc::{vec, vec::Vec};
 use core::convert::TryFrom;
-use core::{fmt, mem, ptr};
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{fmt, mem};
 
 #[cfg(feature = "std")]
 use std::error::Error;
 
 use hashbrown::{HashMap, HashSet};
 
-use crate::archetype::Archetype;
-use crate::entities::{Entities, Location};
+use crate::archetype::{Archetype, ComponentId, TypeInfo};
+use crate::entities::{Entities, EntityMeta, Location};
+use crate::hierarchy::{DescendantsBreadthFirst, DescendantsDepthFirst};
+use crate::query::{assert_borrow, Fetch, Ticks};
+use crate::relation::RelationIndex;
 use crate::{
-    Bundle, DynamicBundle, Entity, EntityRef, MissingComponent, NoSuchEntity, Query, QueryBorrow,
-    QueryOne, Ref, RefMut,
+    BorrowError, Bundle, Children, CloneError, CloneRegistry, CommandBuffer, DynamicBundle, Entity,
+    EntityRef, HierarchyError, MissingComponent, NoSuchEntity, Parent, Query, QueryBorrow,
+    QueryMut, QueryOne, QueryOneError, Ref, RefMut, Relation,
 };
 
 /// An unordered collection of entities, each having any number of distinctly typed components
@@ -37,28 +42,62 @@ use crate::{
 /// The components of entities who have the same set of component types are stored in contiguous
 /// runs, allowing for extremely fast, cache-friendly iteration.
 pub struct World {
+    id: u64,
     entities: Entities,
-    index: HashMap<Vec<TypeId>, u32>,
+    index: HashMap<Vec<ComponentId>, u32>,
     archetypes: Vec<Archetype>,
     archetype_generation: u64,
+    change_tick: u64,
+    removed: HashMap<ComponentId, Vec<Entity>>,
+    relations: HashMap<ComponentId, RelationIndex>,
+    sparse: HashSet<ComponentId>,
 }
 
 impl World {
     /// Create an empty world
     pub fn new() -> Self {
         // `flush` assumes archetype 0 always exists, representing entities with no components.
-        let mut archetypes = Vec::new();
-        archetypes.push(Archetype::new(Vec::new()));
+        let archetypes = vec![Archetype::new(Vec::new())];
         let mut index = HashMap::default();
         index.insert(Vec::new(), 0);
         Self {
+            id: next_id(),
             entities: Entities::default(),
             index,
             archetypes,
             archetype_generation: 0,
+            change_tick: 1,
+            removed: HashMap::default(),
+            relations: HashMap::default(),
+            sparse: HashSet::default(),
         }
     }
 
+    /// Duplicate the world, including all entities and their components
+    ///
+    /// Every component type of every entity must be registered in `registry`. Entity handles refer
+    /// to the same entities in both worlds, and reserved entities remain reserved in both.
+    ///
+    /// See `CloneRegistry` for an example.
+    pub fn try_clone(&self, registry: &CloneRegistry) -> Result<World, CloneError> {
+        let archetypes = self
+            .archetypes
+            .iter()
+            .map(|x| x.try_clone(registry))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            id: next_id(),
+            entities: self.entities.clone(),
+            index: self.index.clone(),
+            archetypes,
+            archetype_generation: self.archetype_generation,
+            change_tick: self.change_tick,
+            removed: self.removed.clone(),
+            relations: self.relations.clone(),
+            sparse: self.sparse.clone(),
+        })
+    }
+
     /// Create an entity with certain components
     ///
     /// Returns the ID of the newly created entity.
@@ -82,22 +121,28 @@ impl World {
         // necessary
         self.flush();
 
+        let linked = self.linked_ids(&components);
         let entity = self.entities.alloc();
-        let archetype_id = components.with_ids(|ids| {
-            self.index.get(ids).copied().unwrap_or_else(|| {
-                let x = self.archetypes.len() as u32;
-                self.archetypes.push(Archetype::new(components.type_info()));
-                self.index.insert(ids.to_vec(), x);
-                self.archetype_generation += 1;
-                x
+        let archetype_id = if self.sparse.is_empty() {
+            components.with_ids(|ids| {
+                self.index.get(ids).copied().unwrap_or_else(|| {
+                    let x = self.archetypes.len() as u32;
+                    self.archetypes.push(Archetype::new(components.type_info()));
+                    self.index.insert(ids.to_vec(), x);
+                    self.archetype_generation += 1;
+                    x
+                })
             })
-        });
+        } else {
+            self.archetype_for(components.type_info())
+        };
 
+        let tick = self.change_tick;
         let archetype = &mut self.archetypes[archetype_id as usize];
         unsafe {
             let index = archetype.allocate(entity.id);
             components.put(|ptr, ty, size| {
-                archetype.put_dynamic(ptr, ty, size, index);
+                archetype.put_dynamic(ptr, ty, size, index, tick, 0);
                 true
             });
             self.entities.meta[entity.id as usize].location = Location {
@@ -105,6 +150,9 @@ impl World {
                 index,
             };
         }
+        if let Some(ids) = linked {
+            self.link(entity, &ids);
+        }
         entity
     }
 
@@ -112,6 +160,9 @@ impl World {
     ///
     /// Faster than calling `spawn` repeatedly with the same components.
     ///
+    /// Panics if the components include `Parent`, `Children` or a `Relation`, which only `spawn`
+    /// can maintain.
+    ///
     /// # Example
     /// ```
     /// # use hecs::*;
@@ -126,6 +177,10 @@ impl World {
         I: IntoIterator,
         I::Item: Bundle,
     {
+        assert!(
+            !I::Item::with_static_ids(|ids| ids.iter().any(|&id| self.is_linked(id))),
+            "spawn_batch can't maintain the hierarchy or relations; use spawn instead"
+        );
         // Ensure all entity allocations are accounted for so `self.entities` can realloc if
         // necessary
         self.flush();
@@ -141,6 +196,7 @@ impl World {
             entities: &mut self.entities,
             archetype_id,
             archetype: &mut self.archetypes[archetype_id as usize],
+            tick: self.change_tick,
         }
     }
 
@@ -159,15 +215,208 @@ impl World {
     }
 
     /// Destroy an entity and all its components
+    ///
+    /// The entity is removed from its parent's `Children`, and its children are left without a
+    /// parent. To destroy its descendants as well, use `despawn_recursive`. Relations targeting the
+    /// entity are removed from the entities they belong to.
     pub fn despawn(&mut self, entity: Entity) -> Result<(), NoSuchEntity> {
         self.flush();
+        self.unlink_parent(entity);
+        self.unlink_children(entity);
+        self.despawn_inner(entity)
+    }
+
+    /// Destroy every entity matching `Q` and all their components
+    ///
+    /// Archetypes whose entities all match are emptied at once rather than entity by entity,
+    /// unless their entities belong to the hierarchy or to relations, which are maintained as by
+    /// `despawn`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// struct Frozen;
+    /// let mut world = World::new();
+    /// let a = world.spawn((1, Frozen));
+    /// let b = world.spawn((2,));
+    /// let c = world.spawn((3, true, Frozen));
+    /// world.despawn_matching::<&Frozen>();
+    /// assert!(!world.contains(a));
+    /// assert!(world.contains(b));
+    /// assert!(!world.contains(c));
+    /// ```
+    pub fn despawn_matching<Q: Query>(&mut self) {
+        self.flush();
+        let (archetypes, entities) = self.matching::<Q>(|archetype| {
+            // Despawning the entities mustn't affect any others
+            let linked = archetype
+                .types()
+                .iter()
+                .chain(archetype.sparse_sets().iter().map(|x| x.ty()))
+                .any(|ty| self.is_linked(ty.id()));
+            let targeted = || {
+                archetype.ids().iter().any(|&id| {
+                    let entity = Entity {
+                        id,
+                        generation: self.entities.meta[id as usize].generation,
+                    };
+                    self.relations
+                        .values()
+                        .any(|x| !x.sources(entity).is_empty())
+                })
+            };
+            Some(!linked && !targeted())
+        });
+        for index in archetypes {
+            let archetype = &mut self.archetypes[index as usize];
+            record_removed(&mut self.removed, &self.entities.meta, archetype);
+            for &id in archetype.ids() {
+                let generation = self.entities.meta[id as usize].generation;
+                self.entities.free(Entity { id, generation }).unwrap();
+            }
+            archetype.clear();
+        }
+        for entity in entities {
+            let _ = self.despawn(entity);
+        }
+    }
+
+    /// Destroy an entity without maintaining the hierarchy
+    fn despawn_inner(&mut self, entity: Entity) -> Result<(), NoSuchEntity> {
+        if self.contains(entity) {
+            self.unlink_relations(entity);
+        }
         let loc = self.entities.free(entity)?;
+        let archetype = &self.archetypes[loc.archetype as usize];
+        for ty in archetype
+            .types()
+            .iter()
+            .chain(archetype.sparse_types(loc.index))
+        {
+            if let Some(removed) = self.removed.get_mut(&ty.id()) {
+                removed.push(entity);
+            }
+        }
         if let Some(moved) = unsafe { self.archetypes[loc.archetype as usize].remove(loc.index) } {
             self.entities.meta[moved as usize].location.index = loc.index;
         }
         Ok(())
     }
 
+    /// Find or create the archetype storing entities with components of `types`, and ensure it has
+    /// a sparse set for each sparsely stored type among them
+    fn archetype_for(&mut self, types: Vec<TypeInfo>) -> u32 {
+        use hashbrown::hash_map::Entry;
+
+        let (sparse, mut columns): (Vec<_>, Vec<_>) = types
+            .into_iter()
+            .partition(|ty| self.sparse.contains(&ty.id()));
+        columns.sort();
+        let elements = columns.iter().map(|x| x.id()).collect::<Vec<_>>();
+        let archetype = match self.index.entry(elements) {
+            Entry::Occupied(x) => *x.get(),
+            Entry::Vacant(x) => {
+                let index = self.archetypes.len() as u32;
+                self.archetypes.push(Archetype::new(columns));
+                x.insert(index);
+                self.archetype_generation += 1;
+                index
+            }
+        };
+        for ty in sparse {
+            if self.archetypes[archetype as usize].add_sparse(ty) {
+                self.archetype_generation += 1;
+            }
+        }
+        archetype
+    }
+
+    /// The archetype entities in archetype `source` move to when `components` are inserted
+    fn insert_target(&mut self, source: u32, components: &impl DynamicBundle) -> u32 {
+        let source = source as usize;
+        if let Some(x) = components.with_ids(|ids| self.archetypes[source].insert_edge(ids)) {
+            return x;
+        }
+        let arch = &self.archetypes[source];
+        let mut info = arch.types().to_vec();
+        info.extend(
+            components
+                .type_info()
+                .into_iter()
+                .filter(|ty| !arch.has_dynamic(ty.id())),
+        );
+        let target = self.archetype_for(info);
+        components.with_ids(|ids| self.archetypes[source].set_insert_edge(ids, target));
+        target
+    }
+
+    /// The archetype entities in archetype `source` move to when the `removed` components are
+    /// removed
+    fn remove_target(&mut self, source: u32, removed: &[ComponentId]) -> u32 {
+        use hashbrown::hash_map::Entry;
+
+        let source = source as usize;
+        if let Some(x) = self.archetypes[source].remove_edge(removed) {
+            return x;
+        }
+        let info = self.archetypes[source]
+            .types()
+            .iter()
+            .cloned()
+            .filter(|x| !removed.contains(&x.id()))
+            .collect::<Vec<_>>();
+        let elements = info.iter().map(|x| x.id()).collect::<Vec<_>>();
+        let target = match self.index.entry(elements) {
+            Entry::Occupied(x) => *x.get(),
+            Entry::Vacant(x) => {
+                let index = self.archetypes.len() as u32;
+                x.insert(index);
+                self.archetypes.push(Archetype::new(info));
+                self.archetype_generation += 1;
+                index
+            }
+        };
+        self.archetypes[source].set_remove_edge(removed, target);
+        target
+    }
+
+    /// Store `T` components in sparse sets rather than in archetype columns
+    ///
+    /// Adding or removing a component normally moves all of an entity's components into another
+    /// archetype. Sparsely stored components don't affect which archetype an entity belongs to, so
+    /// adding and removing them takes constant time regardless of what other components the entity
+    /// has. This suits components that come and go frequently, such as status markers, at the cost
+    /// of a lookup per entity when they're accessed.
+    ///
+    /// Queries and methods like `get`, `insert` and `remove` handle sparsely stored components
+    /// transparently, as do `DynamicQuery` and `EntityRef::component_types`. However, they're
+    /// omitted by `query_raw` and the methods of `Archetype`, and serializing or snapshotting a
+    /// world fails while any entity has one.
+    ///
+    /// Panics if any entity already has a `T` component.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// struct Stunned;
+    /// let mut world = World::new();
+    /// world.store_sparse::<Stunned>();
+    /// let a = world.spawn((123, true));
+    /// world.insert_one(a, Stunned).unwrap();
+    /// assert_eq!(world.query::<(&i32, &Stunned)>().iter().count(), 1);
+    /// world.remove_one::<Stunned>(a).unwrap();
+    /// assert_eq!(world.query::<With<Stunned, &i32>>().iter().count(), 0);
+    /// ```
+    pub fn store_sparse<T: Component>(&mut self) {
+        let id = ComponentId::of::<T>();
+        assert!(
+            !self.archetypes.iter().any(|x| x.has_dynamic(id)),
+            "{} components already stored in archetype columns",
+            core::any::type_name::<T>()
+        );
+        self.sparse.insert(id);
+    }
+
     /// Ensure `additional` entities with exact components `T` can be spawned without reallocating
     pub fn reserve<T: Bundle>(&mut self, additional: u32) {
         self.reserve_inner::<T>(additional);
@@ -177,15 +426,19 @@ impl World {
         self.flush();
         self.entities.reserve(additional);
 
-        let archetype_id = T::with_static_ids(|ids| {
-            self.index.get(ids).copied().unwrap_or_else(|| {
-                let x = self.archetypes.len() as u32;
-                self.archetypes.push(Archetype::new(T::static_type_info()));
-                self.index.insert(ids.to_vec(), x);
-                self.archetype_generation += 1;
-                x
+        let archetype_id = if self.sparse.is_empty() {
+            T::with_static_ids(|ids| {
+                self.index.get(ids).copied().unwrap_or_else(|| {
+                    let x = self.archetypes.len() as u32;
+                    self.archetypes.push(Archetype::new(T::static_type_info()));
+                    self.index.insert(ids.to_vec(), x);
+                    self.archetype_generation += 1;
+                    x
+                })
             })
-        });
+        } else {
+            self.archetype_for(T::static_type_info())
+        };
 
         self.archetypes[archetype_id as usize].reserve(additional);
         archetype_id
@@ -196,6 +449,10 @@ impl World {
     /// Preserves allocated storage for reuse.
     pub fn clear(&mut self) {
         for x in &mut self.archetypes {
+            record_removed(&mut self.removed, &self.entities.meta, x);
+            x.clear();
+        }
+        for x in self.relations.values_mut() {
             x.clear();
         }
         self.entities.clear();
@@ -246,7 +503,7 @@ impl World {
     /// assert!(entities.contains(&(b, 456, false)));
     /// ```
     pub fn query<Q: Query>(&self) -> QueryBorrow<'_, Q> {
-        QueryBorrow::new(&self.entities.meta, &self.archetypes)
+        QueryBorrow::new(&self.entities.meta, &self.archetypes, self.ticks())
     }
 
     /// Prepare a query against a single entity
@@ -270,7 +527,84 @@ impl World {
     /// ```
     pub fn query_one<Q: Query>(&self, entity: Entity) -> Result<QueryOne<'_, Q>, NoSuchEntity> {
         let loc = self.entities.get(entity)?;
-        Ok(unsafe { QueryOne::new(&self.archetypes[loc.archetype as usize], loc.index) })
+        Ok(unsafe {
+            QueryOne::new(
+                &self.entities.meta,
+                &self.archetypes[loc.archetype as usize],
+                loc.index,
+                self.ticks(),
+            )
+        })
+    }
+
+    /// Query a uniquely borrowed world
+    ///
+    /// Like `query`, but faster because dynamic borrow checks can be skipped. Note that, unlike
+    /// `query`, this returns an iterator directly.
+    ///
+    /// Panics if `Q` borrows a component type uniquely while also accessing it elsewhere, such as
+    /// `(&mut T, &T)`, regardless of the world's contents. Such queries compile, since the type
+    /// system can't compare component types, so this is checked at runtime the first time each
+    /// query type is used with `query_mut`, `query_one_mut` or `PreparedQuery::query_mut`, and
+    /// remembered afterwards.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// let a = world.spawn((123, true, "abc"));
+    /// let b = world.spawn((456, false));
+    /// for (_, (number, &flag)) in world.query_mut::<(&mut i32, &bool)>() {
+    ///     if flag { *number *= 2; }
+    /// }
+    /// assert_eq!(*world.get::<i32>(a).unwrap(), 246);
+    /// assert_eq!(*world.get::<i32>(b).unwrap(), 456);
+    /// ```
+    pub fn query_mut<Q: Query>(&mut self) -> QueryMut<'_, Q> {
+        let ticks = self.ticks();
+        QueryMut::new(&self.entities.meta, &mut self.archetypes, ticks)
+    }
+
+    /// Query a single entity in a uniquely borrowed world
+    ///
+    /// Like `query_one`, but faster because dynamic borrow checks can be skipped. Note that, unlike
+    /// `query_one`, the result is returned directly.
+    ///
+    /// Panics under the same conditions as `query_mut`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// let a = world.spawn((123, true, "abc"));
+    /// let (number, flag) = world.query_one_mut::<(&mut i32, &bool)>(a).unwrap();
+    /// if *flag { *number *= 2; }
+    /// assert_eq!(*number, 246);
+    /// assert!(world.query_one_mut::<&f32>(a).is_err());
+    /// ```
+    pub fn query_one_mut<Q: Query>(
+        &mut self,
+        entity: Entity,
+    ) -> Result<<Q::Fetch as Fetch<'_>>::Item, QueryOneError> {
+        assert_borrow::<Q>();
+        let loc = self.entities.get(entity)?;
+        if loc.is_pending() {
+            return Err(QueryOneError::Unsatisfied);
+        }
+        let ticks = self.ticks();
+        unsafe {
+            let mut fetch = Q::Fetch::get(
+                &self.archetypes[loc.archetype as usize],
+                &self.entities.meta,
+                loc.index as usize,
+                ticks,
+            )
+            .ok_or(QueryOneError::Unsatisfied)?;
+            if fetch.should_skip() {
+                return Err(QueryOneError::Unsatisfied);
+            }
+            Ok(fetch.next())
+        }
     }
 
     /// Borrow the `T` component of `entity`
@@ -279,7 +613,7 @@ impl World {
     /// components.
     pub fn get<T: Component>(&self, entity: Entity) -> Result<Ref<'_, T>, ComponentError> {
         let loc = self.entities.get(entity)?;
-        if loc.archetype == 0 {
+        if loc.is_pending() {
             return Err(MissingComponent::new::<T>().into());
         }
         Ok(unsafe { Ref::new(&self.archetypes[loc.archetype as usize], loc.index)? })
@@ -290,10 +624,54 @@ impl World {
     /// Panics if the component is already borrowed from another entity with the same components.
     pub fn get_mut<T: Component>(&self, entity: Entity) -> Result<RefMut<'_, T>, ComponentError> {
         let loc = self.entities.get(entity)?;
-        if loc.archetype == 0 {
+        if loc.is_pending() {
+            return Err(MissingComponent::new::<T>().into());
+        }
+        Ok(unsafe {
+            RefMut::new(
+                &self.archetypes[loc.archetype as usize],
+                loc.index,
+                self.change_tick,
+            )?
+        })
+    }
+
+    /// Borrow the `T` component of `entity`, or fail if it's already uniquely borrowed
+    pub fn try_get<T: Component>(&self, entity: Entity) -> Result<Ref<'_, T>, TryGetError> {
+        let loc = self.entities.get(entity)?;
+        if loc.is_pending() {
             return Err(MissingComponent::new::<T>().into());
         }
-        Ok(unsafe { RefMut::new(&self.archetypes[loc.archetype as usize], loc.index)? })
+        unsafe { Ref::try_new(&self.archetypes[loc.archetype as usize], loc.index) }
+    }
+
+    /// Uniquely borrow the `T` component of `entity`, or fail if it's already borrowed
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// let a = world.spawn((123,));
+    /// let x = world.get::<i32>(a).unwrap();
+    /// match world.try_get_mut::<i32>(a) {
+    ///     Err(TryGetError::BorrowConflict(e)) => assert_eq!(e.conflict(), Access::Read),
+    ///     _ => unreachable!(),
+    /// }
+    /// drop(x);
+    /// *world.try_get_mut::<i32>(a).unwrap() = 42;
+    /// ```
+    pub fn try_get_mut<T: Component>(&self, entity: Entity) -> Result<RefMut<'_, T>, TryGetError> {
+        let loc = self.entities.get(entity)?;
+        if loc.is_pending() {
+            return Err(MissingComponent::new::<T>().into());
+        }
+        unsafe {
+            RefMut::try_new(
+                &self.archetypes[loc.archetype as usize],
+                loc.index,
+                self.change_tick,
+            )
+        }
     }
 
     /// Access an entity regardless of its component types
@@ -301,8 +679,14 @@ impl World {
     /// Does not immediately borrow any component.
     pub fn entity(&self, entity: Entity) -> Result<EntityRef<'_>, NoSuchEntity> {
         Ok(match self.entities.get(entity)? {
-            Location { archetype: 0, .. } => EntityRef::empty(),
-            loc => unsafe { EntityRef::new(&self.archetypes[loc.archetype as usize], loc.index) },
+            loc if loc.is_pending() => EntityRef::empty(),
+            loc => unsafe {
+                EntityRef::new(
+                    &self.archetypes[loc.archetype as usize],
+                    loc.index,
+                    self.change_tick,
+                )
+            },
         })
     }
 
@@ -323,7 +707,7 @@ impl World {
     /// assert!(ids.contains(&b));
     /// ```
     pub fn iter(&self) -> Iter<'_> {
-        Iter::new(&self.archetypes, &self.entities)
+        Iter::new(&self.archetypes, &self.entities, self.change_tick)
     }
 
     /// Add `components` to `entity`
@@ -347,41 +731,55 @@ impl World {
         entity: Entity,
         components: impl DynamicBundle,
     ) -> Result<(), NoSuchEntity> {
-        use hashbrown::hash_map::Entry;
+        let ids = match self.linked_ids(&components) {
+            Some(x) => x,
+            None => return self.insert_unlinked(entity, components),
+        };
+        self.flush();
+        if !self.contains(entity) {
+            return Err(NoSuchEntity);
+        }
+        // Components being replaced are detached first, and their replacements attached once in
+        // place
+        self.unlink_replaced(entity, &ids);
+        self.insert_unlinked(entity, components)?;
+        self.link(entity, &ids);
+        Ok(())
+    }
 
+    /// Add components to `entity` without maintaining the hierarchy or relations
+    fn insert_unlinked(
+        &mut self,
+        entity: Entity,
+        components: impl DynamicBundle,
+    ) -> Result<(), NoSuchEntity> {
         self.flush();
+        let tick = self.change_tick;
         let loc = self.entities.get_mut(entity)?;
         unsafe {
-            // Assemble Vec<TypeInfo> for the final entity
-            let arch = &mut self.archetypes[loc.archetype as usize];
-            let mut info = arch.types().to_vec();
-            for ty in components.type_info() {
-                if let Some(ptr) = arch.get_dynamic(ty.id(), ty.layout().size(), loc.index) {
-                    ty.drop(ptr.as_ptr());
-                } else {
-                    info.push(ty);
+            // Drop components that are being replaced
+            let arch = &self.archetypes[loc.archetype as usize];
+            let removed = &mut self.removed;
+            components.with_ids(|ids| {
+                for &id in ids {
+                    if arch.drop_component(id, loc.index) {
+                        if let Some(removed) = removed.get_mut(&id) {
+                            removed.push(entity);
+                        }
+                    }
                 }
-            }
-            info.sort();
+            });
 
             // Find the archetype it'll live in
-            let elements = info.iter().map(|x| x.id()).collect::<Vec<_>>();
-            let target = match self.index.entry(elements) {
-                Entry::Occupied(x) => *x.get(),
-                Entry::Vacant(x) => {
-                    let index = self.archetypes.len() as u32;
-                    self.archetypes.push(Archetype::new(info));
-                    x.insert(index);
-                    self.archetype_generation += 1;
-                    index
-                }
-            };
+            let source = loc.archetype;
+            let target = self.insert_target(source, &components);
+            let loc = self.entities.get_mut(entity).unwrap();
 
             if target == loc.archetype {
                 // Update components in the current archetype
                 let arch = &mut self.archetypes[loc.archetype as usize];
                 components.put(|ptr, ty, size| {
-                    arch.put_dynamic(ptr, ty, size, loc.index);
+                    arch.put_dynamic(ptr, ty, size, loc.index, tick, 0);
                     true
                 });
                 return Ok(());
@@ -396,13 +794,16 @@ impl World {
             let target_index = target_arch.allocate(entity.id);
             loc.archetype = target;
             let old_index = mem::replace(&mut loc.index, target_index);
-            if let Some(moved) = source_arch.move_to(old_index, |ptr, ty, size| {
-                target_arch.put_dynamic(ptr, ty, size, target_index);
+            if source_arch.move_sparse(entity.id, target_arch) {
+                self.archetype_generation += 1;
+            }
+            if let Some(moved) = source_arch.move_to(old_index, |ptr, ty, size, added, mutated| {
+                target_arch.put_dynamic(ptr, ty, size, target_index, added, mutated);
             }) {
                 self.entities.meta[moved as usize].location.index = old_index;
             }
             components.put(|ptr, ty, size| {
-                target_arch.put_dynamic(ptr, ty, size, target_index);
+                target_arch.put_dynamic(ptr, ty, size, target_index, tick, 0);
                 true
             });
         }
@@ -440,50 +841,72 @@ impl World {
     /// assert_eq!(*world.get::<bool>(e).unwrap(), true);
     /// ```
     pub fn remove<T: Bundle>(&mut self, entity: Entity) -> Result<T, ComponentError> {
-        use hashbrown::hash_map::Entry;
+        T::with_static_ids(|removed| {
+            self.unlink(entity, removed);
+            self.remove_unlinked(entity, removed)
+        })
+    }
 
-        self.flush();
-        let loc = self.entities.get_mut(entity)?;
+    /// Remove components from `entity` without maintaining the hierarchy or relations
+    pub(crate) fn remove_unlinked<T: Bundle>(
+        &mut self,
+        entity: Entity,
+        removed: &[ComponentId],
+    ) -> Result<T, ComponentError> {
         unsafe {
-            let removed = T::with_static_ids(|ids| ids.iter().copied().collect::<HashSet<_>>());
-            let info = self.archetypes[loc.archetype as usize]
-                .types()
-                .iter()
-                .cloned()
-                .filter(|x| !removed.contains(&x.id()))
-                .collect::<Vec<_>>();
-            let elements = info.iter().map(|x| x.id()).collect::<Vec<_>>();
-            let target = match self.index.entry(elements) {
-                Entry::Occupied(x) => *x.get(),
-                Entry::Vacant(x) => {
-                    self.archetypes.push(Archetype::new(info));
-                    let index = (self.archetypes.len() - 1) as u32;
-                    x.insert(index);
-                    self.archetype_generation += 1;
-                    index
-                }
-            };
-            let old_index = loc.index;
-            let source_arch = &self.archetypes[loc.archetype as usize];
-            let bundle = T::get(|ty, size| source_arch.get_dynamic(ty, size, old_index))?;
-            let (source_arch, target_arch) = index2(
-                &mut self.archetypes,
-                loc.archetype as usize,
-                target as usize,
-            );
-            let target_index = target_arch.allocate(entity.id);
-            loc.archetype = target;
-            loc.index = target_index;
-            if let Some(moved) = source_arch.move_to(old_index, |src, ty, size| {
-                // Only move the components present in the target archetype, i.e. the non-removed ones.
-                if let Some(dst) = target_arch.get_dynamic(ty, size, target_index) {
-                    ptr::copy_nonoverlapping(src, dst.as_ptr(), size);
-                }
-            }) {
-                self.entities.meta[moved as usize].location.index = old_index;
+            self.remove_inner(entity, removed, |archetype, index| {
+                Ok(T::get(|ty, size| archetype.get_dynamic(ty, size, index))?)
+            })
+        }
+    }
+
+    /// Move `entity` into the archetype lacking the component types in `removed`
+    ///
+    /// `take` is called with the entity's archetype and index beforehand, and must move or drop
+    /// every removed component, or fail without touching them.
+    unsafe fn remove_inner<R>(
+        &mut self,
+        entity: Entity,
+        removed: &[ComponentId],
+        take: impl FnOnce(&Archetype, u32) -> Result<R, ComponentError>,
+    ) -> Result<R, ComponentError> {
+        self.flush();
+        let source = self.entities.get_mut(entity)?.archetype;
+        let target = self.remove_target(source, removed);
+        let loc = self.entities.get_mut(entity).unwrap();
+        let old_index = loc.index;
+        let result = take(&self.archetypes[loc.archetype as usize], old_index)?;
+        for id in removed {
+            if let Some(removed) = self.removed.get_mut(id) {
+                removed.push(entity);
             }
-            Ok(bundle)
         }
+        self.archetypes[loc.archetype as usize].forget_sparse(removed.iter().copied(), old_index);
+        if target == loc.archetype {
+            // Only sparsely stored components were removed
+            return Ok(result);
+        }
+        let (source_arch, target_arch) = index2(
+            &mut self.archetypes,
+            loc.archetype as usize,
+            target as usize,
+        );
+        let target_index = target_arch.allocate(entity.id);
+        loc.archetype = target;
+        loc.index = target_index;
+        if source_arch.move_sparse(entity.id, target_arch) {
+            self.archetype_generation += 1;
+        }
+        if let Some(moved) = source_arch.move_to(old_index, |src, ty, size, added, mutated| {
+            // Only move the components present in the target archetype, i.e. the non-removed
+            // ones.
+            if target_arch.has_dynamic(ty) {
+                target_arch.put_dynamic(src, ty, size, target_index, added, mutated);
+            }
+        }) {
+            self.entities.meta[moved as usize].location.index = old_index;
+        }
+        Ok(result)
     }
 
     /// Remove the `T` component from `entity`
@@ -493,6 +916,642 @@ impl World {
         self.remove::<(T,)>(entity).map(|(x,)| x)
     }
 
+    /// Add a clone of `components` to every entity matching `Q`
+    ///
+    /// Archetypes whose entities all match are moved into their new archetype at once, copying
+    /// each column in one piece, rather than entity by entity. See `insert`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// struct Frozen;
+    /// let mut world = World::new();
+    /// let a = world.spawn((1, Frozen));
+    /// let b = world.spawn((2,));
+    /// world.insert_all::<&Frozen, _>((0.5f32, "cold"));
+    /// assert_eq!(*world.get::<f32>(a).unwrap(), 0.5);
+    /// assert_eq!(*world.get::<&str>(a).unwrap(), "cold");
+    /// assert!(world.get::<f32>(b).is_err());
+    /// ```
+    pub fn insert_all<Q: Query, B: Bundle + Clone>(&mut self, components: B) {
+        self.flush();
+        let tick = self.change_tick;
+        // Inserting components maintaining the hierarchy or relations may affect other entities
+        let linked = B::with_static_ids(|ids| ids.iter().any(|&id| self.is_linked(id)));
+        let (archetypes, mut entities) = self.matching::<Q>(|_| Some(!linked));
+        // Find every target before moving anything, so that no entity is moved twice
+        let mut moves = Vec::with_capacity(archetypes.len());
+        for source in archetypes {
+            let target = self.insert_target(source, &components);
+            if target != source {
+                moves.push((source, target));
+                continue;
+            }
+            // Only replacing components or adding sparsely stored ones
+            let meta = &self.entities.meta;
+            entities.extend(
+                self.archetypes[source as usize]
+                    .ids()
+                    .iter()
+                    .map(|&id| Entity {
+                        id,
+                        generation: meta[id as usize].generation,
+                    }),
+            );
+        }
+        for (source, target) in moves {
+            // Clone up front, as a panicking `clone` must not leave any rows half-written
+            let bundles = (0..self.archetypes[source as usize].len())
+                .map(|_| components.clone())
+                .collect::<Vec<_>>();
+            unsafe {
+                // Drop components that are being replaced
+                let arch = &self.archetypes[source as usize];
+                let meta = &self.entities.meta;
+                let removed = &mut self.removed;
+                B::with_static_ids(|ids| {
+                    for &id in ids {
+                        for index in 0..arch.len() {
+                            if arch.drop_component(id, index) {
+                                if let Some(removed) = removed.get_mut(&id) {
+                                    let id = arch.entity_id(index);
+                                    removed.push(Entity {
+                                        id,
+                                        generation: meta[id as usize].generation,
+                                    });
+                                }
+                            }
+                        }
+                    }
+                });
+
+                let start = self.move_archetype(source, target);
+                let arch = &mut self.archetypes[target as usize];
+                for (index, bundle) in (start..arch.len()).zip(bundles) {
+                    bundle.put(|ptr, ty, size| {
+                        arch.put_dynamic(ptr, ty, size, index, tick, 0);
+                        true
+                    });
+                }
+            }
+        }
+        for entity in entities {
+            let _ = self.insert(entity, components.clone());
+        }
+    }
+
+    /// Remove and drop the `B` components of every entity matching `Q` that has all of them
+    ///
+    /// Archetypes whose entities all match are moved into their new archetype at once, copying
+    /// each remaining column in one piece, rather than entity by entity. See `remove`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// struct Frozen;
+    /// let mut world = World::new();
+    /// let a = world.spawn((1, 0.5f32, Frozen));
+    /// let b = world.spawn((2, 0.5f32));
+    /// let c = world.spawn((3, Frozen));
+    /// world.remove_all::<&Frozen, (f32,)>();
+    /// assert!(world.get::<f32>(a).is_err());
+    /// assert_eq!(*world.get::<f32>(b).unwrap(), 0.5);
+    /// assert!(world.get::<Frozen>(c).is_ok());
+    /// ```
+    pub fn remove_all<Q: Query, B: Bundle>(&mut self) {
+        self.flush();
+        // Removing components maintaining the hierarchy or relations may affect other entities
+        let linked = B::with_static_ids(|ids| ids.iter().any(|&id| self.is_linked(id)));
+        let (archetypes, entities) = self.matching::<Q>(|archetype| {
+            B::with_static_ids(|ids| {
+                if !ids.iter().all(|&id| archetype.stores(id)) {
+                    return None;
+                }
+                Some(!linked && ids.iter().all(|&id| archetype.has_dynamic(id)))
+            })
+        });
+        for source in archetypes {
+            let target = B::with_static_ids(|ids| self.remove_target(source, ids));
+            if target == source {
+                continue;
+            }
+            let arch = &self.archetypes[source as usize];
+            let meta = &self.entities.meta;
+            let removed = &mut self.removed;
+            B::with_static_ids(|ids| {
+                for id in ids {
+                    if let Some(removed) = removed.get_mut(id) {
+                        removed.extend(arch.ids().iter().map(|&id| Entity {
+                            id,
+                            generation: meta[id as usize].generation,
+                        }));
+                    }
+                }
+            });
+            unsafe {
+                self.move_archetype(source, target);
+            }
+        }
+        for entity in entities {
+            let _ = self.remove::<B>(entity);
+        }
+    }
+
+    /// Move every entity in archetype `source` into `target`, dropping the components of types
+    /// `target` lacks, and return the index of the first moved entity in `target`
+    ///
+    /// Components of types `source` lacks must be written immediately after this call.
+    unsafe fn move_archetype(&mut self, source: u32, target: u32) -> u32 {
+        let (source_arch, target_arch) =
+            index2(&mut self.archetypes, source as usize, target as usize);
+        if source_arch.move_all_sparse(target_arch) {
+            self.archetype_generation += 1;
+        }
+        let start = source_arch.move_all(target_arch);
+        for (index, &id) in target_arch.ids().iter().enumerate().skip(start as usize) {
+            self.entities.meta[id as usize].location = Location {
+                archetype: target,
+                index: index as u32,
+            };
+        }
+        start
+    }
+
+    /// Split the entities matching `Q` into archetypes to be handled whole and other entities
+    ///
+    /// `select` decides, for each archetype `Q` matches, whether to pass over it entirely, or
+    /// whether it may be handled whole if all of its entities match.
+    fn matching<Q: Query>(
+        &self,
+        select: impl Fn(&Archetype) -> Option<bool>,
+    ) -> (Vec<u32>, Vec<Entity>) {
+        let ticks = self.ticks();
+        let mut archetypes = Vec::new();
+        let mut entities = Vec::new();
+        for (index, archetype) in self.archetypes.iter().enumerate() {
+            if archetype.is_empty() {
+                continue;
+            }
+            let mut fetch = match unsafe { Q::Fetch::get(archetype, &self.entities.meta, 0, ticks) }
+            {
+                Some(x) => x,
+                None => continue,
+            };
+            let whole = match select(archetype) {
+                Some(x) => x,
+                None => continue,
+            };
+            let start = entities.len();
+            for &id in archetype.ids() {
+                unsafe {
+                    if !fetch.should_skip() {
+                        entities.push(Entity {
+                            id,
+                            generation: self.entities.meta[id as usize].generation,
+                        });
+                    }
+                    fetch.skip();
+                }
+            }
+            if whole && entities.len() - start == archetype.len() as usize {
+                entities.truncate(start);
+                archetypes.push(index as u32);
+            }
+        }
+        (archetypes, entities)
+    }
+
+    /// Whether adding or removing `id` components requires maintaining the hierarchy or relations
+    fn is_linked(&self, id: ComponentId) -> bool {
+        id == ComponentId::of::<Parent>()
+            || id == ComponentId::of::<Children>()
+            || self.relations.contains_key(&id)
+    }
+
+    /// Add the component of type `ty` stored at `component` to `entity`
+    ///
+    /// Like `insert_one`, but for components whose type is described only at runtime, such as those
+    /// defined with `TypeInfo::dynamic`.
+    ///
+    /// # Safety
+    ///
+    /// `component` must point to a valid component of type `ty`. The component is moved into the
+    /// world, so it must not be used or dropped by the caller afterwards. As the world may be
+    /// shared between threads, the component must be `Send` and `Sync` in spirit.
+    pub unsafe fn insert_raw(
+        &mut self,
+        entity: Entity,
+        ty: TypeInfo,
+        component: *mut u8,
+    ) -> Result<(), NoSuchEntity> {
+        self.insert(entity, RawComponent { ty, ptr: component })
+    }
+
+    /// Pointer to the `ty` component of `entity`
+    ///
+    /// No borrow is acquired, so the component must not be accessed through the pointer while it's
+    /// borrowed elsewhere, e.g. by a query, and the pointer is invalidated when the entity's
+    /// components are added, removed or despawned. Changes made through the pointer aren't visible
+    /// to `Mutated` or `Changed`.
+    pub fn get_raw(&self, entity: Entity, ty: TypeInfo) -> Result<NonNull<u8>, ComponentError> {
+        let loc = self.entities.get(entity)?;
+        if loc.is_pending() {
+            return Err(MissingComponent::from_type_info(&ty).into());
+        }
+        unsafe {
+            self.archetypes[loc.archetype as usize]
+                .get_dynamic(ty.id(), ty.layout().size(), loc.index)
+                .ok_or_else(|| MissingComponent::from_type_info(&ty).into())
+        }
+    }
+
+    /// Drop the `ty` component of `entity`
+    ///
+    /// Like `remove_one`, but for components whose type is described only at runtime.
+    pub fn remove_raw(&mut self, entity: Entity, ty: TypeInfo) -> Result<(), ComponentError> {
+        let removed = [ty.id()];
+        self.unlink(entity, &removed);
+        unsafe {
+            self.remove_inner(entity, &removed, |archetype, index| {
+                let ptr = archetype
+                    .get_dynamic(ty.id(), ty.layout().size(), index)
+                    .ok_or_else(|| MissingComponent::from_type_info(&ty))?;
+                ty.drop(ptr.as_ptr());
+                Ok(())
+            })
+        }
+    }
+
+    /// Iterate over every entity having a `ty` component, with a pointer to that component
+    ///
+    /// The pointers may be freely read and written until the world is next accessed. As with
+    /// `get_raw`, changes made through them aren't visible to `Mutated` or `Changed`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// # use core::alloc::Layout;
+    /// let counter = TypeInfo::dynamic("counter", Layout::new::<u32>(), None);
+    /// let mut world = World::new();
+    /// for i in 0..3u32 {
+    ///     let mut value = i;
+    ///     let e = world.spawn((true,));
+    ///     unsafe { world.insert_raw(e, counter, (&mut value as *mut u32).cast()).unwrap(); }
+    /// }
+    /// let total = world
+    ///     .query_raw(counter)
+    ///     .map(|(_, ptr)| unsafe { *ptr.cast::<u32>().as_ptr() })
+    ///     .sum::<u32>();
+    /// assert_eq!(total, 3);
+    /// ```
+    pub fn query_raw(&mut self, ty: TypeInfo) -> impl Iterator<Item = (Entity, NonNull<u8>)> + '_ {
+        let meta = &self.entities.meta;
+        self.archetypes
+            .iter()
+            .filter(move |archetype| archetype.has_dynamic(ty.id()))
+            .flat_map(move |archetype| {
+                (0..archetype.len()).map(move |index| {
+                    let id = archetype.entity_id(index);
+                    let ptr = unsafe {
+                        archetype
+                            .get_dynamic(ty.id(), ty.layout().size(), index)
+                            .unwrap()
+                    };
+                    (
+                        Entity {
+                            id,
+                            generation: meta[id as usize].generation,
+                        },
+                        ptr,
+                    )
+                })
+            })
+    }
+
+    /// Make `child` a child of `parent`, replacing its previous parent if any
+    ///
+    /// Fails if either entity doesn't exist, or if `parent` is `child` or one of its descendants.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// let a = world.spawn(());
+    /// let b = world.spawn(());
+    /// world.set_parent(b, a).unwrap();
+    /// assert_eq!(world.parent(b), Some(a));
+    /// assert_eq!(&**world.get::<Children>(a).unwrap(), &[b]);
+    /// assert_eq!(world.set_parent(a, b), Err(HierarchyError::Cycle));
+    /// ```
+    pub fn set_parent(&mut self, child: Entity, parent: Entity) -> Result<(), HierarchyError> {
+        if !self.contains(child) || !self.contains(parent) {
+            return Err(HierarchyError::NoSuchEntity);
+        }
+        let mut ancestor = Some(parent);
+        while let Some(x) = ancestor {
+            if x == child {
+                return Err(HierarchyError::Cycle);
+            }
+            ancestor = self.parent(x);
+        }
+        if self.parent(child) == Some(parent) {
+            return Ok(());
+        }
+        self.unlink_parent(child);
+        self.insert_unlinked(child, (Parent(parent),))?;
+        let added = self
+            .get_mut::<Children>(parent)
+            .map(|mut x| x.0.push(child))
+            .is_ok();
+        if !added {
+            self.insert_unlinked(parent, (Children(vec![child]),))?;
+        }
+        Ok(())
+    }
+
+    /// Detach `child` from its parent, returning the former parent if any
+    pub fn remove_parent(&mut self, child: Entity) -> Result<Option<Entity>, NoSuchEntity> {
+        match self.remove_one::<Parent>(child) {
+            Ok(x) => Ok(Some(x.0)),
+            Err(ComponentError::NoSuchEntity) => Err(NoSuchEntity),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// The parent of `entity`, if it exists and has one
+    pub fn parent(&self, entity: Entity) -> Option<Entity> {
+        self.get::<Parent>(entity).ok().map(|x| x.0)
+    }
+
+    /// Destroy an entity and all of its descendants
+    pub fn despawn_recursive(&mut self, entity: Entity) -> Result<(), NoSuchEntity> {
+        self.flush();
+        if !self.contains(entity) {
+            return Err(NoSuchEntity);
+        }
+        self.unlink_parent(entity);
+        // The entire subtree is destroyed, so links within it needn't be maintained
+        for x in self.descendants_depth_first(entity).collect::<Vec<_>>() {
+            self.despawn_inner(x)?;
+        }
+        self.despawn_inner(entity)
+    }
+
+    /// Iterate over the descendants of `entity`, visiting each child's descendants before the
+    /// child's next sibling
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// let [a, b, c, d] = [(); 4].map(|()| world.spawn(()));
+    /// world.set_parent(b, a).unwrap();
+    /// world.set_parent(c, a).unwrap();
+    /// world.set_parent(d, b).unwrap();
+    /// assert_eq!(world.descendants_depth_first(a).collect::<Vec<_>>(), &[b, d, c]);
+    /// assert_eq!(world.descendants_breadth_first(a).collect::<Vec<_>>(), &[b, c, d]);
+    /// ```
+    pub fn descendants_depth_first(&self, entity: Entity) -> DescendantsDepthFirst<'_> {
+        DescendantsDepthFirst::new(self, entity)
+    }
+
+    /// Iterate over the descendants of `entity`, visiting all of its children before any
+    /// grandchildren
+    pub fn descendants_breadth_first(&self, entity: Entity) -> DescendantsBreadthFirst<'_> {
+        DescendantsBreadthFirst::new(self, entity)
+    }
+
+    /// Relate `source` to `target`, replacing any existing `R` relation of `source`
+    ///
+    /// The relation is stored as a `Relation<R>` component of `source`.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// struct DockedAt;
+    /// let mut world = World::new();
+    /// let ship = world.spawn(());
+    /// let station = world.spawn(());
+    /// world.relate(ship, station, DockedAt).unwrap();
+    /// assert_eq!(world.get::<Relation<DockedAt>>(ship).unwrap().target(), station);
+    /// assert_eq!(world.related_to::<DockedAt>(station).collect::<Vec<_>>(), &[ship]);
+    /// ```
+    pub fn relate<R: Component>(
+        &mut self,
+        source: Entity,
+        target: Entity,
+        relation: R,
+    ) -> Result<(), NoSuchEntity> {
+        if !self.contains(source) || !self.contains(target) {
+            return Err(NoSuchEntity);
+        }
+        // Inserting the component maintains the index
+        self.relations
+            .entry(ComponentId::of::<Relation<R>>())
+            .or_insert_with(RelationIndex::new::<R>);
+        self.insert_one(source, Relation::new(target, relation))
+    }
+
+    /// Remove the `R` relation of `source`, returning its data
+    ///
+    /// Equivalent to `remove_one::<Relation<R>>`.
+    pub fn unrelate<R: Component>(&mut self, source: Entity) -> Result<R, ComponentError> {
+        self.remove_one::<Relation<R>>(source)
+            .map(Relation::into_inner)
+    }
+
+    /// Entities having an `R` relation targeting `target`, in the order they were related
+    pub fn related_to<R: Component>(
+        &self,
+        target: Entity,
+    ) -> impl ExactSizeIterator<Item = Entity> + '_ {
+        self.relations
+            .get(&ComponentId::of::<Relation<R>>())
+            .map_or(&[][..], |x| x.sources(target))
+            .iter()
+            .copied()
+    }
+
+    /// Maintain the hierarchy and relations before the `removed` components are removed from
+    /// `entity`
+    fn unlink(&mut self, entity: Entity, removed: &[ComponentId]) {
+        let loc = match self.entities.get(entity) {
+            Ok(loc) if !loc.is_pending() => loc,
+            _ => return,
+        };
+        let archetype = &self.archetypes[loc.archetype as usize];
+        // Leave the hierarchy alone if the removal is going to fail
+        if !removed
+            .iter()
+            .all(|&id| archetype.has_component(id, loc.index))
+        {
+            return;
+        }
+        self.unlink_replaced(entity, removed);
+    }
+
+    /// Maintain the hierarchy and relations before any of `entity`'s components of the `ids`
+    /// types are dropped or replaced
+    fn unlink_replaced(&mut self, entity: Entity, ids: &[ComponentId]) {
+        if ids.contains(&ComponentId::of::<Parent>()) {
+            self.unlink_parent(entity);
+        }
+        if ids.contains(&ComponentId::of::<Children>()) {
+            self.unlink_children(entity);
+        }
+        for id in ids {
+            if let Some(index) = self.relations.get_mut(id) {
+                index.unlink(entity);
+            }
+        }
+    }
+
+    /// The types of `components`, if any of them maintain the hierarchy or relations
+    fn linked_ids(&self, components: &impl DynamicBundle) -> Option<Vec<ComponentId>> {
+        components.with_ids(|ids| {
+            if ids.iter().any(|&id| self.is_linked(id)) {
+                Some(ids.to_vec())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Maintain the hierarchy and relations after components of the `ids` types were added to
+    /// `entity`
+    ///
+    /// Links that can't be made, such as to despawned entities or that would form a cycle, are
+    /// dropped.
+    fn link(&mut self, entity: Entity, ids: &[ComponentId]) {
+        for &id in ids {
+            if id == ComponentId::of::<Parent>() {
+                let (Parent(parent),) = self.remove_unlinked(entity, &[id]).unwrap();
+                let _ = self.set_parent(entity, parent);
+            } else if id == ComponentId::of::<Children>() {
+                let (Children(children),) = self.remove_unlinked(entity, &[id]).unwrap();
+                for child in children {
+                    let _ = self.set_parent(child, entity);
+                }
+            } else if let Some(index) = self.relations.get(&id) {
+                match index.target_of(self, entity) {
+                    Some(target) if self.contains(target) => {
+                        self.relations.get_mut(&id).unwrap().insert(entity, target);
+                    }
+                    _ => index.remover()(self, entity),
+                }
+            }
+        }
+    }
+
+    /// Remove `child` from its parent's `Children`, if it has a parent
+    fn unlink_parent(&mut self, child: Entity) {
+        let parent = match self.parent(child) {
+            Some(x) => x,
+            None => return,
+        };
+        let empty = match self.get_mut::<Children>(parent) {
+            Ok(mut children) => {
+                children.0.retain(|&x| x != child);
+                children.0.is_empty()
+            }
+            Err(_) => false,
+        };
+        if empty {
+            let removed = [ComponentId::of::<Children>()];
+            let _ = self.remove_unlinked::<(Children,)>(parent, &removed);
+        }
+    }
+
+    /// Remove the `Parent` of each of `parent`'s children, leaving its `Children` empty
+    fn unlink_children(&mut self, parent: Entity) {
+        let children = match self.get_mut::<Children>(parent) {
+            Ok(mut x) => mem::take(&mut x.0),
+            Err(_) => return,
+        };
+        let removed = [ComponentId::of::<Parent>()];
+        for child in children {
+            let _ = self.remove_unlinked::<(Parent,)>(child, &removed);
+        }
+    }
+
+    /// Forget the relations of `entity`, and remove every relation targeting it
+    fn unlink_relations(&mut self, entity: Entity) {
+        if self.relations.is_empty() {
+            return;
+        }
+        let mut sources = Vec::new();
+        for index in self.relations.values_mut() {
+            index.unlink(entity);
+            sources.push(index.unlink_target(entity));
+        }
+        for (remove, sources) in sources {
+            for source in sources {
+                remove(self, source);
+            }
+        }
+    }
+
+    /// Start recording which entities have their `T` component removed
+    ///
+    /// Removals by `despawn`, `remove`, `clear`, and replacement of an existing component by
+    /// `insert` are recorded, and can be inspected with `removed` until `clear_removed` is called.
+    pub fn track_removed<T: Component>(&mut self) {
+        self.removed.entry(ComponentId::of::<T>()).or_default();
+    }
+
+    /// Entities whose `T` component was removed since the last call to `clear_removed`
+    ///
+    /// Always empty unless `track_removed::<T>` was called first. An entity may appear more than
+    /// once, and may since have been despawned.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// world.track_removed::<i32>();
+    /// let a = world.spawn((123, true));
+    /// let b = world.spawn((456, false));
+    /// world.remove_one::<i32>(a).unwrap();
+    /// world.despawn(b).unwrap();
+    /// assert_eq!(world.removed::<i32>().collect::<Vec<_>>(), &[a, b]);
+    /// world.clear_removed();
+    /// assert_eq!(world.removed::<i32>().count(), 0);
+    /// ```
+    pub fn removed<T: Component>(&self) -> impl ExactSizeIterator<Item = Entity> + '_ {
+        self.removed
+            .get(&ComponentId::of::<T>())
+            .map_or(&[][..], |x| &x[..])
+            .iter()
+            .copied()
+    }
+
+    /// Forget all removals recorded for `removed`, without disabling tracking
+    pub fn clear_removed(&mut self) {
+        for x in self.removed.values_mut() {
+            x.clear();
+        }
+    }
+
+    /// Apply the operations recorded in `commands`, in order, leaving it empty for reuse
+    ///
+    /// Commands targeting entities that no longer exist, and removals of components that an entity
+    /// doesn't have, are skipped.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// let mut cmd = CommandBuffer::new();
+    /// let e = cmd.spawn(&world, (123, "abc"));
+    /// cmd.remove_one::<&str>(e);
+    /// world.run_commands(&mut cmd);
+    /// assert_eq!(*world.get::<i32>(e).unwrap(), 123);
+    /// assert!(world.get::<&str>(e).is_err());
+    /// ```
+    pub fn run_commands(&mut self, commands: &mut CommandBuffer) {
+        commands.run_on(self);
+    }
+
     /// Borrow the `T` component of `entity` without safety checks
     ///
     /// Should only be used as a building block for safe abstractions.
@@ -503,14 +1562,13 @@ impl World {
     /// same component of `entity` may be live simultaneous to the returned reference.
     pub unsafe fn get_unchecked<T: Component>(&self, entity: Entity) -> Result<&T, ComponentError> {
         let loc = self.entities.get(entity)?;
-        if loc.archetype == 0 {
+        if loc.is_pending() {
             return Err(MissingComponent::new::<T>().into());
         }
-        Ok(&*self.archetypes[loc.archetype as usize]
-            .get::<T>()
-            .ok_or_else(MissingComponent::new::<T>)?
-            .as_ptr()
-            .add(loc.index as usize))
+        let (target, _) = self.archetypes[loc.archetype as usize]
+            .get_at::<T>(loc.index)
+            .ok_or_else(MissingComponent::new::<T>)?;
+        Ok(&*target.as_ptr())
     }
 
     /// Uniquely borrow the `T` component of `entity` without safety checks
@@ -521,24 +1579,26 @@ impl World {
     ///
     /// `entity` must have been previously obtained from this `World`, and no borrow of the same
     /// component of `entity` may be live simultaneous to the returned reference.
+    #[allow(clippy::mut_from_ref)]
     pub unsafe fn get_unchecked_mut<T: Component>(
         &self,
         entity: Entity,
     ) -> Result<&mut T, ComponentError> {
         let loc = self.entities.get(entity)?;
-        if loc.archetype == 0 {
+        if loc.is_pending() {
             return Err(MissingComponent::new::<T>().into());
         }
-        Ok(&mut *self.archetypes[loc.archetype as usize]
-            .get::<T>()
-            .ok_or_else(MissingComponent::new::<T>)?
-            .as_ptr()
-            .add(loc.index as usize))
+        let archetype = &self.archetypes[loc.archetype as usize];
+        let (target, mutated) = archetype
+            .get_at::<T>(loc.index)
+            .ok_or_else(MissingComponent::new::<T>)?;
+        *mutated.as_ptr() = self.change_tick;
+        Ok(&mut *target.as_ptr())
     }
 
     /// Convert all reserved entities into empty entities that can be iterated and accessed
     ///
-    /// Invoked implicitly by `spawn`, `despawn`, `insert`, and `remove`.
+    /// Invoked implicitly by `spawn`, `despawn`, `insert`, `remove`, and `run_commands`.
     pub fn flush(&mut self) {
         let arch = &mut self.archetypes[0];
         for id in self.entities.flush() {
@@ -551,10 +1611,132 @@ impl World {
         self.entities.clear_reserved();
     }
 
+    /// The current change tick
+    ///
+    /// Adding a component or mutably accessing it records the current change tick, which can later
+    /// be compared against by the `Added`, `Mutated` and `Changed` query elements to find components
+    /// that changed.
+    pub fn change_tick(&self) -> u64 {
+        self.change_tick
+    }
+
+    /// Advance the change tick, e.g. once per frame
+    ///
+    /// Returns the previous tick. Changes made after this call can be found by passing it to
+    /// `QueryBorrow::since`, which is the default until the next call.
+    ///
+    /// # Example
+    /// ```
+    /// # use hecs::*;
+    /// let mut world = World::new();
+    /// let a = world.spawn((123,));
+    /// let b = world.spawn((456,));
+    /// let tick = world.increment_change_tick();
+    /// *world.get_mut::<i32>(a).unwrap() = 42;
+    /// world.increment_change_tick();
+    /// let changed = world.query::<Changed<i32>>()
+    ///     .since(tick)
+    ///     .iter()
+    ///     .map(|(e, _)| e)
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(changed, &[a]);
+    /// ```
+    pub fn increment_change_tick(&mut self) -> u64 {
+        self.change_tick += 1;
+        self.change_tick - 1
+    }
+
+    pub(crate) fn ticks(&self) -> Ticks {
+        Ticks {
+            current: self.change_tick,
+            since: self.change_tick - 1,
+        }
+    }
+
+    /// Uniquely identifies this world among all worlds created by the process
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub(crate) fn entities_meta(&self) -> &[EntityMeta] {
+        &self.entities.meta
+    }
+
+    pub(crate) fn archetypes_inner(&self) -> &[Archetype] {
+        &self.archetypes
+    }
+
+    /// The generation of every entity ID that has ever been allocated
+    pub(crate) fn generations(&self) -> impl ExactSizeIterator<Item = u32> + '_ {
+        self.entities.meta.iter().map(|x| x.generation)
+    }
+
+    /// Allocate rows for the restored entities `ids` in the archetype having exactly the sorted
+    /// component types `types`, recording their locations
+    ///
+    /// Returns the archetype and the index of the first row, every component of which must be
+    /// written immediately. Returns `None` without modifying the world if an ID is out of range of
+    /// `locations`, repeated, or already located.
+    pub(crate) fn allocate_restored(
+        &mut self,
+        types: Vec<crate::TypeInfo>,
+        ids: &[u32],
+        locations: &mut [Option<Location>],
+    ) -> Option<(&mut Archetype, u32)> {
+        use hashbrown::hash_map::Entry;
+
+        // Validate before touching the world, so no row is ever left uninitialized
+        for (i, &id) in ids.iter().enumerate() {
+            match locations.get_mut(id as usize) {
+                Some(x @ None) => {
+                    // Placeholder to detect repeats, overwritten below
+                    *x = Some(Location {
+                        archetype: 0,
+                        index: u32::MAX,
+                    });
+                }
+                _ => {
+                    for &id in &ids[..i] {
+                        locations[id as usize] = None;
+                    }
+                    return None;
+                }
+            }
+        }
+
+        let archetype_id = match self.index.entry(types.iter().map(|ty| ty.id()).collect()) {
+            Entry::Occupied(x) => *x.get(),
+            Entry::Vacant(x) => {
+                let id = self.archetypes.len() as u32;
+                self.archetypes.push(Archetype::new(types));
+                x.insert(id);
+                self.archetype_generation += 1;
+                id
+            }
+        };
+        let archetype = &mut self.archetypes[archetype_id as usize];
+        archetype.reserve(ids.len() as u32);
+        let base = archetype.len();
+        for &id in ids {
+            locations[id as usize] = Some(Location {
+                archetype: archetype_id,
+                index: unsafe { archetype.allocate(id) },
+            });
+        }
+        Some((archetype, base))
+    }
+
+    /// Replace the entity allocator of a world containing no reserved entities
+    ///
+    /// `locations` must describe every entity stored in an archetype.
+    pub(crate) fn restore_entities(&mut self, generations: &[u32], locations: &[Option<Location>]) {
+        self.entities = Entities::restore(generations, locations);
+    }
+
     /// Inspect the archetypes that entities are organized into
     ///
-    /// Useful for dynamically scheduling concurrent queries by checking borrows in advance. Does
-    /// not provide access to entities.
+    /// Useful for dynamically scheduling concurrent queries by checking borrows in advance, or for
+    /// examining how components are stored.
     pub fn archetypes(&self) -> impl ExactSizeIterator<Item = &'_ Archetype> + '_ {
         self.archetypes.iter()
     }
@@ -599,6 +1781,28 @@ impl<'a> IntoIterator for &'a World {
     }
 }
 
+/// Record the removal of every component of every entity in `archetype`
+fn record_removed(
+    removed: &mut HashMap<ComponentId, Vec<Entity>>,
+    meta: &[EntityMeta],
+    archetype: &Archetype,
+) {
+    let entity = |id: u32| Entity {
+        id,
+        generation: meta[id as usize].generation,
+    };
+    for ty in archetype.types() {
+        if let Some(removed) = removed.get_mut(&ty.id()) {
+            removed.extend(archetype.ids().iter().map(|&id| entity(id)));
+        }
+    }
+    for set in archetype.sparse_sets() {
+        if let Some(removed) = removed.get_mut(&set.ty().id()) {
+            removed.extend(set.entities().iter().map(|&id| entity(id)));
+        }
+    }
+}
+
 fn index2<T>(x: &mut [T], i: usize, j: usize) -> (&mut T, &mut T) {
     assert!(i != j);
     assert!(i < x.len());
@@ -607,6 +1811,28 @@ fn index2<T>(x: &mut [T], i: usize, j: usize) -> (&mut T, &mut T) {
     unsafe { (&mut *ptr.add(i), &mut *ptr.add(j)) }
 }
 
+/// A single component of a type that may only be known at runtime, moved out of `ptr` by `put`
+struct RawComponent {
+    ty: TypeInfo,
+    ptr: *mut u8,
+}
+
+impl DynamicBundle for RawComponent {
+    fn with_ids<T>(&self, f: impl FnOnce(&[ComponentId]) -> T) -> T {
+        f(&[self.ty.id()])
+    }
+
+    fn type_info(&self) -> Vec<TypeInfo> {
+        vec![self.ty]
+    }
+
+    unsafe fn put(self, mut f: impl FnMut(*mut u8, ComponentId, usize) -> bool) {
+        if !f(self.ptr, self.ty.id(), self.ty.layout().size()) {
+            self.ty.drop(self.ptr);
+        }
+    }
+}
+
 /// Errors that arise when accessing components
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum ComponentError {
@@ -641,6 +1867,51 @@ impl From<MissingComponent> for ComponentError {
     }
 }
 
+/// Errors that arise when attempting to borrow components with `World::try_get` and
+/// `World::try_get_mut`
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum TryGetError {
+    /// The entity was already despawned
+    NoSuchEntity,
+    /// The entity did not have a requested component
+    MissingComponent(MissingComponent),
+    /// The requested component was already borrowed in a conflicting manner
+    BorrowConflict(BorrowError),
+}
+
+#[cfg(feature = "std")]
+impl Error for TryGetError {}
+
+impl fmt::Display for TryGetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use TryGetError::*;
+        match *self {
+            NoSuchEntity => f.write_str("no such entity"),
+            MissingComponent(ref x) => x.fmt(f),
+            BorrowConflict(ref x) => x.fmt(f),
+        }
+    }
+}
+
+impl From<NoSuchEntity> for TryGetError {
+    fn from(NoSuchEntity: NoSuchEntity) -> Self {
+        TryGetError::NoSuchEntity
+    }
+}
+
+impl From<MissingComponent> for TryGetError {
+    fn from(x: MissingComponent) -> Self {
+        TryGetError::MissingComponent(x)
+    }
+}
+
+impl From<BorrowError> for TryGetError {
+    fn from(x: BorrowError) -> Self {
+        TryGetError::BorrowConflict(x)
+    }
+}
+
+#[cfg_attr(feature = "single_threaded", allow(dead_code))]
 mod atomic {
     /// Types that can be components, implemented automatically for all `Send + Sync + 'static` types
     ///
@@ -650,6 +1921,7 @@ mod atomic {
     impl<T: Send + Sync + 'static> Component for T {}
 }
 
+#[cfg_attr(not(feature = "single_threaded"), allow(dead_code))]
 mod single_threaded {
     /// Types that can be components, implemented automatically for all `'static` types
     ///
@@ -671,15 +1943,17 @@ pub struct Iter<'a> {
     entities: &'a Entities,
     current: Option<&'a Archetype>,
     index: u32,
+    tick: u64,
 }
 
 impl<'a> Iter<'a> {
-    fn new(archetypes: &'a [Archetype], entities: &'a Entities) -> Self {
+    fn new(archetypes: &'a [Archetype], entities: &'a Entities, tick: u64) -> Self {
         Self {
             archetypes: archetypes.iter(),
             entities,
             current: None,
             index: 0,
+            tick,
         }
     }
 }
@@ -697,7 +1971,7 @@ impl<'a> Iterator for Iter<'a> {
                     self.index = 0;
                 }
                 Some(current) => {
-                    if self.index == current.len() as u32 {
+                    if self.index == current.len() {
                         self.current = None;
                         continue;
                     }
@@ -709,7 +1983,7 @@ impl<'a> Iterator for Iter<'a> {
                             id,
                             generation: self.entities.meta[id as usize].generation,
                         },
-                        unsafe { EntityRef::new(current, index) },
+                        unsafe { EntityRef::new(current, index, self.tick) },
                     ));
                 }
             }
@@ -740,6 +2014,14 @@ impl<A: DynamicBundle> core::iter::FromIterator<A> for World {
     }
 }
 
+fn next_id() -> u64 {
+    // Starts at 1 so that 0 can never identify a world. Not 64 bits wide, since not every target
+    // has 64-bit atomics.
+    static ID: AtomicUsize = AtomicUsize::new(1);
+    ID.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| x.checked_add(1))
+        .expect("too many worlds created") as u64
+}
+
 /// Determines freshness of information derived from `World::archetypes`
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct ArchetypesGeneration(u64);
@@ -754,6 +2036,7 @@ where
     entities: &'a mut Entities,
     archetype_id: u32,
     archetype: &'a mut Archetype,
+    tick: u64,
 }
 
 impl<I> Drop for SpawnBatchIter<'_, I>
@@ -779,7 +2062,8 @@ where
         unsafe {
             let index = self.archetype.allocate(entity.id);
             components.put(|ptr, ty, size| {
-                self.archetype.put_dynamic(ptr, ty, size, index);
+                self.archetype
+                    .put_dynamic(ptr, ty, size, index, self.tick, 0);
                 true
             });
             self.entities.meta[entity.id as usize].location = Location {
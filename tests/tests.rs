@@ -12,6 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+// Some tests spell `flatten()` as `flat_map(|x| x)`
+#![allow(clippy::flat_map_identity)]
+
 use hecs::*;
 
 #[test]
@@ -130,6 +133,519 @@ fn build_entity() {
     assert_eq!(*world.get::<i32>(f).unwrap(), 456);
 }
 
+#[test]
+fn runtime_defined_components() {
+    use std::alloc::Layout;
+    use std::mem;
+    use std::sync::Arc;
+
+    unsafe fn drop_arc(x: *mut u8) {
+        x.cast::<Arc<()>>().drop_in_place();
+    }
+
+    let shared = TypeInfo::dynamic("shared", Layout::new::<Arc<()>>(), Some(drop_arc));
+    let number = TypeInfo::dynamic("number", Layout::new::<u16>(), None);
+    assert_ne!(shared.id(), number.id());
+    let arc = Arc::new(());
+
+    let mut world = World::new();
+    let mut builder = EntityBuilder::new();
+    let a = unsafe {
+        let mut x = arc.clone();
+        let mut y = 7u16;
+        builder
+            .add(true)
+            .add_raw(shared, (&mut x as *mut Arc<()>).cast())
+            .add_raw(number, (&mut y as *mut u16).cast());
+        mem::forget(x);
+        world.spawn(builder.build())
+    };
+    let b = world.spawn((false,));
+    unsafe {
+        let mut x = arc.clone();
+        world
+            .insert_raw(b, shared, (&mut x as *mut Arc<()>).cast())
+            .unwrap();
+        mem::forget(x);
+    }
+    assert_eq!(Arc::strong_count(&arc), 3);
+    assert!(*world.get::<bool>(a).unwrap());
+
+    unsafe {
+        let ptr = world.get_raw(a, number).unwrap().cast::<u16>();
+        assert_eq!(*ptr.as_ptr(), 7);
+        *ptr.as_ptr() = 8;
+    }
+    assert!(world.get_raw(b, number).is_err());
+    let numbers = world
+        .query_raw(number)
+        .map(|(e, ptr)| (e, unsafe { *ptr.cast::<u16>().as_ptr() }))
+        .collect::<Vec<_>>();
+    assert_eq!(numbers, &[(a, 8)]);
+    assert_eq!(world.query_raw(shared).count(), 2);
+
+    world.remove_raw(a, shared).unwrap();
+    assert_eq!(Arc::strong_count(&arc), 2);
+    assert!(world.remove_raw(a, shared).is_err());
+    assert!(world.get_raw(a, number).is_ok());
+    world.despawn(b).unwrap();
+    assert_eq!(Arc::strong_count(&arc), 1);
+}
+
+#[test]
+fn inspect_archetypes() {
+    let mut world = World::new();
+    let a = world.spawn((1u32, 2u8));
+    let b = world.spawn((3u32, 4u8));
+    world.spawn((5u32,));
+    let archetype = world
+        .archetypes()
+        .find(|x| x.has::<u8>() && x.has::<u32>())
+        .unwrap();
+    assert_eq!(archetype.len(), 2);
+    assert!(!archetype.is_empty());
+    assert!(archetype.capacity() >= 2);
+    assert_eq!(archetype.ids(), &[a.id(), b.id()]);
+    assert_eq!(
+        archetype
+            .types()
+            .iter()
+            .map(|ty| (ty.type_name(), ty.layout().size()))
+            .collect::<Vec<_>>(),
+        &[("u32", 4), ("u8", 1)]
+    );
+    assert_eq!(archetype.column_size(ComponentId::of::<u32>()), Some(8));
+    assert_eq!(archetype.column_size(ComponentId::of::<u8>()), Some(2));
+    assert_eq!(archetype.column_size(ComponentId::of::<bool>()), None);
+    assert!(!archetype.has_dynamic(ComponentId::of::<bool>()));
+
+    let column = archetype.get::<u32>().unwrap();
+    assert_eq!(*column, [1, 3]);
+    let copy = column.clone();
+    drop(column);
+    assert!(world.try_get_mut::<u32>(a).is_err());
+    drop(copy);
+    *world.get_mut::<u32>(a).unwrap() = 6;
+    assert_eq!(*archetype.get::<u32>().unwrap(), [6, 3]);
+}
+
+#[test]
+fn format_components() {
+    let registry = FormatRegistry::new()
+        .register_debug::<&str>()
+        .register_display::<i32>();
+    let mut world = World::new();
+    let a = world.spawn((123, "abc", true));
+    let b = world.spawn(());
+    let entity = world.entity(a).unwrap();
+
+    let mut types = entity
+        .component_types()
+        .map(|ty| (ty.type_name(), ty.id()))
+        .collect::<Vec<_>>();
+    types.sort();
+    assert_eq!(
+        types,
+        &[
+            ("&str", ComponentId::of::<&str>()),
+            ("bool", ComponentId::of::<bool>()),
+            ("i32", ComponentId::of::<i32>())
+        ]
+    );
+    assert_eq!(world.entity(b).unwrap().component_types().count(), 0);
+
+    let format = |id| {
+        registry
+            .component(entity, id)
+            .map(|x| (x.to_string(), format!("{:?}", x)))
+    };
+    assert_eq!(
+        format(ComponentId::of::<&str>()),
+        Some(("\"abc\"".into(), "\"abc\"".into()))
+    );
+    assert_eq!(
+        format(ComponentId::of::<i32>()),
+        Some(("123".into(), "123".into()))
+    );
+    assert_eq!(format(ComponentId::of::<bool>()), None);
+    assert_eq!(format(ComponentId::of::<f32>()), None);
+    assert_eq!(
+        format!("{:?}", registry.entity(world.entity(b).unwrap())),
+        "{}"
+    );
+}
+
+#[test]
+fn hierarchy() {
+    let mut world = World::new();
+    let root = world.spawn(("root",));
+    let a = world.spawn(("a",));
+    let b = world.spawn(("b",));
+    let c = world.spawn(("c",));
+    let d = world.spawn(("d",));
+    world.set_parent(a, root).unwrap();
+    world.set_parent(b, root).unwrap();
+    world.set_parent(c, a).unwrap();
+    world.set_parent(d, c).unwrap();
+    let children = |world: &World, x| {
+        world
+            .get::<Children>(x)
+            .map(|x| x.to_vec())
+            .unwrap_or_default()
+    };
+    assert_eq!(children(&world, root), &[a, b]);
+    assert_eq!(world.get::<Parent>(c).unwrap().entity(), a);
+    assert_eq!(
+        world.descendants_depth_first(root).collect::<Vec<_>>(),
+        &[a, c, d, b]
+    );
+    assert_eq!(
+        world.descendants_breadth_first(root).collect::<Vec<_>>(),
+        &[a, b, c, d]
+    );
+
+    assert_eq!(world.set_parent(root, d), Err(HierarchyError::Cycle));
+    assert_eq!(world.set_parent(a, a), Err(HierarchyError::Cycle));
+
+    // Reparenting moves the child between parents
+    world.set_parent(c, b).unwrap();
+    assert!(world.get::<Children>(a).is_err());
+    assert_eq!(children(&world, b), &[c]);
+
+    // Removing either side of the relationship updates the other
+    world.remove_one::<Parent>(b).unwrap();
+    assert_eq!(children(&world, root), &[a]);
+    let removed = world.remove_one::<Children>(b).unwrap();
+    assert!(removed.is_empty());
+    assert_eq!(world.parent(c), None);
+    assert_eq!(world.remove_parent(d), Ok(Some(c)));
+    assert_eq!(world.remove_parent(d), Ok(None));
+
+    // Despawning orphans children
+    world.set_parent(d, c).unwrap();
+    world.set_parent(c, a).unwrap();
+    world.despawn(c).unwrap();
+    assert_eq!(world.parent(d), None);
+    assert!(world.get::<Children>(a).is_err());
+
+    world.set_parent(b, a).unwrap();
+    world.set_parent(d, b).unwrap();
+    world.despawn_recursive(a).unwrap();
+    for x in [a, b, d] {
+        assert!(!world.contains(x));
+    }
+    assert!(world.get::<Children>(root).is_err());
+    assert!(world.contains(root));
+
+    // Inserted components are linked as by `set_parent`
+    let e = world.spawn(("e",));
+    let f = world.spawn(("f",));
+    let g = world.spawn(("g",));
+    world.set_parent(f, e).unwrap();
+    let parent = world.remove_one::<Parent>(f).unwrap();
+    world.insert_one(g, parent).unwrap();
+    assert_eq!(world.parent(g), Some(e));
+    assert_eq!(children(&world, e), &[g]);
+    let stale = Parent::clone(&world.get::<Parent>(g).unwrap());
+    world.despawn(e).unwrap();
+    assert_eq!(world.parent(g), None);
+    let h = world.spawn(("h", stale));
+    assert_eq!(world.parent(h), None);
+
+    world.set_parent(f, g).unwrap();
+    let kids = Children::clone(&world.get::<Children>(g).unwrap());
+    world.insert_one(h, kids).unwrap();
+    assert_eq!(world.parent(f), Some(h));
+    assert!(world.get::<Children>(g).is_err());
+    let cycle = Children::clone(&world.get::<Children>(h).unwrap());
+    world.insert_one(f, cycle).unwrap();
+    assert!(world.get::<Children>(f).is_err());
+    assert_eq!(world.parent(f), Some(h));
+
+    let registry = CloneRegistry::new()
+        .register::<&str>()
+        .register::<Parent>()
+        .register::<Children>();
+    let clone = world.try_clone(&registry).unwrap();
+    assert_eq!(clone.parent(f), Some(h));
+}
+
+#[test]
+fn relations() {
+    #[derive(Clone)]
+    struct Likes(u32);
+    struct Targets;
+    let mut world = World::new();
+    let a = world.spawn(("a",));
+    let b = world.spawn(("b",));
+    let c = world.spawn(("c",));
+    world.relate(a, c, Likes(1)).unwrap();
+    world.relate(b, c, Likes(2)).unwrap();
+    world.relate(a, b, Targets).unwrap();
+    assert_eq!(world.related_to::<Likes>(c).collect::<Vec<_>>(), &[a, b]);
+    assert_eq!(world.related_to::<Targets>(b).collect::<Vec<_>>(), &[a]);
+    assert_eq!(world.related_to::<Targets>(c).count(), 0);
+
+    let mut targets = world
+        .query::<(&&str, Relation<Likes>)>()
+        .iter()
+        .map(|(_, (&name, target))| (name, target))
+        .collect::<Vec<_>>();
+    targets.sort_unstable();
+    assert_eq!(targets, &[("a", c), ("b", c)]);
+    for (_, likes) in world.query_mut::<&mut Relation<Likes>>() {
+        likes.0 += 10;
+    }
+    assert_eq!(world.get::<Relation<Likes>>(b).unwrap().0, 12);
+
+    // Relating again replaces the previous relation
+    world.relate(b, a, Likes(3)).unwrap();
+    assert_eq!(world.related_to::<Likes>(c).collect::<Vec<_>>(), &[a]);
+    assert_eq!(world.related_to::<Likes>(a).collect::<Vec<_>>(), &[b]);
+
+    // Removing the component forgets the relation
+    assert_eq!(world.unrelate::<Likes>(a).unwrap().0, 11);
+    assert_eq!(world.related_to::<Likes>(c).count(), 0);
+    assert!(world.unrelate::<Likes>(a).is_err());
+    world.relate(c, b, Likes(4)).unwrap();
+    world.remove_one::<Relation<Likes>>(c).unwrap();
+    assert_eq!(world.related_to::<Likes>(b).count(), 0);
+
+    // Despawning a target removes relations targeting it
+    world.despawn(a).unwrap();
+    assert!(world.get::<Relation<Likes>>(b).is_err());
+    assert_eq!(world.related_to::<Likes>(a).count(), 0);
+    assert_eq!(*world.get::<&str>(b).unwrap(), "b");
+
+    // Despawning a source forgets its relations
+    world.relate(b, c, Targets).unwrap();
+    world.despawn(b).unwrap();
+    assert_eq!(world.related_to::<Targets>(c).count(), 0);
+    assert_eq!(world.relate(c, b, Targets), Err(NoSuchEntity));
+
+    // Inserted relations are indexed
+    let d = world.spawn(("d",));
+    world.relate(c, d, Likes(5)).unwrap();
+    let likes = world.remove_one::<Relation<Likes>>(c).unwrap();
+    let e = world.spawn(("e", likes));
+    assert_eq!(world.related_to::<Likes>(d).collect::<Vec<_>>(), &[e]);
+    let registry = CloneRegistry::new()
+        .register::<&str>()
+        .register::<Relation<Likes>>();
+    let clone = world.try_clone(&registry).unwrap();
+    assert_eq!(clone.related_to::<Likes>(d).collect::<Vec<_>>(), &[e]);
+    let stale = Relation::clone(&world.get::<Relation<Likes>>(e).unwrap());
+    world.despawn(d).unwrap();
+    assert!(world.get::<Relation<Likes>>(e).is_err());
+    world.insert_one(c, stale).unwrap();
+    assert!(world.get::<Relation<Likes>>(c).is_err());
+}
+
+#[test]
+fn sparse_storage() {
+    use std::sync::Arc;
+
+    #[derive(Clone)]
+    struct Stunned(Arc<()>);
+    let token = Arc::new(());
+    let mut world = World::new();
+    world.store_sparse::<Stunned>();
+    world.track_removed::<Stunned>();
+    let a = world.spawn((1, true));
+    let b = world.spawn((2, false));
+    let c = world.spawn((Stunned(token.clone()),));
+    let tick = world.increment_change_tick();
+
+    // Toggling a sparse component leaves the entity in its archetype
+    let archetypes = world.archetypes().len();
+    world.insert_one(a, Stunned(token.clone())).unwrap();
+    world.insert_one(b, Stunned(token.clone())).unwrap();
+    world.remove_one::<Stunned>(b).unwrap();
+    assert_eq!(world.archetypes().len(), archetypes);
+    assert!(world.get::<Stunned>(b).is_err());
+    assert_eq!(Arc::strong_count(&token), 3);
+
+    let stunned = world
+        .query::<(&i32, &Stunned)>()
+        .iter()
+        .map(|(e, (&x, _))| (e, x))
+        .collect::<Vec<_>>();
+    assert_eq!(stunned, &[(a, 1)]);
+    let mut free = world
+        .query::<Without<Stunned, &i32>>()
+        .iter()
+        .map(|(e, &x)| (e, x))
+        .collect::<Vec<_>>();
+    free.sort_by_key(|x| x.1);
+    assert_eq!(free, &[(b, 2)]);
+    let mut with = world
+        .query::<With<Stunned, ()>>()
+        .iter()
+        .map(|(e, ())| e)
+        .collect::<Vec<_>>();
+    with.sort();
+    assert_eq!(with, &[a, c]);
+    let mut optional = world
+        .query::<(&bool, Option<&Stunned>)>()
+        .iter()
+        .map(|(e, (_, s))| (e, s.is_some()))
+        .collect::<Vec<_>>();
+    optional.sort();
+    assert_eq!(optional, &[(a, true), (b, false)]);
+    let added = world
+        .query::<Added<Stunned>>()
+        .since(tick)
+        .iter()
+        .map(|(e, _)| e)
+        .collect::<Vec<_>>();
+    assert_eq!(added, &[a]);
+    let mut types = world
+        .entity(a)
+        .unwrap()
+        .component_types()
+        .map(|ty| ty.id())
+        .collect::<Vec<_>>();
+    types.sort();
+    let mut expected = vec![
+        ComponentId::of::<i32>(),
+        ComponentId::of::<bool>(),
+        ComponentId::of::<Stunned>(),
+    ];
+    expected.sort();
+    assert_eq!(types, expected);
+
+    // Dynamic queries see sparse components too
+    let query = DynamicQuery::new()
+        .read(ComponentId::of::<i32>())
+        .write(ComponentId::of::<Stunned>());
+    let mut dynamic = Vec::new();
+    for chunk in &mut query.query(&world) {
+        assert!(chunk.column(1).is_none());
+        for row in 0..chunk.len() {
+            assert!(chunk.get(row, 1).is_some());
+            dynamic.push(chunk.entity(row));
+        }
+    }
+    assert_eq!(dynamic, &[a]);
+    let query = DynamicQuery::new()
+        .read(ComponentId::of::<i32>())
+        .read_optional(ComponentId::of::<Stunned>())
+        .without(ComponentId::of::<Stunned>());
+    let mut dynamic = Vec::new();
+    query
+        .query(&world)
+        .for_each(|e, components| dynamic.push((e, components[1].is_some())));
+    assert_eq!(dynamic, &[(b, false)]);
+
+    // Sparse components follow their entity between archetypes
+    world.insert_one(a, "a").unwrap();
+    world.get_mut::<Stunned>(a).unwrap().0 = token.clone();
+    world.get_mut::<Stunned>(a).unwrap();
+    assert!(world
+        .query_one::<Mutated<Stunned>>(a)
+        .unwrap()
+        .since(tick)
+        .get()
+        .is_some());
+    assert!(world.get::<Stunned>(c).is_ok());
+    assert_eq!(Arc::strong_count(&token), 3);
+
+    let registry = CloneRegistry::new()
+        .register::<i32>()
+        .register::<bool>()
+        .register::<&str>()
+        .register::<Stunned>();
+    let clone = world.try_clone(&registry).unwrap();
+    assert!(clone.get::<Stunned>(a).is_ok());
+    assert_eq!(Arc::strong_count(&token), 5);
+    drop(clone);
+
+    world.despawn(a).unwrap();
+    world.despawn(c).unwrap();
+    assert_eq!(Arc::strong_count(&token), 1);
+    let mut removed = world.removed::<Stunned>().collect::<Vec<_>>();
+    removed.sort();
+    assert_eq!(removed, &[a, b, c]);
+}
+
+#[test]
+fn build_entity_bundles() {
+    let mut world = World::new();
+    let mut entity = EntityBuilder::new();
+    entity
+        .add_bundle((
+            1u8, 2u16, 3u32, 4u64, 5u128, 6usize, 7i8, 8i16, 9i32, 10i64, 11i128, 12isize, 13.0f32,
+            14.0f64, true,
+        ))
+        .add_bundle(('x', "abc", String::from("def"), (), Some(20u8)))
+        .add_bundle((0u8, "ignored"));
+    let e = world.spawn(entity.build());
+    assert_eq!(*world.get::<u8>(e).unwrap(), 1);
+    assert_eq!(*world.get::<&str>(e).unwrap(), "abc");
+
+    // Tuples nest to query more than 15 components at once
+    let mut query = world
+        .query_one::<(
+            (
+                &u8,
+                &u16,
+                &u32,
+                &u64,
+                &u128,
+                &usize,
+                &i8,
+                &i16,
+                &i32,
+                &i64,
+                &i128,
+                &isize,
+                &f32,
+                &f64,
+                &bool,
+            ),
+            (&char, &&str, &mut String, &(), &Option<u8>),
+        )>(e)
+        .unwrap();
+    let ((&a, .., &b), (&c, _, d, _, &f)) = query.get().unwrap();
+    assert_eq!((a, b, c, d.as_str(), f), (1, true, 'x', "def", Some(20)));
+}
+
+#[test]
+fn flatten_bundles() {
+    let mut world = World::new();
+    let e = world.spawn(Flatten((
+        (
+            1u8, 2u16, 3u32, 4u64, 5u128, 6usize, 7i8, 8i16, 9i32, 10i64, 11i128, 12isize, 13.0f32,
+            14.0f64, true,
+        ),
+        Flatten((('x',), ("abc", String::from("def")))),
+    )));
+    assert_eq!(*world.get::<u8>(e).unwrap(), 1);
+    assert!(*world.get::<bool>(e).unwrap());
+    assert_eq!(*world.get::<String>(e).unwrap(), "def");
+
+    assert!(world
+        .remove::<Flatten<((u8, char), (i32, Option<u8>))>>(e)
+        .is_err());
+    assert_eq!(*world.get::<u8>(e).unwrap(), 1);
+    let Flatten(((a, b), (c,))) = world.remove::<Flatten<((u8, char), (String,))>>(e).unwrap();
+    assert_eq!((a, b, c.as_str()), (1, 'x', "def"));
+    assert!(world.get::<u8>(e).is_err());
+    assert_eq!(*world.get::<&str>(e).unwrap(), "abc");
+
+    world
+        .insert(e, Flatten(((b'y', 'y'), ("ghi".to_string(),))))
+        .unwrap();
+    assert_eq!(*world.get::<char>(e).unwrap(), 'y');
+}
+
+#[test]
+#[should_panic(expected = "share a component type")]
+fn flatten_duplicate_types() {
+    let mut world = World::new();
+    world.spawn(Flatten(((1, true), (2,))));
+}
+
 #[test]
 fn dynamic_components() {
     let mut world = World::new();
@@ -162,6 +678,465 @@ fn dynamic_components() {
     );
 }
 
+#[test]
+fn command_buffer() {
+    let mut world = World::new();
+    let a = world.spawn(("abc", 123));
+    let b = world.spawn(("def", 456));
+    let mut cmd = CommandBuffer::new();
+    let mut entity = EntityBuilder::new();
+    entity.add(789).add(true);
+    let c = cmd.spawn(&world, entity.build());
+    for (id, &x) in &mut world.query::<&i32>() {
+        if x == 123 {
+            cmd.insert_one(id, 1.5f32);
+            cmd.remove_one::<&str>(id);
+        } else {
+            cmd.despawn(id);
+        }
+    }
+    cmd.insert_one(c, "ghi");
+    cmd.despawn(b);
+    world.run_commands(&mut cmd);
+    assert!(cmd.is_empty());
+    assert!(!world.contains(b));
+    assert_eq!(*world.get::<f32>(a).unwrap(), 1.5);
+    assert!(world.get::<&str>(a).is_err());
+    assert_eq!(*world.get::<i32>(c).unwrap(), 789);
+    assert!(*world.get::<bool>(c).unwrap());
+    assert_eq!(*world.get::<&str>(c).unwrap(), "ghi");
+}
+
+#[test]
+fn command_buffer_drop() {
+    use std::sync::Arc;
+
+    let mut world = World::new();
+    let a = world.spawn(());
+    let x = Arc::new(());
+    let mut cmd = CommandBuffer::new();
+    cmd.insert_one(a, x.clone());
+    cmd.spawn(&world, (x.clone(), 42u64));
+    drop(cmd);
+    assert_eq!(Arc::strong_count(&x), 1);
+
+    let mut cmd = CommandBuffer::new();
+    cmd.despawn(a);
+    cmd.insert_one(a, x.clone());
+    world.run_commands(&mut cmd);
+    assert_eq!(Arc::strong_count(&x), 1);
+}
+
+#[test]
+fn change_detection() {
+    let mut world = World::new();
+    let a = world.spawn((1, true));
+    let b = world.spawn((2, false));
+    let c = world.spawn((3,));
+    let tick = world.increment_change_tick();
+    for (_, (x, &flag)) in &mut world.query::<(&mut i32, &bool)>() {
+        if flag {
+            *x += 10;
+        }
+    }
+    world.insert_one(c, "abc").unwrap();
+    let d = world.spawn((4,));
+    // Moving entities between archetypes must preserve their ticks
+    world.despawn(b).unwrap();
+    world.insert_one(a, 1.5f32).unwrap();
+    world.increment_change_tick();
+
+    let mutated = world
+        .query::<Mutated<i32>>()
+        .since(tick)
+        .iter()
+        .map(|(e, &x)| (e, x))
+        .collect::<Vec<_>>();
+    assert_eq!(mutated, &[(a, 11)]);
+
+    let added = world
+        .query::<Added<i32>>()
+        .since(tick)
+        .iter()
+        .map(|(e, &x)| (e, x))
+        .collect::<Vec<_>>();
+    assert_eq!(added, &[(d, 4)]);
+
+    let mut changed = world
+        .query::<(Changed<i32>, Option<Added<&str>>)>()
+        .since(tick)
+        .iter()
+        .map(|(e, (&x, s))| (e, x, s.copied()))
+        .collect::<Vec<_>>();
+    changed.sort_by_key(|x| x.1);
+    assert_eq!(changed, &[(d, 4, None), (a, 11, None)]);
+
+    assert_eq!(world.query::<Changed<i32>>().iter().count(), 0);
+    assert!(world.query_one::<Added<&str>>(c).unwrap().get().is_none());
+    assert_eq!(
+        world.query_one::<Added<&str>>(c).unwrap().since(tick).get(),
+        Some(&"abc")
+    );
+}
+
+#[test]
+fn change_detection_skip() {
+    let mut world = World::new();
+    let a = world.spawn((1, 1u8));
+    let b = world.spawn((2, 2u8));
+    let tick = world.increment_change_tick();
+    *world.get_mut::<i32>(a).unwrap() = 10;
+    world.increment_change_tick();
+    for (_, (_, y)) in &mut world.query::<(Changed<i32>, &mut u8)>().since(tick) {
+        *y += 1;
+    }
+    assert_eq!(*world.get::<u8>(a).unwrap(), 2);
+    assert_eq!(*world.get::<u8>(b).unwrap(), 2);
+    let mutated = world
+        .query::<Mutated<u8>>()
+        .iter()
+        .map(|(e, _)| e)
+        .collect::<Vec<_>>();
+    assert_eq!(mutated, &[a]);
+}
+
+#[test]
+fn repeated_transitions() {
+    let mut world = World::new();
+    world.track_removed::<bool>();
+    let a = world.spawn((1,));
+    let b = world.spawn((2,));
+    let archetypes = world.archetypes().len();
+    for _ in 0..3 {
+        world.insert(a, (true, "a")).unwrap();
+        world.insert(b, (false, "b")).unwrap();
+        world.insert_one(a, false).unwrap();
+        assert_eq!(world.remove::<(bool, &str)>(b), Ok((false, "b")));
+        assert_eq!(
+            world.remove::<(bool, &str)>(b),
+            Err(ComponentError::MissingComponent(MissingComponent::new::<
+                bool,
+            >()))
+        );
+        assert_eq!(world.remove::<(&str, bool)>(a), Ok(("a", false)));
+    }
+    assert_eq!(world.archetypes().len(), archetypes + 1);
+    assert_eq!(*world.get::<i32>(a).unwrap(), 1);
+    assert_eq!(*world.get::<i32>(b).unwrap(), 2);
+    assert_eq!(world.removed::<bool>().filter(|&x| x == a).count(), 6);
+    assert_eq!(world.removed::<bool>().filter(|&x| x == b).count(), 3);
+}
+
+#[test]
+fn bulk_operations() {
+    use std::sync::Arc;
+
+    struct Frozen;
+    struct Likes;
+    let token = Arc::new(());
+    let mut world = World::new();
+    world.store_sparse::<bool>();
+    world.track_removed::<i32>();
+    world.track_removed::<Arc<()>>();
+    let a = world.spawn((1, Frozen, token.clone()));
+    let b = world.spawn((2, Frozen, token.clone()));
+    let c = world.spawn((3, Frozen, "c", true));
+    let d = world.spawn((4,));
+    let e = world.spawn((5, Frozen, "e"));
+    // Already in the target archetype, so moved entities are appended after it
+    let f = world.spawn((6, Frozen, token.clone(), 0.5f32));
+
+    world.insert_all::<&Frozen, _>((1.5f32, token.clone()));
+    assert_eq!(Arc::strong_count(&token), 6);
+    assert_eq!(world.removed::<Arc<()>>().collect::<Vec<_>>(), &[a, b, f]);
+    for entity in [a, b, c, e, f].iter().copied() {
+        assert_eq!(*world.get::<f32>(entity).unwrap(), 1.5);
+    }
+    assert!(world.get::<f32>(d).is_err());
+    assert!(*world.get::<bool>(c).unwrap());
+    assert_eq!(*world.get::<&str>(e).unwrap(), "e");
+    let mut ids = world
+        .query::<(&i32, &f32)>()
+        .iter()
+        .map(|(_, (&x, _))| x)
+        .collect::<Vec<_>>();
+    ids.sort_unstable();
+    assert_eq!(ids, &[1, 2, 3, 5, 6]);
+
+    // Entities not all matching are handled individually
+    world.remove_all::<With<bool, ()>, (f32, Arc<()>)>();
+    assert!(world.get::<f32>(c).is_err());
+    assert!(world.get::<Arc<()>>(c).is_err());
+    assert!(world.get::<f32>(e).is_ok());
+    assert_eq!(Arc::strong_count(&token), 5);
+    world.remove_all::<&Frozen, (f32, Arc<()>)>();
+    assert_eq!(Arc::strong_count(&token), 1);
+    assert!(world.get::<f32>(a).is_err());
+    assert_eq!(*world.get::<i32>(a).unwrap(), 1);
+    world.insert_one(a, "a").unwrap();
+    assert_eq!(*world.get::<&str>(a).unwrap(), "a");
+
+    // Relations and the hierarchy are maintained
+    let g = world.spawn((7,));
+    world.relate(g, b, Likes).unwrap();
+    world.set_parent(d, e).unwrap();
+    world.despawn_matching::<&Frozen>();
+    for entity in [a, b, c, e, f].iter().copied() {
+        assert!(!world.contains(entity));
+    }
+    assert!(world.contains(d));
+    assert!(world.get::<Relation<Likes>>(g).is_err());
+    assert_eq!(world.parent(d), None);
+    let mut removed = world.removed::<i32>().collect::<Vec<_>>();
+    removed.sort();
+    assert_eq!(removed, &[a, b, c, e, f]);
+    assert_eq!(world.iter().count(), 2);
+}
+
+#[test]
+fn insert_all_clone_panic() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CLONES: AtomicUsize = AtomicUsize::new(0);
+    struct Fragile;
+    impl Clone for Fragile {
+        fn clone(&self) -> Self {
+            if CLONES.fetch_add(1, Ordering::Relaxed) == 2 {
+                panic!("clone failed");
+            }
+            Fragile
+        }
+    }
+
+    let mut world = World::new();
+    for i in 0..4 {
+        world.spawn((i, true));
+    }
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        world.insert_all::<&bool, _>((Fragile,));
+    }));
+    assert!(result.is_err());
+    assert_eq!(world.query::<&Fragile>().iter().count(), 0);
+    let mut ids = world
+        .query::<&i32>()
+        .iter()
+        .map(|(_, &x)| x)
+        .collect::<Vec<_>>();
+    ids.sort_unstable();
+    assert_eq!(ids, &[0, 1, 2, 3]);
+}
+
+#[test]
+fn move_all_into_populated_archetype() {
+    let mut world = World::new();
+    for i in 0..10 {
+        world.spawn((i,));
+    }
+    for i in 10..110 {
+        world.spawn((i, true));
+    }
+    world.remove_all::<&bool, (bool,)>();
+    assert_eq!(world.query::<&bool>().iter().count(), 0);
+    let mut ids = world
+        .query::<&i32>()
+        .iter()
+        .map(|(_, &x)| x)
+        .collect::<Vec<_>>();
+    ids.sort_unstable();
+    assert_eq!(ids, (0..110).collect::<Vec<_>>());
+
+    for i in 110..120 {
+        world.spawn((i, 0.5f32));
+    }
+    world.insert_all::<(), _>((0.5f32,));
+    assert_eq!(world.query::<&f32>().iter().count(), 120);
+    for (_, (&i, &x)) in world.query::<(&i32, &f32)>().iter() {
+        assert!(i < 120);
+        assert_eq!(x, 0.5);
+    }
+}
+
+#[test]
+fn removal_tracking() {
+    let mut world = World::new();
+    world.track_removed::<i32>();
+    let a = world.spawn((1, true));
+    let b = world.spawn((2, false));
+    let c = world.spawn((3,));
+    let d = world.spawn((true,));
+    assert_eq!(world.removed::<i32>().len(), 0);
+    world.insert_one(a, 10).unwrap();
+    world.remove::<(i32, bool)>(b).unwrap();
+    world.despawn(d).unwrap();
+    world.remove_one::<bool>(a).unwrap();
+    assert!(world.remove_one::<bool>(c).is_err());
+    assert_eq!(world.removed::<i32>().collect::<Vec<_>>(), &[a, b]);
+    assert_eq!(world.removed::<bool>().count(), 0);
+    world.clear_removed();
+    world.clear();
+    let mut removed = world.removed::<i32>().collect::<Vec<_>>();
+    removed.sort();
+    assert_eq!(removed, &[a, c]);
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn serialize_roundtrip() {
+    use hecs::serialize::Registry;
+
+    let registry = Registry::new()
+        .register::<i32>("i32")
+        .register::<bool>("bool")
+        .register::<String>("string")
+        .register::<u8>("u8");
+    let mut world = World::new();
+    let a = world.spawn((1, true));
+    let b = world.spawn((2, "b".to_string()));
+    let c = world.spawn(());
+    let d = world.spawn((4, false));
+    world.despawn(b).unwrap();
+
+    let json = serde_json::to_string(&registry.serialize(&world)).unwrap();
+    let mut world = registry
+        .deserialize(&mut serde_json::Deserializer::from_str(&json))
+        .unwrap();
+    assert_eq!(world.iter().count(), 3);
+    assert!(!world.contains(b));
+    assert!(world.contains(c));
+    assert_eq!(*world.get::<i32>(a).unwrap(), 1);
+    assert!(*world.get::<bool>(a).unwrap());
+    assert_eq!(*world.get::<i32>(d).unwrap(), 4);
+    assert!(world.get::<String>(d).is_err());
+    let e = world.spawn((5,));
+    assert_eq!(e.id(), b.id());
+    assert_ne!(e, b);
+
+    world.insert_one(a, 1.0f32).unwrap();
+    assert!(serde_json::to_string(&registry.serialize(&world)).is_err());
+    assert!(registry
+        .deserialize(&mut serde_json::Deserializer::from_str(
+            r#"[[0],[[["i32"],[0,0],[[1,2]]]]]"#
+        ))
+        .is_err());
+
+    world.remove_one::<f32>(a).unwrap();
+    world.store_sparse::<u8>();
+    world.insert_one(d, 5u8).unwrap();
+    assert!(serde_json::to_string(&registry.serialize(&world)).is_err());
+}
+
+#[test]
+fn clone_world() {
+    let registry = CloneRegistry::new().register::<i32>().register::<String>();
+    let mut world = World::new();
+    let a = world.spawn((1, "a".to_string()));
+    let b = world.spawn((2,));
+    world.despawn(b).unwrap();
+    let c = world.reserve_entity();
+
+    let mut clone = world.try_clone(&registry).unwrap();
+    world.get_mut::<String>(a).unwrap().push('!');
+    assert_eq!(*clone.get::<String>(a).unwrap(), "a");
+    assert!(!clone.contains(b));
+    assert!(clone.contains(c));
+    // Both allocators continue from the same state
+    assert_eq!(world.spawn(()), clone.spawn(()));
+
+    world.insert_one(a, true).unwrap();
+    let err = world.try_clone(&registry).err().unwrap();
+    assert_eq!(err.type_name(), "bool");
+}
+
+#[test]
+fn clone_world_panic() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    struct Fragile;
+    impl Clone for Fragile {
+        fn clone(&self) -> Self {
+            panic!("clone failed");
+        }
+    }
+    #[derive(Clone)]
+    struct Sparse(Fragile);
+
+    let registry = CloneRegistry::new()
+        .register::<Fragile>()
+        .register::<Sparse>();
+    let mut world = World::new();
+    world.store_sparse::<Sparse>();
+    let a = world.spawn((Fragile,));
+    let b = world.spawn((Sparse(Fragile),));
+    let result = catch_unwind(AssertUnwindSafe(|| world.try_clone(&registry)));
+    assert!(result.is_err());
+    // Borrows taken for cloning are released despite the panic
+    world.get_mut::<Fragile>(a).unwrap();
+
+    world.despawn(a).unwrap();
+    let result = catch_unwind(AssertUnwindSafe(|| world.try_clone(&registry)));
+    assert!(result.is_err());
+    world.get_mut::<Sparse>(b).unwrap();
+}
+
+#[test]
+fn snapshot_roundtrip() {
+    use hecs::snapshot::{Registry, SnapshotError};
+
+    let registry = Registry::new()
+        .register::<u32>("u32")
+        .register::<[f32; 3]>("position");
+    let mut world = World::new();
+    let a = world.spawn((1u32, [1.0f32, 2.0, 3.0]));
+    let b = world.spawn((2u32,));
+    let c = world.spawn(());
+    let d = world.spawn((4u32,));
+    world.despawn(b).unwrap();
+
+    let data = registry.save(&world).unwrap();
+    let mut loaded = registry.load(&data).unwrap();
+    assert_eq!(loaded.iter().count(), 3);
+    assert!(!loaded.contains(b));
+    assert!(loaded.contains(c));
+    assert_eq!(*loaded.get::<u32>(a).unwrap(), 1);
+    assert_eq!(*loaded.get::<[f32; 3]>(a).unwrap(), [1.0, 2.0, 3.0]);
+    assert_eq!(*loaded.get::<u32>(d).unwrap(), 4);
+    let e = loaded.spawn((5u32,));
+    assert_eq!(e.id(), b.id());
+    assert_ne!(e, b);
+
+    let mut corrupted = data.clone();
+    *corrupted.last_mut().unwrap() ^= 1;
+    assert_eq!(
+        registry.load(&corrupted).err(),
+        Some(SnapshotError::ChecksumMismatch)
+    );
+    assert_eq!(
+        registry.load(&data[..data.len() - 1]).err(),
+        Some(SnapshotError::Corrupt)
+    );
+    assert_eq!(
+        registry.load(&data[..4]).err(),
+        Some(SnapshotError::InvalidHeader)
+    );
+    assert_eq!(
+        Registry::new().register::<u32>("u32").load(&data).err(),
+        Some(SnapshotError::UnknownComponent("position".into()))
+    );
+    world.insert_one(a, 1.5f64).unwrap();
+    assert_eq!(
+        registry.save(&world).err(),
+        Some(SnapshotError::UnregisteredComponent)
+    );
+    world.remove_one::<f64>(a).unwrap();
+    world.store_sparse::<u16>();
+    world.insert_one(d, 5u16).unwrap();
+    assert_eq!(
+        registry.save(&world).err(),
+        Some(SnapshotError::SparseComponent)
+    );
+}
+
 #[test]
 #[should_panic(expected = "already borrowed")]
 fn illegal_borrow() {
@@ -210,6 +1185,310 @@ fn illegal_random_access() {
     world.get::<i32>(e).unwrap();
 }
 
+#[test]
+fn query_size_hint() {
+    let mut world = World::new();
+    world.store_sparse::<&str>();
+    let a = world.spawn((1, true));
+    world.spawn((2, true));
+    world.spawn((3,));
+    let tick = world.increment_change_tick();
+    *world.get_mut::<i32>(a).unwrap() += 1;
+    world.insert_one(a, "a").unwrap();
+
+    let mut query = world.query::<&i32>();
+    let mut iter = query.iter();
+    assert_eq!(iter.size_hint(), (3, Some(3)));
+    iter.next();
+    assert_eq!(iter.size_hint(), (2, Some(2)));
+    drop(query);
+
+    // Per-row filters only bound the count
+    let mut query = world.query::<Mutated<i32>>().since(tick);
+    let iter = query.iter();
+    assert_eq!(iter.size_hint(), (0, Some(3)));
+    assert_eq!(iter.count(), 1);
+    drop(query);
+    assert_eq!(
+        world.query::<(&bool, Option<&&str>)>().iter().size_hint(),
+        (2, Some(2))
+    );
+    assert_eq!(
+        world.query::<Without<&str, &i32>>().iter().size_hint(),
+        (1, Some(3))
+    );
+    let mut query = PreparedQuery::<With<&str, &bool>>::default();
+    assert_eq!(query.query(&world).iter().size_hint(), (0, Some(2)));
+}
+
+#[test]
+fn query_mut() {
+    let mut world = World::new();
+    let a = world.spawn((1, true));
+    let b = world.spawn((2, false));
+    let c = world.spawn((3,));
+    let tick = world.increment_change_tick();
+    let mut query = world.query_mut::<(&mut i32, Option<&bool>)>();
+    assert_eq!(query.size_hint(), (3, Some(3)));
+    for (_, (x, flag)) in &mut query {
+        if flag == Some(&true) {
+            *x *= 10;
+        }
+    }
+    assert_eq!(*world.get::<i32>(a).unwrap(), 10);
+    assert_eq!(*world.get::<i32>(b).unwrap(), 2);
+    let mutated = world
+        .query::<Mutated<i32>>()
+        .since(tick)
+        .iter()
+        .map(|(e, _)| e)
+        .collect::<Vec<_>>();
+    assert_eq!(mutated.len(), 3);
+
+    *world.query_one_mut::<&mut i32>(c).unwrap() += 1;
+    assert_eq!(*world.get::<i32>(c).unwrap(), 4);
+    assert_eq!(
+        world.query_one_mut::<&bool>(c).err(),
+        Some(QueryOneError::Unsatisfied)
+    );
+    world.despawn(c).unwrap();
+    assert_eq!(
+        world.query_one_mut::<&i32>(c).err(),
+        Some(QueryOneError::NoSuchEntity)
+    );
+}
+
+#[test]
+#[should_panic(expected = "query violates a unique borrow")]
+fn query_mut_alias() {
+    let mut world = World::new();
+    world.query_mut::<(&mut i32, &bool)>();
+    world.query_mut::<(&mut i32, &bool)>();
+    // Rejected queries aren't mistaken for checked ones on later uses
+    let first = std::panic::catch_unwind(|| {
+        World::new().query_mut::<(&mut i32, Option<&i32>)>();
+    });
+    assert!(first.is_err());
+    world.query_mut::<(&mut i32, Option<&i32>)>();
+}
+
+#[test]
+fn prepared_query() {
+    let mut world = World::new();
+    let a = world.spawn((1, true));
+    let b = world.spawn((2, "abc"));
+    let mut query = PreparedQuery::<(&mut i32, Option<&bool>)>::new();
+    for (_, (x, flag)) in query.query(&world).iter() {
+        if flag.is_some() {
+            *x *= 10;
+        }
+    }
+    assert_eq!(*world.get::<i32>(a).unwrap(), 10);
+    assert_eq!(*world.get::<i32>(b).unwrap(), 2);
+
+    // Archetypes added after preparation are picked up
+    let c = world.spawn((3, 4.0f32));
+    world.spawn((true,));
+    let mut entities = query
+        .query_mut(&mut world)
+        .map(|(e, (&mut x, _))| (e, x))
+        .collect::<Vec<_>>();
+    entities.sort_by_key(|&(_, x)| x);
+    assert_eq!(entities, &[(b, 2), (c, 3), (a, 10)]);
+    assert_eq!(query.query_mut(&mut world).size_hint(), (3, Some(3)));
+
+    // Switching worlds discards the cache
+    let mut other = World::new();
+    let d = other.spawn((5,));
+    let entities = query
+        .query(&other)
+        .iter()
+        .map(|(e, (&mut x, _))| (e, x))
+        .collect::<Vec<_>>();
+    assert_eq!(entities, &[(d, 5)]);
+}
+
+#[test]
+#[should_panic(expected = "already borrowed")]
+fn prepared_query_borrow_conflict() {
+    let mut world = World::new();
+    let a = world.spawn((1,));
+    let mut query = PreparedQuery::<&mut i32>::new();
+    let _x = world.get::<i32>(a).unwrap();
+    query.query(&world).iter();
+}
+
+#[test]
+fn dynamic_query() {
+    let mut world = World::new();
+    let a = world.spawn((1, true));
+    let b = world.spawn((2, "abc"));
+    let c = world.spawn((3, true, 'x'));
+    world.spawn((4.0f32,));
+    let query = DynamicQuery::new()
+        .write(ComponentId::of::<i32>())
+        .read_optional(ComponentId::of::<bool>())
+        .without(ComponentId::of::<char>());
+
+    let mut entities = Vec::new();
+    query.query(&world).for_each(|e, components| unsafe {
+        let x = components[0].unwrap().cast::<i32>().as_ptr();
+        *x *= 10;
+        entities.push((e, *x, components[1].map(|x| *x.cast::<bool>().as_ptr())));
+    });
+    entities.sort_by_key(|&(_, x, _)| x);
+    assert_eq!(entities, &[(a, 10, Some(true)), (b, 20, None)]);
+    assert_eq!(*world.get::<i32>(c).unwrap(), 3);
+
+    let mut borrow = query.query(&world);
+    let mut total = 0;
+    for chunk in &mut borrow {
+        let column = chunk.column(0).unwrap();
+        assert_eq!(unsafe { column.as_ref() }.len(), 4 * chunk.len());
+        for row in 0..chunk.len() {
+            total += unsafe { *chunk.get(row, 0).unwrap().cast::<i32>().as_ptr() };
+        }
+    }
+    assert_eq!(total, 30);
+    assert!(world.try_get::<i32>(a).is_err());
+    drop(borrow);
+    assert!(world.try_get::<i32>(a).is_ok());
+}
+
+#[test]
+#[should_panic(expected = "already borrowed")]
+fn dynamic_query_borrow_conflict() {
+    let mut world = World::new();
+    let a = world.spawn((1,));
+    let query = DynamicQuery::new().write(ComponentId::of::<i32>());
+    let _x = world.get::<i32>(a).unwrap();
+    query.query(&world).iter();
+}
+
+#[test]
+fn or_query() {
+    let mut world = World::new();
+    let a = world.spawn((1, true));
+    let b = world.spawn((2, "abc"));
+    let c = world.spawn((3, true, "def"));
+    world.spawn((4.0f32,));
+    let mut entities = world
+        .query::<(&i32, Or<(&bool, &&str)>)>()
+        .iter()
+        .map(|(e, (&x, (flag, name)))| (e, x, flag.copied(), name.copied()))
+        .collect::<Vec<_>>();
+    entities.sort_by_key(|&(_, x, _, _)| x);
+    assert_eq!(
+        entities,
+        &[
+            (a, 1, Some(true), None),
+            (b, 2, None, Some("abc")),
+            (c, 3, Some(true), Some("def")),
+        ]
+    );
+
+    // Only the matching subset of an archetype's components is borrowed
+    let _flag = world.get_mut::<bool>(a).unwrap();
+    assert_eq!(
+        world
+            .query::<Or<(&mut i32, &bool)>>()
+            .try_iter()
+            .err()
+            .unwrap()
+            .conflict(),
+        Access::Write
+    );
+    assert_eq!(
+        world.query::<Or<(&mut f32, (&i32, &f32))>>().iter().count(),
+        1
+    );
+    for archetype in world.archetypes() {
+        let expected = match (archetype.access::<&i32>(), archetype.access::<&f32>()) {
+            (Some(_), _) => Some(Access::Write),
+            (None, Some(_)) => Some(Access::Read),
+            (None, None) => None,
+        };
+        assert_eq!(archetype.access::<Or<(&mut i32, &f32)>>(), expected);
+    }
+}
+
+#[test]
+fn or_query_changes() {
+    let mut world = World::new();
+    let a = world.spawn((1, true));
+    let b = world.spawn((2, false));
+    let tick = world.increment_change_tick();
+    *world.get_mut::<i32>(a).unwrap() = 10;
+    *world.get_mut::<bool>(b).unwrap() = true;
+    world.spawn((3,));
+    let mut entities = world
+        .query::<Or<(Mutated<i32>, Mutated<bool>)>>()
+        .since(tick)
+        .iter()
+        .map(|(e, (x, flag))| (e, x.copied(), flag.copied()))
+        .collect::<Vec<_>>();
+    entities.sort();
+    assert_eq!(entities, &[(a, Some(10), None), (b, None, Some(true))]);
+}
+
+#[test]
+fn entity_query() {
+    let mut world = World::new();
+    let a = world.spawn((1, true));
+    let b = world.spawn((2,));
+    world.despawn(b).unwrap();
+    let b = world.spawn((3,));
+    for (e, (inner, x)) in world.query::<(Entity, &i32)>().iter() {
+        assert_eq!(e, inner);
+        assert_eq!(*world.get::<i32>(e).unwrap(), *x);
+    }
+    let mut entities = world
+        .query_mut::<(Entity, Or<(&bool, Satisfies<&bool>)>)>()
+        .map(|(_, (e, (flag, has_flag)))| (e, flag.copied(), has_flag))
+        .collect::<Vec<_>>();
+    entities.sort();
+    assert_eq!(
+        entities,
+        &[(a, Some(true), Some(true)), (b, None, Some(false))]
+    );
+    let (e, _) = world.query_one_mut::<(Entity, Satisfies<&i32>)>(b).unwrap();
+    assert_eq!(e, b);
+}
+
+#[test]
+fn try_borrow() {
+    let mut world = World::new();
+    let e = world.spawn(("abc", 123));
+    let borrow = world.get_mut::<i32>(e).unwrap();
+    let err = match world.try_get::<i32>(e) {
+        Err(TryGetError::BorrowConflict(err)) => err,
+        _ => panic!("expected a borrow conflict"),
+    };
+    assert_eq!(err.type_name(), "i32");
+    assert_eq!(err.conflict(), Access::Write);
+    assert!(world.try_get::<&str>(e).is_ok());
+    assert!(world.try_get::<bool>(e).is_err());
+
+    // The `&str` borrow acquired before the conflict must be rolled back
+    let mut query = world.query::<(&mut &str, &i32)>();
+    assert_eq!(query.try_iter().err(), Some(err));
+    drop(query);
+    assert!(world.try_get_mut::<&str>(e).is_ok());
+
+    let entity = world.entity(e).unwrap();
+    assert!(entity.try_get_mut::<i32>().is_err());
+    drop(borrow);
+    assert!(entity.try_get_mut::<i32>().unwrap().is_some());
+    assert_eq!(
+        world
+            .query::<(&mut &str, &i32)>()
+            .try_iter()
+            .unwrap()
+            .count(),
+        1
+    );
+}
+
 #[test]
 #[cfg(feature = "macros")]
 fn derived_bundle() {
@@ -225,6 +1504,45 @@ fn derived_bundle() {
     assert_eq!(*world.get::<f64>(e).unwrap(), 1.0);
 }
 
+#[test]
+#[cfg(feature = "macros")]
+fn derived_generic_bundle() {
+    #[derive(Bundle)]
+    struct Tagged<T> {
+        inner: T,
+        tag: &'static str,
+    }
+
+    #[derive(Bundle)]
+    struct Empty;
+
+    let mut world = World::new();
+    let a = world.spawn(Tagged {
+        inner: 42,
+        tag: "a",
+    });
+    let b = world.spawn(Tagged {
+        inner: true,
+        tag: "b",
+    });
+    world
+        .insert(
+            b,
+            Tagged {
+                inner: 1.0f32,
+                tag: "c",
+            },
+        )
+        .unwrap();
+    world.spawn(Empty);
+    assert_eq!(*world.get::<i32>(a).unwrap(), 42);
+    assert!(*world.get::<bool>(b).unwrap());
+    assert_eq!(*world.get::<&str>(b).unwrap(), "c");
+    let removed = world.remove::<Tagged<f32>>(b).unwrap();
+    assert_eq!((removed.inner, removed.tag), (1.0, "c"));
+    assert!(world.get::<&str>(b).is_err());
+}
+
 #[test]
 #[cfg(feature = "macros")]
 #[should_panic(expected = "each type must occur at most once")]
@@ -239,6 +1557,85 @@ fn bad_bundle_derive() {
     world.spawn(Foo { x: 42, y: 42 });
 }
 
+#[test]
+#[cfg(feature = "macros")]
+#[should_panic(expected = "each type must occur at most once")]
+fn bad_generic_bundle_derive() {
+    #[derive(Bundle)]
+    struct Pair<T> {
+        x: T,
+        y: i32,
+    }
+
+    let mut world = World::new();
+    world.spawn(Pair { x: true, y: 42 });
+    world.spawn(Pair { x: 42, y: 42 });
+}
+
+#[test]
+#[cfg(feature = "macros")]
+fn derived_query() {
+    #[derive(Query, Debug, PartialEq)]
+    struct Foo<'a> {
+        x: &'a i32,
+        #[without(&'static str)]
+        y: &'a mut f64,
+        z: Option<&'a bool>,
+    }
+
+    #[derive(Query, Debug, PartialEq)]
+    struct Bar<'a>(Entity, #[with(bool)] &'a i32);
+
+    let mut world = World::new();
+    let a = world.spawn((1, 1.0f64));
+    let b = world.spawn((2, 2.0f64, true));
+    world.spawn((3, 3.0f64, "abc"));
+    for (_, foo) in world.query::<Foo>().iter() {
+        *foo.y += 1.0;
+    }
+    let mut results = world
+        .query::<Foo>()
+        .iter()
+        .map(|(e, _)| e)
+        .collect::<Vec<_>>();
+    results.sort();
+    assert_eq!(results, &[a, b]);
+    assert_eq!(
+        world.query_one_mut::<Foo>(b).unwrap(),
+        Foo {
+            x: &2,
+            y: &mut 3.0,
+            z: Some(&true)
+        }
+    );
+    let bars = world.query_mut::<Bar>().map(|(_, x)| x).collect::<Vec<_>>();
+    assert_eq!(bars, &[Bar(b, &2)]);
+
+    let _x = world.get::<i32>(a).unwrap();
+    assert_eq!(
+        world
+            .query::<(Foo, &mut bool)>()
+            .try_iter()
+            .err()
+            .unwrap()
+            .conflict(),
+        Access::Read
+    );
+}
+
+#[test]
+#[cfg(feature = "macros")]
+#[should_panic(expected = "query violates a unique borrow")]
+fn derived_query_alias() {
+    #[derive(Query)]
+    struct Foo<'a> {
+        _x: &'a i32,
+        _y: &'a mut i32,
+    }
+
+    World::new().query_mut::<Foo>();
+}
+
 #[test]
 #[cfg_attr(miri, ignore)]
 fn spawn_many() {
@@ -330,6 +1727,30 @@ fn query_batched() {
     assert!(entities.contains(&c));
 }
 
+#[test]
+#[cfg(feature = "rayon")]
+fn query_par_iter() {
+    use rayon::iter::ParallelIterator;
+
+    let mut world = World::new();
+    let entities = world
+        .spawn_batch((0..10_000).map(|i| (i, true)))
+        .collect::<Vec<_>>();
+    world.spawn_batch((0..1_000).map(|i| (i, 'a')));
+    world
+        .query::<(&mut i32, &bool)>()
+        .par_for_each(|(_, (i, _))| *i *= 2);
+    assert_eq!(*world.get::<i32>(entities[42]).unwrap(), 84);
+    let mut ids = world
+        .query::<&bool>()
+        .par_iter()
+        .map(|(e, _)| e)
+        .collect::<Vec<_>>();
+    ids.sort();
+    assert_eq!(ids, entities);
+    assert_eq!(world.query::<&i32>().par_iter().count(), 11_000);
+}
+
 #[test]
 fn spawn_batch() {
     let mut world = World::new();
@@ -358,3 +1779,51 @@ fn query_one() {
     world.despawn(a).unwrap();
     assert!(world.query_one::<&i32>(a).is_err());
 }
+
+#[test]
+fn query_one_reserved_entity() {
+    let mut world = World::new();
+    let a = world.reserve_entity();
+    assert!(world.query_one::<Entity>(a).unwrap().get().is_none());
+    assert!(world
+        .query_one::<Satisfies<&i32>>(a)
+        .unwrap()
+        .get()
+        .is_none());
+    assert_eq!(
+        world.query_one_mut::<Entity>(a),
+        Err(QueryOneError::Unsatisfied)
+    );
+    assert_eq!(
+        world.query_one_mut::<Option<&i32>>(a),
+        Err(QueryOneError::Unsatisfied)
+    );
+    world.flush();
+    assert_eq!(world.query_one_mut::<Entity>(a), Ok(a));
+}
+
+#[test]
+fn satisfies_sparse() {
+    struct Stunned;
+    let mut world = World::new();
+    world.store_sparse::<Stunned>();
+    let a = world.spawn((1, Stunned));
+    let b = world.spawn((2,));
+    let c = world.spawn((3, Stunned));
+    world.remove_one::<Stunned>(c).unwrap();
+    let mut entities = world
+        .query::<(&i32, Satisfies<&Stunned>, Satisfies<Without<Stunned, ()>>)>()
+        .iter()
+        .map(|(e, (_, stunned, calm))| (e, stunned, calm))
+        .collect::<Vec<_>>();
+    entities.sort_by_key(|&(e, _, _)| e.id());
+    assert_eq!(
+        entities,
+        &[(a, true, false), (b, false, true), (c, false, true)]
+    );
+    assert!(!world
+        .query_one::<Satisfies<(&i32, &mut Stunned)>>(c)
+        .unwrap()
+        .get()
+        .unwrap());
+}